@@ -1,11 +1,8 @@
 #import bevy_pbr::{
     pbr_fragment::pbr_input_from_standard_material,
     mesh_view_bindings::{view, globals},
-    pbr_types::{STANDARD_MATERIAL_FLAGS_DOUBLE_SIDED_BIT, STANDARD_MATERIAL_FLAGS_ALPHA_MODE_BLEND, PbrInput, pbr_input_new},
     pbr_functions as fns,
-    mesh_functions::{get_world_from_local, mesh_position_local_to_clip, mesh_position_local_to_world},
 }
-#import bevy_core_pipeline::tonemapping::tone_mapping
 
 #ifdef PREPASS_PIPELINE
 #import bevy_pbr::{
@@ -19,128 +16,58 @@
 }
 #endif
 
-@group(2) @binding(100) var texture_pack: texture_2d_array<f32>;
-@group(2) @binding(101) var texture_sampler: sampler;
-@group(2) @binding(102) var<storage, read> anim_offsets: array<u32>;
-
-const MASK2: u32 = 3;
-const MASK3: u32 = 7;
-const MASK4: u32 = 15;
-const MASK6: u32 = 63;
-const MASK9: u32 = 511;
-const MASK16: u32 = 65535;
+struct VoxelTexture {
+    layer: u32,
+    // world units per tile
+    scale: f32,
+}
 
-struct VertexInput {
-    @builtin(instance_index) instance_index: u32,
-    @location(0) voxel_data: vec2<u32>,
-};
+@group(#{MATERIAL_BIND_GROUP}) @binding(100) var texture_pack: texture_2d_array<f32>;
+@group(#{MATERIAL_BIND_GROUP}) @binding(101) var texture_sampler: sampler;
+// animation frame count for each layer, frames are stored in consecutive layers
+@group(#{MATERIAL_BIND_GROUP}) @binding(102) var<storage, read> anim_offsets: array<u32>;
+@group(#{MATERIAL_BIND_GROUP}) @binding(103) var<uniform> voxel_texture: VoxelTexture;
 
-struct CustomVertexOutput {
-    @builtin(position) position: vec4<f32>,
-    @location(0) world_position: vec4<f32>,
-    @location(1) world_normal: vec3<f32>,
-    @location(2) uv: vec2<f32>,
-    @location(3) color: vec4<f32>,
-};
+const NO_TEXTURE_LAYER: u32 = 0xFFFFFFFFu;
+const ANIMATION_FPS: f32 = 2.0;
+// higher is a sharper blend between the projected axes
+const TRIPLANAR_SHARPNESS: f32 = 4.0;
 
-fn normal_from_id(id: u32) -> vec3<f32> {
-    var n: vec3<f32>;
-    switch id {
-        case 0u {
-            n = vec3(0.0, 1.0, 0.0);
-        }
-        case 1u {
-            n = vec3(0.0, -1.0, 0.0);
-        }
-        case 2u {
-            n = vec3(1.0, 0.0, 0.0);
-        }
-        case 3u {
-            n = vec3(-1.0, 0.0, 0.0);
-        }
-        case 4u {
-            n = vec3(0.0, 0.0, 1.0);
-        }
-        case 5u {
-            n = vec3(0.0, 0.0, -1.0);
-        }
-        default {
-            n = vec3(0.0);
-        }
-    }
-    return n;
+fn animated_layer(layer: u32) -> u32 {
+    let frames = max(anim_offsets[layer], 1u);
+    return layer + (u32(globals.time * ANIMATION_FPS) % frames);
 }
 
-fn color_from_id(id: u32) -> vec4<f32> {
-    var r = f32(id & MASK3)/f32(MASK3);
-    var g = f32((id >> 3) & MASK3)/f32(MASK3);
-    var b = f32((id >> 6) & MASK3)/f32(MASK3);
-    return vec4(r, g, b, 1.0);
+fn sample_layer(uv: vec2<f32>, layer: u32) -> vec4<f32> {
+    return textureSampleBias(texture_pack, texture_sampler, uv, layer, view.mip_bias);
 }
 
-@vertex
-fn vertex(vertex: VertexInput) -> CustomVertexOutput {
-    var out: CustomVertexOutput;
+// surface nets have no sensible uvs, so project the texture along each axis
+// and blend by the normal
+fn triplanar(world_position: vec3<f32>, world_normal: vec3<f32>, layer: u32) -> vec4<f32> {
+    let position = world_position / voxel_texture.scale;
+    var weights = pow(abs(world_normal), vec3(TRIPLANAR_SHARPNESS));
+    weights /= weights.x + weights.y + weights.z;
 
-    // Vertex specific information
-    var vertex_info = vertex.voxel_data.x;
-    var x = f32(vertex_info & MASK6);
-    var y = f32((vertex_info >> 6) & MASK6);
-    var z = f32((vertex_info >> 12) & MASK6);
-    var u = f32((vertex_info >> 18) & MASK6);
-    var v = f32((vertex_info >> 24) & MASK6);
-    var position = vec4(x, y, z, 1.0);
-    
-    // Quad specific information
-    var quad_info = vertex.voxel_data.y;
-    var n_id = quad_info & MASK3;
-    var normal = normal_from_id(n_id);
-    var c_id = (quad_info >> 3) & MASK9;
-    var face_color = color_from_id(c_id);
-    var texture_layer = quad_info >> 12;
-    var face_light = light_from_id(n_id);
-    var light = f32((quad_info >> 28) & MASK4) / f32(MASK4);
-
-    out.position = mesh_position_local_to_clip(
-        get_world_from_local(vertex.instance_index),
-        position,
-    );
-    out.world_position = mesh_position_local_to_world(
-        get_world_from_local(vertex.instance_index),
-        position,
-    );
-    out.world_normal = normal;
-    out.uv = vec2(u, v);
-    out.color = face_color;
-    out.texture_layer = texture_layer;
-    out.face_light = face_light;
-    return out;
+    let x = sample_layer(position.zy, layer);
+    let y = sample_layer(position.xz, layer);
+    let z = sample_layer(position.xy, layer);
+    return x * weights.x + y * weights.y + z * weights.z;
 }
 
 @fragment
 fn fragment(
-    in: CustomVertexOutput,
+    in: VertexOutput,
     @builtin(front_facing) is_front: bool,
 ) -> FragmentOutput {
-    var vertex_output: VertexOutput;
-    vertex_output.position = in.position;
-    vertex_output.world_position = in.world_position;
-    vertex_output.world_normal = in.world_normal;
-#ifdef VERTEX_UVS
-    vertex_output.uv = in.uv;
-#endif
-#ifdef VERTEX_UVS_B
-    vertex_output.uv_b = in.uv;
-#endif
-#ifdef VERTEX_COLORS
-    vertex_output.color = in.color;
-#endif
     // generate a PbrInput struct from the StandardMaterial bindings
-    var pbr_input = pbr_input_from_standard_material(vertex_output, is_front);
-    
-    // sample texture
-    pbr_input.material.base_color = in.color * in.face_light * textureSampleBias(texture_pack, texture_sampler, in.uv, in.texture_layer + (u32(globals.time*2.0) % anim_offsets[in.texture_layer]), view.mip_bias);
-    
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+
+    if voxel_texture.layer != NO_TEXTURE_LAYER {
+        let layer = animated_layer(voxel_texture.layer);
+        pbr_input.material.base_color *= triplanar(in.world_position.xyz, pbr_input.world_normal, layer);
+    }
+
     // alpha discard
     pbr_input.material.base_color = fns::alpha_discard(pbr_input.material, pbr_input.material.base_color);
 
@@ -158,4 +85,4 @@ fn fragment(
 #endif
 
     return out;
-}
\ No newline at end of file
+}
@@ -1,13 +1,15 @@
 use std::hint::black_box;
 
+use arch::core::map::terrain::TerrainKind;
+use arch::core::map::{Layers, TerrainParams};
 use arch::core::sdf::voxel_rasterize::{RasterConfig, RasterVoxel, rasterize};
 use arch::core::sdf::{self};
-use arch::core::voxel::{self, Voxel, Voxels};
+use arch::core::voxel::{self, Voxel, VoxelAabb, Voxels};
 use bevy::math::bounding::Aabb3d;
 use bevy::prelude::*;
 use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
 
-criterion_group!(benches, get_voxel, set_voxel, updates_iterator);
+criterion_group!(benches, get_voxel, set_voxel, updates_iterator, compress, fill);
 criterion_main!(benches);
 
 fn get_voxel(c: &mut Criterion) {
@@ -129,3 +131,86 @@ fn updates_iterator(c: &mut Criterion) {
         );
     });
 }
+
+/// Compressed 1024x32x1024 hilly map, the same as the default map.
+fn compressed_map() -> Voxels {
+    let mut voxels = Voxels::new(IVec3::new(1024, 32, 1024));
+    let terrain = TerrainParams {
+        aabb: VoxelAabb { min: IVec3::ZERO, max: IVec3::new(1024, 32, 1024) },
+        kind: TerrainKind::Hilly,
+        layers: Layers { layers: vec![(0.0, Voxel::Dirt), (0.9, Voxel::Grass)] },
+    };
+    terrain.apply(&mut voxels).unwrap();
+    voxels.tree.compress();
+    voxels
+}
+
+/// Dig a handful of holes, like a few ticks of player edits.
+fn dig(voxels: &mut Voxels) {
+    for hole in 0..16 {
+        let center = IVec3::new(64 * hole, 24, 64 * hole);
+        voxels.set_voxel_brush(center, sdf::Sphere { radius: 4.0 }, Voxel::Air);
+    }
+}
+
+fn compress(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compress");
+    let map = compressed_map();
+
+    group.bench_function("compress_full", |b| {
+        b.iter_batched(
+            || {
+                let mut voxels = map.clone();
+                dig(&mut voxels);
+                voxels
+            },
+            |mut voxels| {
+                voxels.tree.compress();
+                black_box(voxels)
+            },
+            BatchSize::LargeInput,
+        );
+    });
+
+    group.bench_function("compress_touched", |b| {
+        b.iter_batched(
+            || {
+                let mut voxels = map.clone();
+                dig(&mut voxels);
+                voxels
+            },
+            |mut voxels| {
+                voxels.tree.compress_touched();
+                black_box(voxels)
+            },
+            BatchSize::LargeInput,
+        );
+    });
+}
+
+fn fill(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fill");
+    let aabb = VoxelAabb::new(IVec3::ZERO, IVec3::new(255, 31, 255));
+
+    group.bench_function("fill_set_voxel", |b| {
+        b.iter(|| {
+            let mut voxels = Voxels::new(IVec3::splat(256));
+            for x in aabb.min.x..=aabb.max.x {
+                for y in aabb.min.y..=aabb.max.y {
+                    for z in aabb.min.z..=aabb.max.z {
+                        voxels.set_voxel(IVec3::new(x, y, z), Voxel::Dirt);
+                    }
+                }
+            }
+            black_box(voxels)
+        });
+    });
+
+    group.bench_function("fill_aabb", |b| {
+        b.iter(|| {
+            let mut voxels = Voxels::new(IVec3::splat(256));
+            voxels.fill_aabb(aabb, Voxel::Dirt);
+            black_box(voxels)
+        });
+    });
+}
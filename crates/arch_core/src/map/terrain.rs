@@ -5,7 +5,7 @@ use noiz::prelude::*;
 use thiserror::Error;
 
 use crate::map::{MapParams, VoxelAabb, WorldGenSet};
-use crate::voxel::{Voxel, Voxels};
+use crate::voxel::{Voxel, Voxels, WorldGrid};
 
 pub fn plugin(app: &mut App) {
     info!("terrain plugin !!!!!");
@@ -14,7 +14,7 @@ pub fn plugin(app: &mut App) {
 
 pub fn gen_terrain(
     mut map: Query<&mut MapParams, Changed<MapParams>>,
-    mut voxels: Query<&mut Voxels>,
+    mut voxels: Query<&mut Voxels, With<WorldGrid>>,
 ) {
     let Ok(mut map) = map.single_mut() else {
         return;
@@ -88,20 +88,17 @@ impl TerrainParams {
         let min = self.aabb.min;
         let max = self.aabb.max;
 
-        for x in min.x..max.x {
-            for z in min.z..max.z {
-                let bounds_height = max.y - min.y;
-                let coord_height = bounds_height;
+        let bounds_height = max.y - min.y;
+        let coord_height = bounds_height;
 
-                for y in min.y..coord_height {
-                    let range_height = y as f32 / (coord_height - min.y) as f32;
-                    let voxel = self.layers.sample_height(range_height);
-                    voxels.set_voxel(IVec3::new(x, y, z), voxel);
-                }
+        // layers only depend on the height, so fill the whole footprint at once
+        let footprint = VoxelAabb::new(min, (max - IVec3::ONE).with_y(min.y));
+        fill_layer_runs(voxels, footprint, min.y, coord_height, |y| {
+            let range_height = y as f32 / (coord_height - min.y) as f32;
+            self.layers.sample_height(range_height)
+        });
 
-                voxels.set_voxel(IVec3::new(x, min.y, z), Voxel::Base);
-            }
-        }
+        voxels.fill_aabb(footprint, Voxel::Base);
 
         Ok(())
     }
@@ -121,12 +118,13 @@ impl TerrainParams {
                 let removed = layer_noise.sample(Vec2::new(x as f32, z as f32));
                 let coord_height = bounds_height - removed as i32;
 
-                for y in min.y..coord_height {
+                let column_point = IVec3::new(x, min.y, z);
+                let column = VoxelAabb::new(column_point, column_point);
+                fill_layer_runs(voxels, column, min.y, coord_height, |y| {
                     let range_height = y as f32 / (coord_height - min.y) as f32;
                     // info!("range_height: {:?}", range_height);
-                    let voxel = self.layers.sample_height(range_height);
-                    voxels.set_voxel(IVec3::new(x, y, z), voxel);
-                }
+                    self.layers.sample_height(range_height)
+                });
 
                 voxels.set_voxel(IVec3::new(x, min.y, z), Voxel::Base);
             }
@@ -143,21 +141,7 @@ impl TerrainParams {
         let min = self.aabb.min;
         let max = self.aabb.max;
 
-        // voxels.set_voxel_aabb(
-        //     VoxelAabb {
-        //         min: IVec3::new(self.aabb.min.x, self.aabb.min.y, self.aabb.min.z),
-        //         max: IVec3::new(self.aabb.max.x, self.aabb.min.y + 2,
-        // self.aabb.max.z),     },
-        //     Voxel::Base,
-        // );
-
-        for z in min.z..=max.z {
-            for x in min.x..=max.x {
-                for y in min.y..=min.y + 4 {
-                    voxels.set_voxel(IVec3::new(x, y, z), Voxel::Base);
-                }
-            }
-        }
+        voxels.fill_aabb(VoxelAabb::new(min, max.with_y(min.y + 4)), Voxel::Base);
 
         // for x in min.x..=max.x {
         //     for z in min.z..=max.z {
@@ -182,6 +166,29 @@ impl TerrainParams {
     }
 }
 
+/// Fill `columns` from `min_y` up to `max_y` (exclusive) with one
+/// [`Voxels::fill_aabb`] per run of the same voxel from `sample`.
+fn fill_layer_runs(
+    voxels: &mut Voxels,
+    columns: VoxelAabb,
+    min_y: i32,
+    max_y: i32,
+    sample: impl Fn(i32) -> Voxel,
+) {
+    let mut y = min_y;
+    while y < max_y {
+        let voxel = sample(y);
+        let mut end = y + 1;
+        while end < max_y && sample(end) == voxel {
+            end += 1;
+        }
+
+        let run = VoxelAabb::new(columns.min.with_y(y), columns.max.with_y(end - 1));
+        voxels.fill_aabb(run, voxel);
+        y = end;
+    }
+}
+
 pub fn basic_noise() -> impl SampleableFor<Vec2, f32> + ScalableNoise + SeedableNoise {
     Noise {
         noise: Masked(
@@ -7,7 +7,7 @@ use rand::Rng;
 
 use crate::map::WorldGenSet;
 use crate::voxel::pick::CursorVoxel;
-use crate::voxel::{GRID_SCALE, Voxels};
+use crate::voxel::{GRID_SCALE, Voxels, WorldGrid};
 
 pub fn plugin(app: &mut App) {
     app.register_type::<Fence>().register_type::<BoardParams>();
@@ -112,7 +112,7 @@ pub fn paint_fence(
     mut fence: Local<Option<Entity>>,
     mut fences: Query<&mut Fence>,
 
-    voxels: Query<&Voxels>,
+    voxels: Query<&Voxels, With<WorldGrid>>,
     cursor_voxel: Res<CursorVoxel>,
 ) {
     let fence = if let Some(fence) = *fence {
@@ -136,7 +136,11 @@ pub fn paint_fence(
     }
 }
 
-pub fn test_fence(mut commands: Commands, voxels: Query<&Voxels>, mut done: Local<bool>) {
+pub fn test_fence(
+    mut commands: Commands,
+    voxels: Query<&Voxels, With<WorldGrid>>,
+    mut done: Local<bool>,
+) {
     if *done {
         return;
     }
@@ -4,7 +4,7 @@ use avian3d::prelude::*;
 use bevy::prelude::*;
 
 use crate::map::Aabb;
-use crate::voxel::{VoxelAabb, Voxels};
+use crate::voxel::{VoxelAabb, Voxels, WorldGrid};
 
 pub fn plugin(app: &mut App) {
     app.add_systems(Update, rebuild_borders);
@@ -16,7 +16,7 @@ pub struct Border;
 
 pub fn rebuild_borders(
     mut commands: Commands,
-    voxels: Query<(&GlobalTransform, &Voxels), Changed<Voxels>>,
+    voxels: Query<(&GlobalTransform, &Voxels), (Changed<Voxels>, With<WorldGrid>)>,
     borders: Query<Entity, With<Border>>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
@@ -1,17 +1,24 @@
+use std::sync::Arc;
+
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
 use crate::sdf::voxel_rasterize::{ChunkIntersectIter, PointIter};
 use crate::sdf::{Sdf, SdfNode};
 use crate::voxel::data::linearize;
-use crate::voxel::simulation::SimChunks;
+use crate::voxel::simulation::replay::{RecordPhase, SimRecorder};
+use crate::voxel::simulation::{FallingSandTick, SimChunks};
 use crate::voxel::tree::VoxelTree;
-use crate::voxel::{SimStep, Voxel, VoxelNode, VoxelSet, Voxels};
+use crate::voxel::voxel::SimKind;
+use crate::voxel::{SimStep, Voxel, VoxelAabb, VoxelNode, VoxelSet, Voxels, diff, smooth};
 
 pub fn plugin(app: &mut App) {
     app.register_type::<VoxelCommand>();
-    // app.add_message::<VoxelCommand>();
-    app.init_resource::<Messages<VoxelCommand>>();
+    app.register_type::<GridCommand>();
+    app.register_type::<DamageTool>();
+    app.add_message::<BrokenVoxels>();
+    // app.add_message::<GridCommand>();
+    app.init_resource::<Messages<GridCommand>>();
 
     // app.add_systems(FixedLast, update_command_messages);
     app.add_systems(
@@ -44,35 +51,206 @@ impl Default for SetVoxelsSdfParams {
     }
 }
 
-pub fn apply_tree(mut voxels: Query<&mut Voxels>, mut commands: MessageReader<VoxelCommand>) {
-    for command in commands.read() {
-        for mut voxels in &mut voxels {
-            command.apply_tree(&mut voxels.tree);
+/// What is doing the damage in [`VoxelCommand::Damage`], tools are better at
+/// breaking some voxels than others.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Reflect)]
+pub enum DamageTool {
+    #[default]
+    Hand,
+    /// Good at soft voxels, bad at hard ones.
+    Shovel,
+    /// Good at hard voxels.
+    Pickaxe,
+    /// Only clears loose voxels, safe to use around finds.
+    Brush,
+}
+
+impl DamageTool {
+    /// Health at or above this is considered hard to dig.
+    pub const HARD_HEALTH: i16 = 50;
+
+    /// Scale the damage done to a voxel based on the tool.
+    pub fn damage(self, voxel: Voxel, amount: i16) -> i16 {
+        let hard = voxel.starting_health() >= Self::HARD_HEALTH;
+        match self {
+            DamageTool::Hand => amount,
+            DamageTool::Shovel if hard => amount / 2,
+            DamageTool::Shovel => amount.saturating_mul(2),
+            DamageTool::Pickaxe if hard => amount.saturating_mul(2),
+            DamageTool::Pickaxe => amount,
+            DamageTool::Brush => match voxel.definition().simulation_kind {
+                SimKind::Solid => 0,
+                _ => amount,
+            },
+        }
+    }
+}
+
+/// Voxels that were broken by a [`VoxelCommand::Damage`].
+#[derive(Message, Debug, Clone)]
+pub struct BrokenVoxels {
+    pub grid_entity: Entity,
+    pub tool: DamageTool,
+    /// Point and the voxel that was there before breaking.
+    pub voxels: Vec<(IVec3, Voxel)>,
+}
+
+pub fn apply_tree(
+    mut grids: Query<(&mut Voxels, Option<&mut SimChunks>)>,
+    mut commands: MessageReader<GridCommand>,
+    mut broken_writer: MessageWriter<BrokenVoxels>,
+    tick: Option<Res<FallingSandTick>>,
+    mut recorder: Option<ResMut<SimRecorder>>,
+) {
+    let mut broken = Vec::new();
+    for GridCommand { grid_entity, command } in commands.read() {
+        let Ok((mut voxels, sim_chunks)) = grids.get_mut(*grid_entity) else {
+            warn!("voxel command for missing grid {:?}", grid_entity);
+            continue;
+        };
+
+        if let (Some(recorder), Some(tick)) = (&mut recorder, &tick) {
+            recorder.record(*grid_entity, RecordPhase::Tree, **tick, command);
+        }
+
+        apply_grid_tree(&mut voxels, sim_chunks.map(Mut::into_inner), command, &mut broken);
+        if broken.is_empty() {
+            continue;
+        }
+
+        let VoxelCommand::Damage { tool, .. } = command else {
+            unreachable!("only damage breaks voxels");
+        };
+
+        broken_writer.write(BrokenVoxels {
+            grid_entity: *grid_entity,
+            tool: *tool,
+            voxels: std::mem::take(&mut broken),
+        });
+    }
+}
+
+/// Apply `command` to a grid's tree, keeping its sim in sync with whatever
+/// [`VoxelCommand::apply_sim`] can't do on its own.
+pub fn apply_grid_tree(
+    voxels: &mut Voxels,
+    mut sim_chunks: Option<&mut SimChunks>,
+    command: &VoxelCommand,
+    broken: &mut Vec<(IVec3, Voxel)>,
+) {
+    // chunks outside of the sim get the command from the tree
+    if let (Some(sim_chunks), Some(aabb)) = (&mut sim_chunks, command.aabb()) {
+        sim_chunks.activate_aabb(aabb);
+    }
+
+    if let VoxelCommand::Smooth { .. } = command {
+        // Smoothing moves whole columns around, sync the sim with everything that
+        // changed.
+        let before = voxels.tree.snapshot();
+        command.apply_tree(&mut voxels.tree, broken);
+        if let Some(sim_chunks) = &mut sim_chunks {
+            for chunk_diff in diff::diff(&before, &voxels.tree) {
+                for (point, change) in chunk_diff.iter_points() {
+                    sim_chunks.set_voxel(point, change.new);
+                }
+            }
+        }
+        return;
+    }
+
+    command.apply_tree(&mut voxels.tree, broken);
+
+    // Damage is only tracked in the tree, keep the sim in sync with what broke.
+    if let Some(sim_chunks) = &mut sim_chunks {
+        for (point, _) in broken.iter() {
+            sim_chunks.set_voxel(*point, Voxel::Air);
         }
     }
 }
 
-pub fn apply_sim(mut sims: Query<&mut SimChunks>, mut commands: MessageReader<VoxelCommand>) {
-    for command in commands.read() {
-        for mut sim in &mut sims {
+pub fn apply_sim(
+    mut sims: Query<&mut SimChunks>,
+    mut commands: MessageReader<GridCommand>,
+    tick: Res<FallingSandTick>,
+    mut recorder: Option<ResMut<SimRecorder>>,
+) {
+    for GridCommand { grid_entity, command } in commands.read() {
+        // grids without a sim only need the tree updated
+        if let Ok(mut sim) = sims.get_mut(*grid_entity) {
+            if let Some(recorder) = &mut recorder {
+                recorder.record(*grid_entity, RecordPhase::Sim, *tick, command);
+            }
+
             command.apply_sim(&mut *sim);
         }
     }
 }
 
-pub fn update_command_messages(mut messages: ResMut<Messages<VoxelCommand>>) {
+pub fn update_command_messages(mut messages: ResMut<Messages<GridCommand>>) {
     messages.update();
 }
 
-/// Commands for setting voxels across simulation/tree/network.
-#[derive(Message, Debug, Clone, Serialize, Deserialize, Reflect)]
+/// [`VoxelCommand`] for a specific grid entity.
+#[derive(Message, Debug, Clone, Reflect)]
+pub struct GridCommand {
+    pub grid_entity: Entity,
+    pub command: VoxelCommand,
+}
+
+impl GridCommand {
+    pub fn new(grid_entity: Entity, command: VoxelCommand) -> Self {
+        Self { grid_entity, command }
+    }
+}
+
+/// Commands for setting voxels across simulation/tree/network, sent to a grid
+/// as a [`GridCommand`].
+#[derive(Debug, Clone, Serialize, Deserialize, Reflect)]
 pub enum VoxelCommand {
-    SetVoxel { point: IVec3, voxel: Voxel, params: SetVoxelParams },
-    SetVoxelsSdf { origin: IVec3, sdf: SdfNode, voxel: Voxel, params: SetVoxelsSdfParams },
+    SetVoxel {
+        point: IVec3,
+        voxel: Voxel,
+        params: SetVoxelParams,
+    },
+    SetVoxelsSdf {
+        origin: IVec3,
+        sdf: SdfNode,
+        voxel: Voxel,
+        params: SetVoxelsSdfParams,
+    },
+    /// Subtract health from the voxels inside the sdf, voxels are only turned
+    /// into [`Voxel::Air`] once they run out of health. See [`BrokenVoxels`].
+    Damage {
+        origin: IVec3,
+        sdf: SdfNode,
+        amount: i16,
+        tool: DamageTool,
+    },
+    /// Smooth the terrain surface of the columns inside the sdf while
+    /// keeping their layers in order, see [`smooth`].
+    Smooth {
+        origin: IVec3,
+        sdf: SdfNode,
+    },
 }
 
 impl VoxelCommand {
-    pub fn apply_tree(&self, tree: &mut VoxelTree) {
+    /// Voxels this command could touch.
+    pub fn aabb(&self) -> Option<VoxelAabb> {
+        match self {
+            Self::SetVoxel { point, .. } => Some(VoxelAabb::new(*point, *point)),
+            Self::SetVoxelsSdf { origin, sdf, .. }
+            | Self::Damage { origin, sdf, .. }
+            | Self::Smooth { origin, sdf } => {
+                let aabb = sdf.translate(origin.as_vec3()).aabb()?;
+                Some(VoxelAabb::new(aabb.min.floor().as_ivec3(), aabb.max.ceil().as_ivec3()))
+            },
+        }
+    }
+
+    /// Apply this command to the tree, pushing any voxels that were broken
+    /// into `broken`.
+    pub fn apply_tree(&self, tree: &mut VoxelTree, broken: &mut Vec<(IVec3, Voxel)>) {
         // info!("applying command to tree: {:?}", self);
 
         let mut set = 0;
@@ -88,14 +266,16 @@ impl VoxelCommand {
                 let sdf = sdf.translate(origin.as_vec3());
                 let intersections = ChunkIntersectIter::from_sdf(sdf.clone(), 16);
                 for (chunk_point, local_points) in intersections {
-                    if !tree.chunk_point_in_bounds(*chunk_point) {
+                    if !tree.grow_to_contain_chunk(*chunk_point) {
                         continue;
                     }
 
                     let VoxelNode::Leaf { leaf, .. } = tree.get_leaf_mut(*chunk_point) else {
                         panic!("chunk was not a leaf");
                     };
+                    let leaf = Arc::make_mut(leaf);
 
+                    let mut written = Vec::new();
                     let chunk_min = chunk_point.0 * IVec3::splat(16);
                     for local_point in local_points {
                         let world_point = chunk_min + local_point;
@@ -110,10 +290,30 @@ impl VoxelCommand {
                         if params.can_replace.contains(current_voxel) {
                             set += 1;
                             leaf[index] = *voxel;
+                            written.push(index);
                         }
                     }
+                    tree.clear_chunk_damage(*chunk_point, written);
                 }
             },
+            Self::Damage { origin, sdf, amount, tool } => {
+                let sdf = sdf.translate(origin.as_vec3());
+                for point in PointIter::from_sdf(&sdf) {
+                    if sdf.sdf(point.as_vec3()) >= 0.0 {
+                        continue;
+                    }
+
+                    let current_voxel = tree.get_voxel(point);
+                    let amount = tool.damage(current_voxel, *amount);
+                    if let Some(broken_voxel) = tree.damage_voxel(point, amount) {
+                        set += 1;
+                        broken.push((point, broken_voxel));
+                    }
+                }
+            },
+            Self::Smooth { origin, sdf } => {
+                smooth::smooth_sdf(tree, sdf.translate(origin.as_vec3()));
+            },
         }
 
         // info!("{} voxels set from command", set);
@@ -150,8 +350,63 @@ impl VoxelCommand {
                     }
                 }
             },
+            // Health only lives in the tree, broken voxels are synced in `apply_tree`.
+            Self::Damage { .. } => {},
+            // Smoothing needs the tree's columns, changes are synced in `apply_tree`.
+            Self::Smooth { .. } => {},
         }
 
         // info!("{} voxels set in sim from command", set);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    #[test]
+    pub fn command_targets_grid() {
+        let mut world = World::new();
+        world.init_resource::<Messages<GridCommand>>();
+        world.init_resource::<Messages<BrokenVoxels>>();
+
+        let grid = world.spawn(Voxels::new(IVec3::splat(16))).id();
+        let other_grid = world.spawn(Voxels::new(IVec3::splat(16))).id();
+
+        let point = IVec3::new(3, 4, 5);
+        world.write_message(GridCommand::new(grid, VoxelCommand::SetVoxel {
+            point,
+            voxel: Voxel::Stone,
+            params: default(),
+        }));
+        world.run_system_once(apply_tree).unwrap();
+
+        assert_eq!(world.get::<Voxels>(grid).unwrap().get_voxel(point), Voxel::Stone);
+        assert_eq!(world.get::<Voxels>(other_grid).unwrap().get_voxel(point), Voxel::Air);
+    }
+
+    #[test]
+    pub fn brush_resets_damage() {
+        let mut tree = VoxelTree::new();
+        tree.fill_aabb(VoxelAabb::new(IVec3::ZERO, IVec3::splat(15)), Voxel::Stone);
+        let point = IVec3::splat(8);
+        tree.damage_voxel(point, 3);
+        assert_eq!(tree.voxel_health(point), Voxel::Stone.starting_health() - 3);
+
+        let command = VoxelCommand::SetVoxelsSdf {
+            origin: point,
+            sdf: crate::sdf::Sphere { radius: 2.0 }.as_node(),
+            voxel: Voxel::Dirt,
+            params: SetVoxelsSdfParams {
+                within: 0.0,
+                can_replace: VoxelSet::from_voxel(Voxel::Stone),
+            },
+        };
+        command.apply_tree(&mut tree, &mut Vec::new());
+
+        assert_eq!(tree.get_voxel(point), Voxel::Dirt);
+        assert_eq!(tree.voxel_health(point), Voxel::Dirt.starting_health());
+    }
+}
@@ -0,0 +1,284 @@
+//! Structural diff between two [`VoxelTree`]s.
+//!
+//! Trees are walked in lockstep, identical [`VoxelNode::Solid`] nodes and
+//! subtrees shared between [snapshots](VoxelTree::snapshot) are skipped
+//! entirely, so diffing a snapshot against the live tree only costs as much as
+//! what changed since.
+
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use bevy::prelude::*;
+
+use crate::voxel::simulation::data::ChunkPoint;
+use crate::voxel::tree::{
+    CHUNK_LENGTH, CHUNK_WIDTH, SharedData, TREE_LENGTH, from_child_index, from_leaf_index,
+    layer_width_chunk,
+};
+use crate::voxel::{Voxel, VoxelAabb, VoxelNode, VoxelTree};
+
+/// A single voxel that differs between two trees.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct VoxelChange {
+    /// Index into the chunk's leaf.
+    pub index: u16,
+    pub old: Voxel,
+    pub new: Voxel,
+}
+
+/// Every voxel that differs in a chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkDiff {
+    pub chunk_point: ChunkPoint,
+    /// Sorted by index.
+    pub changes: Vec<VoxelChange>,
+}
+
+impl ChunkDiff {
+    /// Changes along with their voxel point.
+    pub fn iter_points(&self) -> impl Iterator<Item = (IVec3, VoxelChange)> + '_ {
+        let chunk_min = self.chunk_point.0 * IVec3::splat(CHUNK_WIDTH as i32);
+        self.changes
+            .iter()
+            .map(move |change| (chunk_min + from_leaf_index(change.index as usize), *change))
+    }
+}
+
+/// Chunks that differ between `old` and `new`, ordered by position in the tree.
+///
+/// Space outside of a tree counts as [`Voxel::Air`].
+pub fn diff(old: &VoxelTree, new: &VoxelTree) -> Vec<ChunkDiff> {
+    let mut diffs = Vec::new();
+    diff_with(old, new, &mut |chunk_diff| diffs.push(chunk_diff));
+    diffs
+}
+
+/// [`diff`], calling `changed` with each chunk instead of collecting them.
+pub fn diff_with(old: &VoxelTree, new: &VoxelTree, changed: &mut impl FnMut(ChunkDiff)) {
+    // grow both sides so they cover the same space, cheap since snapshots
+    // share their nodes
+    let (mut old, mut new) = (old.snapshot(), new.snapshot());
+    for (min, max) in [old.voxel_bounds(), new.voxel_bounds()] {
+        old.grow_to_contain(min);
+        old.grow_to_contain(max - IVec3::ONE);
+        new.grow_to_contain(min);
+        new.grow_to_contain(max - IVec3::ONE);
+    }
+
+    if old.origin == new.origin && old.root_layer() == new.root_layer() {
+        diff_nodes(&old.root, &new.root, old.origin, changed);
+        return;
+    }
+
+    // Trees grew in different directions so only chunks line up, pair up the
+    // lowest nodes by region instead.
+    let (min, max) = new.voxel_bounds();
+    let bounds = VoxelAabb::new(min, max - IVec3::ONE);
+    for (new_aabb, new_node) in new.iter_nodes_in(bounds) {
+        for (old_aabb, old_node) in old.iter_nodes_in(new_aabb) {
+            let Some(overlap) = old_aabb.intersection(&new_aabb) else {
+                continue;
+            };
+
+            diff_region(overlap, old_node, new_node, changed);
+        }
+    }
+}
+
+/// Diff two nodes covering the same region, `origin` is the chunk point of
+/// their minimum corner.
+fn diff_nodes(
+    old: &VoxelNode,
+    new: &VoxelNode,
+    origin: IVec3,
+    changed: &mut impl FnMut(ChunkDiff),
+) {
+    match (old, new) {
+        (VoxelNode::Solid { voxel: old_voxel, .. }, VoxelNode::Solid { voxel: new_voxel, .. })
+            if old_voxel == new_voxel => {},
+        (
+            VoxelNode::Children { children: old_children, .. },
+            VoxelNode::Children { children: new_children, .. },
+        ) if Arc::ptr_eq(old_children, new_children) => {},
+        (VoxelNode::Leaf { leaf: old_leaf, .. }, VoxelNode::Leaf { leaf: new_leaf, .. })
+            if Arc::ptr_eq(old_leaf, new_leaf) => {},
+        _ if old.layer() == 0 => {
+            diff_chunk(origin, ChunkVoxels::from_node(old), ChunkVoxels::from_node(new), changed);
+        },
+        _ => {
+            let child_width = layer_width_chunk(old.layer() - 1) as i32;
+            for child_index in 0..TREE_LENGTH {
+                let child_origin = origin + from_child_index(child_index) * child_width;
+                let old_child = child_or_solid(old, child_index);
+                let new_child = child_or_solid(new, child_index);
+                diff_nodes(&old_child, &new_child, child_origin, changed);
+            }
+        },
+    }
+}
+
+/// Child of a [`VoxelNode::Children`], or the part of a [`VoxelNode::Solid`]
+/// that child would cover.
+fn child_or_solid(node: &VoxelNode, child_index: usize) -> Cow<'_, VoxelNode> {
+    match node {
+        VoxelNode::Children { children, .. } => Cow::Borrowed(&children[child_index]),
+        VoxelNode::Solid { shared, voxel } => Cow::Owned(VoxelNode::Solid {
+            shared: SharedData { layer: shared.layer - 1, ..shared.clone() },
+            voxel: *voxel,
+        }),
+        VoxelNode::Leaf { .. } => unreachable!("leaves are at layer 0"),
+    }
+}
+
+/// Diff the chunks of a chunk aligned `region` covered by both nodes.
+fn diff_region(
+    region: VoxelAabb,
+    old: &VoxelNode,
+    new: &VoxelNode,
+    changed: &mut impl FnMut(ChunkDiff),
+) {
+    if matches!(
+        (old, new),
+        (VoxelNode::Solid { voxel: old_voxel, .. }, VoxelNode::Solid { voxel: new_voxel, .. })
+            if old_voxel == new_voxel
+    ) {
+        return;
+    }
+
+    let chunk_width = IVec3::splat(CHUNK_WIDTH as i32);
+    let min_chunk = region.min.div_euclid(chunk_width);
+    let max_chunk = region.max.div_euclid(chunk_width);
+    for x in min_chunk.x..=max_chunk.x {
+        for y in min_chunk.y..=max_chunk.y {
+            for z in min_chunk.z..=max_chunk.z {
+                let chunk_point = IVec3::new(x, y, z);
+                diff_chunk(
+                    chunk_point,
+                    ChunkVoxels::from_node(old),
+                    ChunkVoxels::from_node(new),
+                    changed,
+                );
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+enum ChunkVoxels<'a> {
+    Solid(Voxel),
+    Leaf(&'a [Voxel; CHUNK_LENGTH]),
+}
+
+impl<'a> ChunkVoxels<'a> {
+    fn from_node(node: &'a VoxelNode) -> Self {
+        match node {
+            VoxelNode::Solid { voxel, .. } => Self::Solid(*voxel),
+            VoxelNode::Leaf { leaf, .. } => Self::Leaf(leaf),
+            VoxelNode::Children { .. } => unreachable!("only the lowest nodes are chunks"),
+        }
+    }
+
+    #[inline]
+    fn get(self, index: usize) -> Voxel {
+        match self {
+            Self::Solid(voxel) => voxel,
+            Self::Leaf(leaf) => leaf[index],
+        }
+    }
+}
+
+fn diff_chunk(
+    chunk_point: IVec3,
+    old: ChunkVoxels,
+    new: ChunkVoxels,
+    changed: &mut impl FnMut(ChunkDiff),
+) {
+    if matches!((old, new), (ChunkVoxels::Solid(a), ChunkVoxels::Solid(b)) if a == b) {
+        return;
+    }
+
+    let changes = (0..CHUNK_LENGTH)
+        .filter_map(|index| {
+            let (old, new) = (old.get(index), new.get(index));
+            (old != new).then_some(VoxelChange { index: index as u16, old, new })
+        })
+        .collect::<Vec<_>>();
+
+    if !changes.is_empty() {
+        changed(ChunkDiff { chunk_point: ChunkPoint(chunk_point), changes });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn changed_points(diffs: &[ChunkDiff]) -> Vec<(IVec3, Voxel, Voxel)> {
+        let mut points = diffs
+            .iter()
+            .flat_map(|chunk_diff| chunk_diff.iter_points())
+            .map(|(point, change)| (point, change.old, change.new))
+            .collect::<Vec<_>>();
+        points.sort_by_key(|(point, ..)| point.to_array());
+        points
+    }
+
+    #[test]
+    pub fn snapshot_diff() {
+        let mut tree = VoxelTree::new();
+        tree.grow_n_layers(3);
+        tree.fill_aabb(VoxelAabb::new(IVec3::ZERO, IVec3::new(1023, 31, 1023)), Voxel::Dirt);
+        tree.set_voxel(IVec3::new(3, 4, 5), Voxel::Stone);
+        tree.compress();
+
+        let snapshot = tree.snapshot();
+        assert!(diff(&snapshot, &tree).is_empty());
+
+        tree.set_voxel(IVec3::new(3, 4, 5), Voxel::Dirt);
+        tree.set_voxel(IVec3::new(500, 40, 500), Voxel::Sand);
+        tree.fill_aabb(VoxelAabb::new(IVec3::new(16, 0, 0), IVec3::new(31, 15, 15)), Voxel::Air);
+        // back to the original, not a change
+        tree.set_voxel(IVec3::new(700, 0, 0), Voxel::Stone);
+        tree.set_voxel(IVec3::new(700, 0, 0), Voxel::Dirt);
+
+        let diffs = diff(&snapshot, &tree);
+        let chunk_points = diffs.iter().map(|chunk_diff| chunk_diff.chunk_point.0);
+        assert_eq!(chunk_points.collect::<Vec<_>>(), vec![
+            IVec3::new(0, 0, 0),
+            IVec3::new(1, 0, 0),
+            IVec3::new(31, 2, 31)
+        ]);
+
+        let points = changed_points(&diffs);
+        assert_eq!(points.len(), 1 + 1 + CHUNK_LENGTH);
+        assert!(points.contains(&(IVec3::new(3, 4, 5), Voxel::Stone, Voxel::Dirt)));
+        assert!(points.contains(&(IVec3::new(500, 40, 500), Voxel::Air, Voxel::Sand)));
+        assert!(points.contains(&(IVec3::new(20, 10, 3), Voxel::Dirt, Voxel::Air)));
+
+        // reversed diff undoes the changes
+        let reversed = changed_points(&diff(&tree, &snapshot));
+        let undo = points.iter().map(|(point, old, new)| (*point, *new, *old)).collect::<Vec<_>>();
+        assert_eq!(reversed, undo);
+    }
+
+    #[test]
+    pub fn differently_grown_trees() {
+        // same voxels, but the roots grew in different directions
+        let mut a = VoxelTree::new();
+        a.set_voxel(IVec3::new(-5, 0, 0), Voxel::Stone);
+        a.set_voxel(IVec3::new(300, 0, 0), Voxel::Sand);
+
+        let mut b = VoxelTree::new();
+        b.set_voxel(IVec3::new(300, 0, 0), Voxel::Sand);
+        b.set_voxel(IVec3::new(-5, 0, 0), Voxel::Stone);
+        assert_ne!(a.origin, b.origin);
+        assert!(diff(&a, &b).is_empty());
+
+        b.set_voxel(IVec3::new(-5, 0, 0), Voxel::Air);
+        b.set_voxel(IVec3::new(-100, 0, 0), Voxel::Dirt);
+        assert_eq!(changed_points(&diff(&a, &b)), vec![
+            (IVec3::new(-100, 0, 0), Voxel::Air, Voxel::Dirt),
+            (IVec3::new(-5, 0, 0), Voxel::Stone, Voxel::Air),
+        ]);
+    }
+}
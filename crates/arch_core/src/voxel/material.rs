@@ -0,0 +1,289 @@
+//! Textured voxel chunk material, extends [`StandardMaterial`] with
+//! `assets/shaders/chunk.wgsl`.
+
+use bevy::image::{ImageAddressMode, ImageSampler, ImageSamplerDescriptor};
+use bevy::pbr::{ExtendedMaterial, MaterialExtension};
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+use bevy::render::render_resource::AsBindGroup;
+use bevy::render::storage::ShaderStorageBuffer;
+use bevy::shader::ShaderRef;
+
+use crate::voxel::Voxel;
+use crate::voxel::registry::VoxelRegistry;
+use crate::voxel::voxel::{VoxelId, VoxelPbr};
+
+pub const CHUNK_SHADER_PATH: &str = "shaders/chunk.wgsl";
+/// Square tiles stacked vertically, referenced by
+/// [`VoxelPbr::texture_layer`]:
+///
+/// | layer | voxel            |
+/// |-------|------------------|
+/// | 0     | stone            |
+/// | 1     | dirt             |
+/// | 2     | grass            |
+/// | 3     | sand             |
+/// | 4     | gravel           |
+/// | 5     | clay             |
+/// | 6     | iron ore         |
+/// | 7     | ice              |
+/// | 8-9   | water, 2 frames  |
+/// | 10-11 | lava, 2 frames   |
+pub const TEXTURE_PACK_PATH: &str = "array_texture.png";
+
+/// `texture_layer` of voxels without a texture, only the base color is used.
+pub const NO_TEXTURE_LAYER: u32 = u32::MAX;
+
+/// World units covered by one tile of the texture pack.
+pub const TEXTURE_SCALE: f32 = 1.0;
+
+pub type VoxelChunkMaterial = ExtendedMaterial<StandardMaterial, VoxelTextureExtension>;
+
+pub fn plugin(app: &mut App) {
+    app.add_plugins(MaterialPlugin::<VoxelChunkMaterial>::default());
+
+    app.register_type::<VoxelMaterials>();
+    app.init_resource::<VoxelTexturePack>();
+    app.init_resource::<VoxelMaterials>();
+    app.add_systems(PreUpdate, VoxelMaterials::rebuild.run_if(resource_changed::<VoxelRegistry>));
+    app.add_systems(Update, VoxelTexturePack::reinterpret);
+}
+
+#[derive(Asset, AsBindGroup, Reflect, Debug, Clone)]
+pub struct VoxelTextureExtension {
+    #[texture(100, dimension = "2d_array")]
+    #[sampler(101)]
+    pub texture_pack: Handle<Image>,
+    /// Animation frame count for each layer of the texture pack.
+    #[storage(102, read_only)]
+    pub anim_offsets: Handle<ShaderStorageBuffer>,
+    #[uniform(103)]
+    pub texture_layer: u32,
+    #[uniform(103)]
+    pub texture_scale: f32,
+}
+
+impl MaterialExtension for VoxelTextureExtension {
+    fn fragment_shader() -> ShaderRef {
+        CHUNK_SHADER_PATH.into()
+    }
+
+    fn deferred_fragment_shader() -> ShaderRef {
+        CHUNK_SHADER_PATH.into()
+    }
+}
+
+/// Voxel textures stacked vertically in a single image, one square tile per
+/// layer.
+#[derive(Resource, Debug, Clone)]
+pub struct VoxelTexturePack {
+    pub image: Handle<Image>,
+}
+
+impl FromWorld for VoxelTexturePack {
+    fn from_world(world: &mut World) -> Self {
+        let image = world
+            .get_resource::<AssetServer>()
+            .map(|asset_server| asset_server.load(TEXTURE_PACK_PATH))
+            .unwrap_or_default();
+        Self { image }
+    }
+}
+
+impl VoxelTexturePack {
+    /// Turn the stacked image into an array texture once it has loaded.
+    pub fn reinterpret(
+        texture_pack: Res<VoxelTexturePack>,
+        mut image_events: MessageReader<AssetEvent<Image>>,
+        images: Option<ResMut<Assets<Image>>>,
+    ) {
+        let Some(mut images) = images else {
+            return;
+        };
+
+        for event in image_events.read() {
+            if !event.is_loaded_with_dependencies(&texture_pack.image) {
+                continue;
+            }
+
+            let Some(image) = images.get_mut(&texture_pack.image) else {
+                continue;
+            };
+
+            // already an array
+            if image.texture_descriptor.size.depth_or_array_layers > 1 {
+                continue;
+            }
+
+            let (width, height) = (image.width(), image.height());
+            if width == 0 || height % width != 0 {
+                warn!(
+                    "voxel texture pack should be square tiles stacked vertically, got \
+                     {width}x{height}"
+                );
+                continue;
+            }
+
+            image.reinterpret_stacked_2d_as_array(height / width);
+            image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+                address_mode_u: ImageAddressMode::Repeat,
+                address_mode_v: ImageAddressMode::Repeat,
+                ..ImageSamplerDescriptor::nearest()
+            });
+        }
+    }
+}
+
+/// Render materials for each registered voxel type, rebuilt whenever the
+/// [`VoxelRegistry`] changes.
+///
+/// Handles are kept across rebuilds so existing chunk meshes pick up the new
+/// materials.
+#[derive(Resource, Clone, Default, Reflect)]
+#[reflect(Resource)]
+pub struct VoxelMaterials {
+    pub materials: HashMap<VoxelId, Handle<VoxelChunkMaterial>>,
+    pub anim_offsets: Handle<ShaderStorageBuffer>,
+}
+
+impl VoxelMaterials {
+    pub fn get(&self, voxel: Voxel) -> Handle<VoxelChunkMaterial> {
+        self.materials.get(&(voxel.id() as VoxelId)).cloned().unwrap_or_default()
+    }
+
+    /// Frame count for each layer of the texture pack referenced by the
+    /// registry, 1 for unanimated layers.
+    pub fn anim_offsets(registry: &VoxelRegistry) -> Vec<u32> {
+        let mut offsets = vec![1];
+        for def in registry.iter() {
+            let Some(layer) = def.material.texture_layer else {
+                continue;
+            };
+
+            let frames = def.material.texture_frames.max(1);
+            let end = (layer + frames) as usize;
+            if offsets.len() < end {
+                offsets.resize(end, 1);
+            }
+            offsets[layer as usize] = frames;
+        }
+        offsets
+    }
+
+    pub fn material(
+        pbr: &VoxelPbr,
+        texture_pack: &VoxelTexturePack,
+        anim_offsets: &Handle<ShaderStorageBuffer>,
+    ) -> VoxelChunkMaterial {
+        ExtendedMaterial {
+            base: pbr.standard_material(),
+            extension: VoxelTextureExtension {
+                texture_pack: texture_pack.image.clone(),
+                anim_offsets: anim_offsets.clone(),
+                texture_layer: pbr.texture_layer.unwrap_or(NO_TEXTURE_LAYER),
+                texture_scale: TEXTURE_SCALE,
+            },
+        }
+    }
+
+    pub fn rebuild(
+        registry: Res<VoxelRegistry>,
+        texture_pack: Res<VoxelTexturePack>,
+        mut voxel_materials: ResMut<VoxelMaterials>,
+        materials: Option<ResMut<Assets<VoxelChunkMaterial>>>,
+        buffers: Option<ResMut<Assets<ShaderStorageBuffer>>>,
+    ) {
+        let (Some(mut materials), Some(mut buffers)) = (materials, buffers) else {
+            return;
+        };
+
+        let voxel_materials = &mut *voxel_materials;
+        let anim_offsets = ShaderStorageBuffer::from(Self::anim_offsets(&registry));
+        replace_asset(&mut buffers, &mut voxel_materials.anim_offsets, anim_offsets);
+
+        let mut stale = voxel_materials.materials.keys().copied().collect::<Vec<_>>();
+        for def in registry.iter().filter(|def| def.rendered) {
+            let id = def.voxel.id() as VoxelId;
+            stale.retain(|stale_id| *stale_id != id);
+
+            let material =
+                Self::material(&def.material, &texture_pack, &voxel_materials.anim_offsets);
+            let handle = voxel_materials.materials.entry(id).or_default();
+            replace_asset(&mut materials, handle, material);
+        }
+
+        for id in stale {
+            if let Some(handle) = voxel_materials.materials.remove(&id) {
+                materials.remove(&handle);
+            }
+        }
+    }
+}
+
+/// Overwrite the asset behind `handle`, or add a new one if it doesn't exist.
+fn replace_asset<A: Asset>(assets: &mut Assets<A>, handle: &mut Handle<A>, asset: A) {
+    match assets.get_mut(&*handle) {
+        Some(existing) => *existing = asset,
+        None => *handle = assets.add(asset),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::voxel::registry::{VoxelDefinitionAsset, VoxelRegistry};
+    use crate::voxel::voxel::VOXEL_DEFINITIONS;
+
+    fn parse(ron: &str) -> VoxelDefinitionAsset {
+        VoxelDefinitionAsset::from_bytes(ron.as_bytes()).unwrap()
+    }
+
+    #[test]
+    pub fn anim_offsets() {
+        let a =
+            parse(r#"(id: 32, name: "a", material: (texture_layer: Some(2), texture_frames: 3))"#);
+        let b = parse(r#"(id: 33, name: "b", material: (texture_layer: Some(6)))"#);
+        let (registry, errors) = VoxelRegistry::from_assets([&a, &b]);
+        assert_eq!(errors, vec![]);
+
+        assert_eq!(VoxelMaterials::anim_offsets(&registry), vec![
+            1, 1, 3, 1, 1, 1, 1, 1, 2, 1, 2, 1
+        ]);
+    }
+
+    #[test]
+    pub fn shipped_layers_dont_overlap() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../../assets/voxels");
+        let assets = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| parse(&std::fs::read_to_string(entry.unwrap().path()).unwrap()))
+            .collect::<Vec<_>>();
+        let (registry, errors) = VoxelRegistry::from_assets(&assets);
+        assert_eq!(errors, vec![]);
+
+        let mut used = HashMap::new();
+        for def in registry.iter() {
+            let Some(layer) = def.material.texture_layer else {
+                continue;
+            };
+
+            for frame in layer..layer + def.material.texture_frames {
+                if let Some(other) = used.insert(frame, &def.name) {
+                    panic!("layer {frame} is used by both {other} and {}", def.name);
+                }
+            }
+        }
+
+        // the ground is textured
+        for voxel in [Voxel::Stone, Voxel::Dirt, Voxel::Grass, Voxel::Sand] {
+            let builtin = VOXEL_DEFINITIONS[voxel.id() as usize];
+            assert!(builtin.material.texture_layer.is_some(), "{} isn't textured", builtin.name);
+            let shipped = registry.get(voxel.id() as VoxelId).unwrap();
+            assert_eq!(shipped.material.texture_layer, builtin.material.texture_layer);
+            assert_eq!(shipped.material.texture_frames, builtin.material.texture_frames);
+        }
+        assert_eq!(VoxelMaterials::anim_offsets(&registry), vec![
+            1, 1, 1, 1, 1, 1, 1, 1, 2, 1, 2, 1
+        ]);
+    }
+}
@@ -2,22 +2,22 @@ use std::collections::{BTreeSet, VecDeque};
 
 use avian3d::prelude::*;
 use bevy::asset::RenderAssetUsages;
+use bevy::camera::primitives::MeshAabb;
 use bevy::light::{NotShadowCaster, NotShadowReceiver};
+use bevy::mesh::{Indices, VertexAttributeValues};
 use bevy::platform::collections::{HashMap, HashSet};
 use bevy::prelude::*;
-use bevy::camera::primitives::MeshAabb;
-use bevy::mesh::{Indices, VertexAttributeValues};
 use bevy::render::render_resource::PrimitiveTopology;
 use bgm::Face;
 use binary_greedy_meshing::{self as bgm, Quad};
 
 use super::UpdateVoxelMeshSet;
+use crate::voxel::material::VoxelMaterials;
 use crate::voxel::mesh::chunk::VoxelChunk;
 use crate::voxel::mesh::lod::Lod;
 use crate::voxel::mesh::surface_net::{SurfaceNetColliders, SurfaceNetMeshes};
 use crate::voxel::mesh::{ChangedChunk, Remesh, SurfaceNet};
 use crate::voxel::simulation::data::ChunkPoint;
-use crate::voxel::voxel::VoxelMaterials;
 use crate::voxel::{Voxel, Voxels};
 
 pub(super) fn plugin(app: &mut App) {
@@ -7,11 +7,11 @@ use bevy::mesh::{Indices, VertexAttributeValues};
 use bevy::platform::collections::{HashMap, HashSet};
 use bevy::prelude::*;
 use bevy::render::render_resource::PrimitiveTopology;
-use bevy_math::Affine3A;
 use fast_surface_nets::ndshape::{ConstShape3u32, RuntimeShape, Shape};
 use fast_surface_nets::{SurfaceNetsBuffer, surface_nets};
 use priority_queue::PriorityQueue;
 
+use crate::voxel::material::VoxelMaterials;
 use crate::voxel::mesh::binary_greedy::Chunks;
 use crate::voxel::mesh::chunk::VoxelChunk;
 use crate::voxel::mesh::frustum_chunks::FrustumChunks;
@@ -64,31 +64,36 @@ pub struct SurfaceNetMeshes(HashMap<u16, Entity>);
 // #[derive(Component, Default)]
 // pub struct Remeshed(HashSet<ChunkPoint>);
 
-pub const VOXEL_TYPE_COUNT: usize = 16;
+pub const VOXEL_TYPE_COUNT: usize = VoxelSet::CAPACITY;
 
 #[derive(Resource, Debug, Clone)]
 pub struct SampleBuffers {
-    pub buffers: Vec<[f32; 18 * 18 * 18]>,
+    /// Indexed by voxel id, only allocated once that id has been sampled.
+    pub buffers: Vec<Option<Box<[f32; 18 * 18 * 18]>>>,
     pub voxel_set: VoxelSet,
 }
 
 impl Default for SampleBuffers {
     fn default() -> Self {
-        Self {
-            buffers: vec![[1.0; 18 * 18 * 18]; VOXEL_TYPE_COUNT],
-            voxel_set: VoxelSet::default(),
-        }
+        Self { buffers: vec![None; VOXEL_TYPE_COUNT], voxel_set: VoxelSet::default() }
     }
 }
 
 impl SampleBuffers {
     pub fn clear(&mut self) {
         for voxel in self.voxel_set {
-            self.buffers[voxel.id() as usize] = [1.0; 18 * 18 * 18];
+            if let Some(buffer) = &mut self.buffers[voxel.id() as usize] {
+                buffer.fill(1.0);
+            }
         }
 
         // self.voxel_set.clear();
     }
+
+    /// Buffer for `voxel_id`, allocating it on first use.
+    pub fn buffer_mut(&mut self, voxel_id: usize) -> &mut [f32; 18 * 18 * 18] {
+        self.buffers[voxel_id].get_or_insert_with(|| Box::new([1.0; 18 * 18 * 18]))
+    }
 }
 
 pub fn update_surface_net_mesh(
@@ -97,12 +102,11 @@ pub fn update_surface_net_mesh(
 
     remesh_center: Res<RemeshCenter>,
 
-    grids: Query<(Entity, &Voxels, &Chunks /* &mut Remeshed */)>,
+    grids: Query<(Entity, &Voxels, &Chunks, &GlobalTransform /* &mut Remeshed */)>,
     mut chunk_mesh_entities: Query<(&mut SurfaceNetMeshes, &mut SurfaceNetColliders)>,
 
     mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    // voxel_materials: Res<VoxelMaterials>, // buggy when reusing material rn, figure it out later
+    voxel_materials: Res<VoxelMaterials>,
     // mut mesher: Local<BgmMesher>,
     mut surface_net_buffer: Local<SurfaceNetsBuffer>,
     mut sample_buffers: ResMut<SampleBuffers>,
@@ -137,12 +141,16 @@ pub fn update_surface_net_mesh(
 
     // increase priority of all in the queue currently
     for (chunk, priority) in queue.iter_mut() {
-        // center of chunk
-        let chunk_size = Vec3::splat(CHUNK_WIDTH as f32) * GRID_SCALE;
-        let chunk_radius = chunk_size.length() / 2.0;
+        let Ok((.., grid_transform)) = grids.get(chunk.grid_entity) else {
+            continue;
+        };
+
+        // center of chunk, grids can be moved/rotated so work in grid space
+        let chunk_size = Vec3::splat(CHUNK_WIDTH as f32);
+        let chunk_radius = (chunk_size * GRID_SCALE).length() / 2.0;
         let chunk_min = chunk.chunk_point.as_vec3() * chunk_size;
         let chunk_max = chunk_min + chunk_size;
-        let chunk_center = (chunk.chunk_point.as_vec3() * chunk_size) + (chunk_size / 2.0);
+        let chunk_center = grid_transform.transform_point(chunk_min + chunk_size / 2.0);
         let chunk_aabb = Aabb::from_min_max(chunk_min, chunk_max);
 
         // flash chunks in queue
@@ -176,7 +184,7 @@ pub fn update_surface_net_mesh(
         }
 
         // more if within camera frustum
-        if remesh_center.frustum.intersects_obb(&chunk_aabb, &Affine3A::IDENTITY, true, true) {
+        if remesh_center.frustum.intersects_obb(&chunk_aabb, &grid_transform.affine(), true, true) {
             // gizmos.sphere(chunk_center, 0.5, Color::srgb(1.0, 0.0, 1.0));
 
             // adjust by distance
@@ -202,7 +210,7 @@ pub fn update_surface_net_mesh(
 
         let ChangedChunk { grid_entity, chunk_point } = changed_chunk;
 
-        let Ok((_, _, voxel_chunks)) = grids.get(grid_entity) else {
+        let Ok((_, _, voxel_chunks, _)) = grids.get(grid_entity) else {
             warn!("No voxels for entity `{}`", named.get(grid_entity).unwrap());
             continue;
         };
@@ -237,7 +245,7 @@ pub fn update_surface_net_mesh(
 
         let ChangedChunk { grid_entity, chunk_point } = changed_chunk;
 
-        let Ok((_, voxels, voxel_chunks)) = grids.get(grid_entity) else {
+        let Ok((_, voxels, voxel_chunks, _)) = grids.get(grid_entity) else {
             warn!("No voxels for entity `{}`", named.get(grid_entity).unwrap());
             continue;
         };
@@ -247,6 +255,20 @@ pub fn update_surface_net_mesh(
             continue;
         };
 
+        // buried in a solid region, nothing to mesh and whatever was meshed
+        // before is hidden now
+        if voxels.tree.is_interior_chunk(*chunk_point) {
+            if let Ok((mut chunk_meshes, mut chunk_colliders)) =
+                chunk_mesh_entities.get_mut(*chunk_entity)
+            {
+                for (_, entity) in chunk_meshes.drain().chain(chunk_colliders.drain()) {
+                    apply_later.retain(|(later, ..)| *later != entity);
+                    commands.entity(entity).despawn();
+                }
+            }
+            continue;
+        }
+
         #[cfg(feature = "trace")]
         let surface_net_iter_span = info_span!("surface_net_iter").entered();
 
@@ -283,13 +305,15 @@ pub fn update_surface_net_mesh(
             processed = true;
 
             let voxel_id = voxel.id();
-            let sample_buffer = sample_buffers.buffers[voxel_id as usize];
+            let Some(sample_buffer) = sample_buffers.buffers[voxel_id as usize].as_deref() else {
+                continue;
+            };
             let shape = SurfaceNetShape {};
             // info!("creating surface net mesh");
             {
                 #[cfg(feature = "trace")]
                 let span = info_span!("generate").entered();
-                surface_nets(&sample_buffer, &shape, [0; 3], [17; 3], &mut surface_net_buffer);
+                surface_nets(sample_buffer, &shape, [0; 3], [17; 3], &mut surface_net_buffer);
             }
 
             let SurfaceNetsBuffer { ref mut normals, ref mut positions, .. } = *surface_net_buffer;
@@ -363,9 +387,7 @@ pub fn update_surface_net_mesh(
                 // info!("missing chunk mesh");
                 // if let Some(mesh) = render_mesh {
                 let mesh_handle = meshes.add(mesh);
-                let material = materials.add(voxel.material());
-                // let material = materials.add(voxel.material());
-                // let material = voxel_materials.get(voxel);
+                let material = voxel_materials.get(voxel);
                 let mut voxel_mesh_commands = commands.spawn((
                     Name::new(format!("Voxel Mesh ({:?})", voxel.as_name())),
                     Mesh3d(mesh_handle),
@@ -496,10 +518,61 @@ impl VoxelSampler {
                         relative_point.y as u32,
                         relative_point.z as u32,
                     ]);
-                    let buffer = &mut buffers.buffers[buffer_index];
+                    let buffer = buffers.buffer_mut(buffer_index);
                     buffer[voxel_index as usize] = -1.0;
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use bevy::ecs::system::RunSystemOnce;
+    use bevy::gizmos::config::{DefaultGizmoConfigGroup, GizmoConfig, GizmoConfigStore};
+    use bevy::gizmos::gizmos::GizmoStorage;
+
+    use super::*;
+
+    #[test]
+    pub fn buried_chunk_drops_meshes() {
+        let mut world = World::new();
+        world.init_resource::<Messages<ChangedChunk>>();
+        world.init_resource::<Assets<Mesh>>();
+        world.init_resource::<VoxelMaterials>();
+        world.init_resource::<SampleBuffers>();
+        world.init_resource::<RemeshCenter>();
+        world.insert_resource(Remesh { surface_net: 1, ..default() });
+        world.init_resource::<GizmoConfigStore>();
+        let mut gizmo_configs = world.resource_mut::<GizmoConfigStore>();
+        gizmo_configs.insert(GizmoConfig::default(), DefaultGizmoConfigGroup);
+        world.init_resource::<GizmoStorage<DefaultGizmoConfigGroup, ()>>();
+
+        // meshed while it was still on the surface
+        let mesh = world.spawn_empty().id();
+        let collider = world.spawn_empty().id();
+        let mut chunk_meshes = SurfaceNetMeshes::default();
+        chunk_meshes.insert(Voxel::Stone.id(), mesh);
+        let mut chunk_colliders = SurfaceNetColliders::default();
+        chunk_colliders.insert(Voxel::Stone.id(), collider);
+        let chunk_entity = world.spawn((chunk_meshes, chunk_colliders)).id();
+
+        // then everything around it was filled in
+        let chunk_point = ChunkPoint(IVec3::ONE);
+        let mut voxels = Voxels::new(IVec3::splat(64));
+        voxels.fill_aabb(voxels.voxel_aabb(), Voxel::Stone);
+        voxels.tree.compress();
+        assert!(voxels.tree.is_interior_chunk(*chunk_point));
+
+        let mut chunks = Chunks::default();
+        chunks.insert(chunk_point, chunk_entity);
+        let grid_entity = world.spawn((voxels, chunks, GlobalTransform::IDENTITY)).id();
+        world.write_message(ChangedChunk { grid_entity, chunk_point });
+        world.run_system_once(update_surface_net_mesh).unwrap();
+
+        assert!(world.get_entity(mesh).is_err());
+        assert!(world.get_entity(collider).is_err());
+        assert!(world.get::<SurfaceNetMeshes>(chunk_entity).unwrap().is_empty());
+        assert!(world.get::<SurfaceNetColliders>(chunk_entity).unwrap().is_empty());
+    }
+}
@@ -1,22 +1,28 @@
 use bevy::ecs::schedule::ScheduleLabel;
 use bevy::prelude::*;
-pub use commands::VoxelCommand;
+use bevy_inspector_egui::quick::ResourceInspectorPlugin;
+pub use commands::{GridCommand, VoxelCommand};
 pub use mesh::UpdateVoxelMeshSet;
 pub use pick::CursorVoxel;
 pub use simulation::*;
 pub use tree::{VoxelNode, VoxelTree};
 pub use voxel::{Voxel, VoxelSet};
 pub use voxel_aabb::VoxelAabb;
-pub use voxels::Voxels;
+pub use voxels::{Voxels, WorldGrid};
 
 pub mod brush;
 pub mod collider;
 pub mod commands;
+pub mod diff;
+pub mod material;
 pub mod mesh;
 pub mod painter;
 pub mod pick;
 pub mod raycast;
+pub mod registry;
+pub mod save;
 pub mod simulation;
+pub mod smooth;
 pub mod tree;
 pub mod voxel;
 pub mod voxel_aabb;
@@ -33,7 +39,9 @@ pub struct VoxelPlugin;
 impl Plugin for VoxelPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(pick::VoxelPickPlugin)
+            .add_plugins(registry::plugin)
             .add_plugins(voxel::plugin)
+            .add_plugins(material::plugin)
             .add_plugins(voxels::plugin)
             .add_plugins(tree::plugin)
             .add_plugins(collider::plugin)
@@ -48,6 +56,9 @@ impl Plugin for VoxelPlugin {
                 // sim_run_schedule: Last.intern(),
             });
 
+        app.add_plugins(ResourceInspectorPlugin::<SimSettings>::default());
+        app.add_plugins(ResourceInspectorPlugin::<tree::DebugTree>::default());
+
         app.add_systems(Startup, spawn_voxel_grid);
         app.add_systems(Startup, spawn_directional_lights);
         // app.add_systems(Update, dynamic_scene);
@@ -56,6 +67,7 @@ impl Plugin for VoxelPlugin {
 
 pub fn spawn_voxel_grid(mut commands: Commands) {
     commands.spawn((
+        WorldGrid,
         Voxels::new(IVec3::new(1000, 1000, 1000)),
         // Voxels::new(IVec3::new(15, 15, 15)),
         Transform { scale: GRID_SCALE, ..default() },
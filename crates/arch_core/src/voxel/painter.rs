@@ -3,7 +3,7 @@ use bevy_enhanced_input::prelude::{Press, *};
 
 use crate::sdf;
 use crate::voxel::commands::SetVoxelsSdfParams;
-use crate::voxel::{CursorVoxel, Voxel, VoxelCommand, VoxelSet};
+use crate::voxel::{CursorVoxel, GridCommand, Voxel, VoxelCommand, VoxelSet};
 
 pub fn plugin(app: &mut App) {
     app.add_input_context::<VoxelPainter>();
@@ -11,13 +11,35 @@ pub fn plugin(app: &mut App) {
     app.add_systems(Startup, add_voxel_painter);
 
     app.add_observer(cycle_brush)
+        .add_observer(cycle_mode)
         .add_observer(cycle_voxel)
         .add_observer(paint_voxels)
         .add_observer(erase_voxels);
 }
 
+/// What painting with the [`VoxelPainter`] does.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum PaintMode {
+    /// Place the painter's voxel.
+    #[default]
+    Voxel,
+    /// Smooth the terrain under the brush, see [`VoxelCommand::Smooth`].
+    Smooth,
+}
+
+impl PaintMode {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Voxel => Self::Smooth,
+            Self::Smooth => Self::Voxel,
+        }
+    }
+}
+
 #[derive(Component, Default)]
 pub struct VoxelPainter {
+    pub mode: PaintMode,
+
     pub brushes: Vec<&'static dyn sdf::Sdf>,
     pub brush_index: usize,
 
@@ -51,10 +73,16 @@ pub struct CycleBrush;
 #[action_output(bool)]
 pub struct CycleVoxel;
 
+#[derive(InputAction, Debug, Default)]
+#[action_output(bool)]
+pub struct CycleMode;
+
 pub fn add_voxel_painter(mut commands: Commands) {
     commands.spawn((
         Name::new("Voxel painter"),
         VoxelPainter {
+            mode: PaintMode::Voxel,
+
             brushes: vec![
                 &Cuboid { half_size: Vec3::ONE },
                 &sdf::Torus { minor_radius: 4.0, major_radius: 12.0 },
@@ -66,6 +94,7 @@ pub fn add_voxel_painter(mut commands: Commands) {
                 Voxel::Dirt,
                 Voxel::Sand,
                 Voxel::Water(default()),
+                Voxel::Steam(default()),
                 // Voxel::Oil(default())
             ],
             voxel_index: 0,
@@ -81,6 +110,7 @@ pub fn add_voxel_painter(mut commands: Commands) {
             ]),
             (Action::<CycleVoxel>::new(), Press::default(), bindings![KeyCode::KeyV]),
             (Action::<CycleBrush>::new(), Press::default(), bindings![KeyCode::KeyB]),
+            (Action::<CycleMode>::new(), Press::default(), bindings![KeyCode::KeyM]),
         ]],
     ));
 }
@@ -102,29 +132,43 @@ pub fn cycle_brush(trigger: On<Fire<CycleBrush>>, mut painters: Query<&mut Voxel
     painter.brush_index %= painter.brushes.len();
 }
 
+pub fn cycle_mode(trigger: On<Fire<CycleMode>>, mut painters: Query<&mut VoxelPainter>) {
+    let Ok(mut painter) = painters.get_mut(trigger.target()) else {
+        return;
+    };
+    painter.mode = painter.mode.next();
+    info!("paint mode: {:?}", painter.mode);
+}
+
 pub fn paint_voxels(
     trigger: On<Fire<Paint>>,
     cursor_voxel: Res<CursorVoxel>,
     painters: Query<&VoxelPainter>,
 
-    mut commands: EventWriter<VoxelCommand>,
+    mut commands: EventWriter<GridCommand>,
 ) {
     // info!("painting");
     let Ok(painter) = painters.get(trigger.target()) else {
         return;
     };
 
-    if let Some(hit) = cursor_voxel.hit() {
+    if let Some((grid_entity, hit)) = &**cursor_voxel {
         let normal = hit.normal.unwrap_or(IVec3::Y);
         let point = hit.voxel + normal;
 
         // info!("painting at {:?}", hit);
-        commands.write(VoxelCommand::SetVoxelsSdf {
-            origin: point,
-            sdf: painter.brush().as_node(),
-            voxel: painter.voxel(),
-            params: SetVoxelsSdfParams { within: 0.0, can_replace: VoxelSet::AIR },
-        });
+        let command = match painter.mode {
+            PaintMode::Voxel => VoxelCommand::SetVoxelsSdf {
+                origin: point,
+                sdf: painter.brush().as_node(),
+                voxel: painter.voxel(),
+                params: SetVoxelsSdfParams { within: 0.0, can_replace: VoxelSet::AIR },
+            },
+            PaintMode::Smooth => {
+                VoxelCommand::Smooth { origin: hit.voxel, sdf: painter.brush().as_node() }
+            },
+        };
+        commands.write(GridCommand::new(*grid_entity, command));
     }
 }
 
@@ -133,22 +177,22 @@ pub fn erase_voxels(
     cursor_voxel: Res<CursorVoxel>,
     painters: Query<&VoxelPainter>,
 
-    mut commands: MessageWriter<VoxelCommand>,
+    mut commands: MessageWriter<GridCommand>,
 ) {
     let Ok(painter) = painters.get(trigger.target()) else {
         return;
     };
 
-    if let Some(hit) = cursor_voxel.hit() {
+    if let Some((grid_entity, hit)) = &**cursor_voxel {
         let normal = hit.normal.unwrap_or(IVec3::Y);
         let point = hit.voxel;
 
         info!("erasing at {:?}", hit);
-        commands.write(VoxelCommand::SetVoxelsSdf {
+        commands.write(GridCommand::new(*grid_entity, VoxelCommand::SetVoxelsSdf {
             origin: point,
             sdf: painter.brush().as_node(),
             voxel: Voxel::Air,
             params: SetVoxelsSdfParams { within: 0.0, can_replace: VoxelSet::BREAKABLE },
-        });
+        }));
     }
 }
@@ -19,13 +19,19 @@ impl Plugin for VoxelPickPlugin {
     }
 }
 
+/// Closest voxel under the cursor across all grids, along with the grid it
+/// belongs to.
 #[derive(Resource, Debug, Clone, Deref, Reflect)]
 #[reflect(Resource)]
-pub struct CursorVoxel(Option<VoxelHit>);
+pub struct CursorVoxel(Option<(Entity, VoxelHit)>);
 
 impl CursorVoxel {
-    pub fn hit(&self) -> &Option<VoxelHit> {
-        &self.0
+    pub fn hit(&self) -> Option<&VoxelHit> {
+        self.0.as_ref().map(|(_, hit)| hit)
+    }
+
+    pub fn grid_entity(&self) -> Option<Entity> {
+        self.0.as_ref().map(|(grid_entity, _)| *grid_entity)
     }
 }
 
@@ -33,7 +39,7 @@ pub fn cursor_voxel(
     camera_query: Query<(&Camera, &GlobalTransform)>,
     windows: Query<&Window>,
 
-    mut voxels: Query<(&GlobalTransform, &Voxels)>,
+    grids: Query<(Entity, &GlobalTransform, &Voxels)>,
 
     mut cursor_voxel: ResMut<CursorVoxel>,
 ) {
@@ -56,29 +62,26 @@ pub fn cursor_voxel(
 
     // https://github.com/cgyurgyik/fast-voxel-traversal-algorithm/blob/master/overview/FastVoxelTraversalOverview.md
 
-    // Calculate if and where the ray is hitting a voxel.
-    let Ok((voxels_transform, voxels)) = voxels.single_mut() else {
-        info!("No voxels found");
-        return;
-    };
-
-    let hit = voxels.cast_ray(voxels_transform, ray, 1_000.0);
-    cursor_voxel.0 = hit;
+    // Calculate if and where the ray is hitting a voxel, closest grid wins.
+    cursor_voxel.0 = grids
+        .iter()
+        .filter_map(|(grid_entity, grid_transform, voxels)| {
+            Some((grid_entity, voxels.cast_ray(grid_transform, ray, 1_000.0)?))
+        })
+        .min_by(|(_, a), (_, b)| a.distance.total_cmp(&b.distance));
 }
 
 pub fn draw_cursor(
     cursor_voxel: Res<CursorVoxel>,
-    mut voxels: Query<(&GlobalTransform, &mut Voxels)>,
+    grids: Query<&GlobalTransform, With<Voxels>>,
     mut gizmos: Gizmos,
 ) {
-    // Calculate if and where the ray is hitting a voxel.
-    let Ok((voxel_transform, mut voxels)) = voxels.single_mut() else {
-        info!("No voxels found");
-        return;
-    };
-
     // Draw a circle just above the ground plane at that position.
-    if let Some(hit) = cursor_voxel.hit() {
+    if let Some((grid_entity, hit)) = &cursor_voxel.0 {
+        let Ok(voxel_transform) = grids.get(*grid_entity) else {
+            return;
+        };
+
         // let direct_point = ray.origin + hit.distance_to_chunk * chunk_SCALE;
 
         // info!("hit: {:?}", hit);
@@ -90,9 +93,10 @@ pub fn draw_cursor(
 
         let point_with_normal = point + normal * 0.501;
         let world_space_point = voxel_transform.transform_point(point_with_normal);
+        let world_normal = voxel_transform.affine().transform_vector3(normal).normalize();
 
         gizmos.circle(
-            Isometry3d::new(world_space_point, Quat::from_rotation_arc(Vec3::Z, normal)),
+            Isometry3d::new(world_space_point, Quat::from_rotation_arc(Vec3::Z, world_normal)),
             0.05,
             Color::WHITE,
         );
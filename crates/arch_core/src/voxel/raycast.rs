@@ -265,7 +265,7 @@ pub fn debug_raycast(
     camera_query: Query<(&Camera, &GlobalTransform)>,
     windows: Query<&Window>,
 
-    mut voxels: Query<(&GlobalTransform, &crate::voxel::Voxels)>,
+    grids: Query<(&GlobalTransform, &crate::voxel::Voxels)>,
 
     mut gizmos: Gizmos,
 
@@ -300,55 +300,30 @@ pub fn debug_raycast(
 
     // https://github.com/cgyurgyik/fast-voxel-traversal-algorithm/blob/master/overview/FastVoxelTraversalOverview.md
 
-    // Calculate if and where the ray is hitting a voxel.
-    let Ok((voxels_transform, voxels)) = voxels.single_mut() else {
-        info!("No voxels found");
-        return;
-    };
-
     let test_ray = if let Some(last_ray) = debug_raycast.debug_ray { last_ray } else { ray };
 
-    // const GREEN: Color = Color::srgb(0.0, 1.0, 0.0);
-    // const RED: Color = Color::srgb(1.0, 0.0, 0.0);
-    // const BLUE: Color = Color::srgb(0.0, 0.0, 1.0);
-    for hit in voxels.ray_iter(voxels_transform, test_ray, 1_000.0) {
-        use crate::voxel::GRID_SCALE;
-        const CHUNK_SIZE: Vec3 = Vec3::splat(crate::voxel::mesh::unpadded::SIZE as f32);
-
-        // info!("- hit: {:?}", hit);
-
-        // Generate chunk aabbs that we sampled
-        // if debug_raycast.show_chunks {
-        //     #[allow(non_snake_case)]
-        //     let SCALED_CHUNK_SIZE: Vec3 = CHUNK_SIZE * GRID_SCALE;
-
-        //     let pos = hit.chunk.as_vec3();
-        //     gizmos.cuboid(
-        //         Transform {
-        //             translation: pos * SCALED_CHUNK_SIZE + SCALED_CHUNK_SIZE / 2.0,
-        //             scale: SCALED_CHUNK_SIZE,
-        //             ..default()
-        //         },
-        //         Color::srgb(1.0, 0.0, 0.0),
-        //     );
-        // }
-
-        // Generate voxel aabbs that we sampled
-        if debug_raycast.show_voxels {
-            let pos = hit.voxel.as_vec3();
-            gizmos.cuboid(
-                Transform {
-                    translation: pos * GRID_SCALE + GRID_SCALE / 2.0,
-                    scale: GRID_SCALE,
-                    ..default()
-                },
-                Color::srgb(1.0, 0.0, 0.0),
-            );
-        }
+    // Calculate if and where the ray is hitting a voxel in each grid.
+    for (grid_transform, voxels) in &grids {
+        // const GREEN: Color = Color::srgb(0.0, 1.0, 0.0);
+        // const RED: Color = Color::srgb(1.0, 0.0, 0.0);
+        // const BLUE: Color = Color::srgb(0.0, 0.0, 1.0);
+        for hit in voxels.ray_iter(grid_transform, test_ray, 1_000.0) {
+            // info!("- hit: {:?}", hit);
+
+            // Generate voxel aabbs that we sampled
+            if debug_raycast.show_voxels {
+                let pos = hit.voxel.as_vec3();
+                gizmos.cuboid(
+                    grid_transform
+                        .mul_transform(Transform::from_translation(pos + Vec3::splat(0.5))),
+                    Color::srgb(1.0, 0.0, 0.0),
+                );
+            }
 
-        let voxel = voxels.get_voxel(hit.voxel);
-        if voxel.pickable() {
-            break;
+            let voxel = voxels.get_voxel(hit.voxel);
+            if voxel.pickable() {
+                break;
+            }
         }
     }
 }
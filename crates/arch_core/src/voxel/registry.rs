@@ -0,0 +1,700 @@
+//! Data-driven voxel definitions.
+//!
+//! The builtins in [`VOXEL_DEFINITIONS`] are always registered,
+//! `assets/voxels/*.voxel.ron` files add to them. A file with a builtin id is
+//! layered over that builtin, only the fields it sets change. Other ids must
+//! be at least [`CUSTOM_VOXEL_ID_START`] and become a [`Voxel::Custom`].
+//!
+//! The active [`VoxelRegistry`] is published to a global table so the hot
+//! paths ([`Voxel::definition`] in the simulation/meshing) don't need access
+//! to the world.
+
+use std::borrow::Cow;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, LoadContext, LoadedFolder};
+use bevy::prelude::*;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::voxel::Voxel;
+use crate::voxel::voxel::{
+    CUSTOM_VOXEL_ID_START, DEFAULT_PBR, Interactions, MISSING_DEFINITION, PhaseChange, Reaction,
+    SimKind, VOXEL_DEFINITIONS, VOXEL_ID_COUNT, VoxelDefinition, VoxelId, VoxelPbr,
+};
+
+pub fn plugin(app: &mut App) {
+    let registry = VoxelRegistry::builtin();
+    registry.publish();
+    app.insert_resource(registry);
+
+    // Headless apps (tests, benches) only get the builtin voxels.
+    if app.is_plugin_added::<AssetPlugin>() {
+        app.init_asset::<VoxelDefinitionAsset>();
+        app.init_asset_loader::<VoxelDefinitionLoader>();
+        app.add_systems(Startup, load_voxel_definitions);
+        app.add_systems(
+            PreUpdate,
+            rebuild_registry.run_if(on_message::<AssetEvent<VoxelDefinitionAsset>>),
+        );
+    }
+}
+
+/// Published definitions, indexed by voxel id. Null means "use the builtin".
+static DEFINITIONS: [AtomicPtr<VoxelDefinition>; VOXEL_ID_COUNT] =
+    [const { AtomicPtr::new(std::ptr::null_mut()) }; VOXEL_ID_COUNT];
+
+/// Definition for this id in the published registry.
+#[inline]
+pub fn definition(id: VoxelId) -> Option<&'static VoxelDefinition> {
+    let ptr = DEFINITIONS[id as usize].load(Ordering::Acquire);
+    if ptr.is_null() {
+        VOXEL_DEFINITIONS.get(id as usize).copied()
+    } else {
+        // SAFETY: only ever set from leaked `&'static VoxelDefinition`s in
+        // `VoxelRegistry::publish`.
+        Some(unsafe { &*ptr })
+    }
+}
+
+/// Iterate all definitions in the published registry, in id order.
+pub fn iter_definitions() -> impl Iterator<Item = &'static VoxelDefinition> {
+    (0..VOXEL_ID_COUNT).filter_map(|id| definition(id as VoxelId))
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum RegistryError {
+    #[error("voxel `{name}` has id {id} which is out of range (max {max})")]
+    IdOutOfRange { name: String, id: usize, max: usize },
+    #[error(
+        "voxel `{name}` has id {id} which is reserved for builtins (custom ids start at {min})"
+    )]
+    ReservedId { name: String, id: usize, min: usize },
+    #[error("voxel id {id} is defined by both `{first}` and `{second}`")]
+    DuplicateId { id: usize, first: String, second: String },
+    #[error("voxel name `{name}` is used by both id {first} and id {second}")]
+    DuplicateName { name: String, first: usize, second: usize },
+    #[error("voxel `{name}` references unknown voxel `{reference}`")]
+    UnknownReference { name: String, reference: String },
+}
+
+/// All known voxel definitions, indexed by id.
+#[derive(Resource, Debug, Clone)]
+pub struct VoxelRegistry {
+    definitions: Vec<Option<&'static VoxelDefinition>>,
+}
+
+impl VoxelRegistry {
+    /// Registry containing only the builtin [`VOXEL_DEFINITIONS`].
+    pub fn builtin() -> Self {
+        let mut definitions = vec![None; VOXEL_ID_COUNT];
+        for (id, &def) in VOXEL_DEFINITIONS.iter().enumerate() {
+            definitions[id] = Some(def);
+        }
+        Self { definitions }
+    }
+
+    /// Build a registry from the builtins overlayed with loaded definition
+    /// assets.
+    ///
+    /// Invalid definitions are skipped and reported, the rest of the registry
+    /// is still usable.
+    pub fn from_assets<'a>(
+        assets: impl IntoIterator<Item = &'a VoxelDefinitionAsset>,
+    ) -> (Self, Vec<RegistryError>) {
+        let mut registry = Self::builtin();
+        let mut errors = Vec::new();
+
+        // Sort so the result doesn't depend on load order.
+        let mut assets = assets.into_iter().collect::<Vec<_>>();
+        assets.sort_by(|a, b| a.id.cmp(&b.id).then_with(|| a.name.cmp(&b.name)));
+
+        let mut accepted: Vec<&VoxelDefinitionAsset> = Vec::new();
+        for asset in assets {
+            if asset.id >= VOXEL_ID_COUNT {
+                errors.push(RegistryError::IdOutOfRange {
+                    name: asset.name.clone(),
+                    id: asset.id,
+                    max: VOXEL_ID_COUNT - 1,
+                });
+                continue;
+            }
+
+            if (VOXEL_DEFINITIONS.len()..CUSTOM_VOXEL_ID_START).contains(&asset.id) {
+                errors.push(RegistryError::ReservedId {
+                    name: asset.name.clone(),
+                    id: asset.id,
+                    min: CUSTOM_VOXEL_ID_START,
+                });
+                continue;
+            }
+
+            if let Some(first) = accepted.iter().find(|other| other.id == asset.id) {
+                errors.push(RegistryError::DuplicateId {
+                    id: asset.id,
+                    first: first.name.clone(),
+                    second: asset.name.clone(),
+                });
+                continue;
+            }
+
+            if let Some(first) =
+                accepted.iter().find(|other| other.name.eq_ignore_ascii_case(&asset.name))
+            {
+                errors.push(RegistryError::DuplicateName {
+                    name: asset.name.clone(),
+                    first: first.id,
+                    second: asset.id,
+                });
+                continue;
+            }
+
+            accepted.push(asset);
+        }
+
+        // Names of builtins that aren't overridden still need to resolve.
+        let lookup = |name: &str| -> Option<Voxel> {
+            accepted
+                .iter()
+                .find(|asset| asset.name.eq_ignore_ascii_case(name))
+                .map(|asset| voxel_for_id(asset.id))
+                .or_else(|| {
+                    VOXEL_DEFINITIONS
+                        .iter()
+                        .filter(|def| {
+                            accepted.iter().all(|asset| asset.id != def.voxel.id() as usize)
+                        })
+                        .find(|def| def.name == name)
+                        .map(|def| def.voxel)
+                })
+        };
+
+        for asset in &accepted {
+            let base = match VOXEL_DEFINITIONS.get(asset.id) {
+                Some(&builtin) => builtin.clone(),
+                None => custom_base(),
+            };
+
+            // Without an `interactions` block the base's are kept as they are.
+            let Some(asset_interactions) = &asset.interactions else {
+                let definition = asset.to_definition(&base, base.interactions);
+                registry.definitions[asset.id] =
+                    Some(intern(definition, base.interactions.reactions));
+                continue;
+            };
+
+            let mut resolve = |reference: &String| {
+                let voxel = lookup(reference);
+                if voxel.is_none() {
+                    errors.push(RegistryError::UnknownReference {
+                        name: asset.name.clone(),
+                        reference: reference.clone(),
+                    });
+                }
+                voxel
+            };
+
+            let burnt = asset_interactions.burnt.as_ref().and_then(&mut resolve);
+            let crumbled = asset_interactions.crumbled.as_ref().and_then(&mut resolve);
+
+            // Reactions referencing unknown voxels are dropped entirely.
+            let mut reactions = Vec::new();
+            for reaction in &asset_interactions.reactions {
+                let Some(with) = resolve(&reaction.with) else {
+                    continue;
+                };
+                let becomes = match &reaction.becomes {
+                    Some(becomes) => match resolve(becomes) {
+                        Some(voxel) => Some(voxel),
+                        None => continue,
+                    },
+                    None => None,
+                };
+                let other_becomes = match &reaction.other_becomes {
+                    Some(other_becomes) => match resolve(other_becomes) {
+                        Some(voxel) => Some(voxel),
+                        None => continue,
+                    },
+                    None => None,
+                };
+
+                reactions.push(Reaction {
+                    with,
+                    becomes,
+                    other_becomes,
+                    chance: reaction.chance.clamp(0.0, 1.0),
+                });
+            }
+
+            let mut phase_change = |phase_change: &Option<PhaseChangeAsset>| {
+                let phase_change = phase_change.as_ref()?;
+                resolve(&phase_change.becomes)
+                    .map(|becomes| PhaseChange { temperature: phase_change.temperature, becomes })
+            };
+            let heated = phase_change(&asset_interactions.heated);
+            let cooled = phase_change(&asset_interactions.cooled);
+
+            let interactions = Interactions {
+                burnt,
+                // set by `intern`
+                reactions: &[],
+                temperature: asset_interactions.temperature,
+                heated,
+                cooled,
+                crumbled,
+            };
+            let definition = asset.to_definition(&base, interactions);
+            registry.definitions[asset.id] = Some(intern(definition, &reactions));
+        }
+
+        (registry, errors)
+    }
+
+    #[inline]
+    pub fn get(&self, id: VoxelId) -> Option<&'static VoxelDefinition> {
+        self.definitions[id as usize]
+    }
+
+    pub fn from_name(&self, name: &str) -> Option<Voxel> {
+        self.iter().find(|def| def.name == name).map(|def| def.voxel)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'static VoxelDefinition> + '_ {
+        self.definitions.iter().filter_map(|def| *def)
+    }
+
+    /// Make this the registry used by [`Voxel::definition`] and friends.
+    pub fn publish(&self) {
+        for (id, slot) in DEFINITIONS.iter().enumerate() {
+            let ptr = match self.definitions[id] {
+                // Keep builtins pointing at the static table.
+                Some(def)
+                    if VOXEL_DEFINITIONS
+                        .get(id)
+                        .is_some_and(|&builtin| std::ptr::eq(builtin, def)) =>
+                {
+                    std::ptr::null_mut()
+                },
+                Some(def) => def as *const VoxelDefinition as *mut VoxelDefinition,
+                None => std::ptr::null_mut(),
+            };
+            slot.store(ptr, Ordering::Release);
+        }
+    }
+}
+
+/// Everything [`VoxelRegistry::from_assets`] has leaked so far.
+static INTERNED: Mutex<Vec<&'static VoxelDefinition>> = Mutex::new(Vec::new());
+
+/// Leak `definition` with `reactions` so `Voxel::definition` can hand out
+/// `&'static`.
+///
+/// Definitions equal to a builtin or to one leaked by an earlier rebuild are
+/// reused, so hot reloading only leaks the definitions that changed.
+fn intern(mut definition: VoxelDefinition, reactions: &[Reaction]) -> &'static VoxelDefinition {
+    let mut interned = INTERNED.lock().unwrap_or_else(|err| err.into_inner());
+    for &existing in VOXEL_DEFINITIONS.iter().chain(interned.iter()) {
+        definition.interactions.reactions = existing.interactions.reactions;
+        if existing.interactions.reactions == reactions && *existing == definition {
+            return existing;
+        }
+    }
+
+    definition.interactions.reactions = reactions.to_vec().leak();
+    let definition: &'static VoxelDefinition = Box::leak(Box::new(definition));
+    interned.push(definition);
+    definition
+}
+
+/// What custom voxels are layered over, [`MISSING_DEFINITION`] without the
+/// magenta.
+fn custom_base() -> VoxelDefinition {
+    VoxelDefinition { material: DEFAULT_PBR, ..MISSING_DEFINITION }
+}
+
+/// Builtin ids keep their enum variant so the packed extra data still works.
+fn voxel_for_id(id: usize) -> Voxel {
+    match VOXEL_DEFINITIONS.get(id) {
+        Some(def) => def.voxel,
+        None => Voxel::Custom(id as VoxelId),
+    }
+}
+
+/// On-disk description of a voxel type, see `assets/voxels/`.
+///
+/// Fields that are left out keep the value of the builtin with the same id,
+/// custom voxels start from [`MISSING_DEFINITION`] with the default material.
+/// `interactions` replaces all of the base's interactions when present since
+/// `None` is meaningful inside it, `material` is layered field by field.
+///
+/// Parse with [`VoxelDefinitionAsset::from_bytes`], it enables implicit `Some`
+/// so layered fields are written like plain values.
+#[derive(Asset, TypePath, Debug, Clone, Deserialize)]
+pub struct VoxelDefinitionAsset {
+    pub id: usize,
+    pub name: String,
+    #[serde(default)]
+    pub simulation_kind: Option<SimKind>,
+    #[serde(default)]
+    pub simulated: Option<bool>,
+    #[serde(default)]
+    pub collidable: Option<bool>,
+    #[serde(default)]
+    pub rendered: Option<bool>,
+    #[serde(default)]
+    pub transparent: Option<bool>,
+    #[serde(default)]
+    pub pickable: Option<bool>,
+    #[serde(default)]
+    pub breakable: Option<bool>,
+    #[serde(default)]
+    pub initial_health: Option<i16>,
+    #[serde(default)]
+    pub density: Option<i8>,
+    #[serde(default)]
+    pub strength: Option<u8>,
+    #[serde(default)]
+    pub shadow_caster: Option<bool>,
+    #[serde(default)]
+    pub shadow_receiver: Option<bool>,
+    #[serde(default)]
+    pub interactions: Option<InteractionsAsset>,
+    #[serde(default)]
+    pub material: MaterialAsset,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct InteractionsAsset {
+    /// Name of the voxel this turns into after being burnt.
+    #[serde(default)]
+    pub burnt: Option<String>,
+    #[serde(default)]
+    pub reactions: Vec<ReactionAsset>,
+    #[serde(default)]
+    pub temperature: Option<i16>,
+    #[serde(default)]
+    pub heated: Option<PhaseChangeAsset>,
+    #[serde(default)]
+    pub cooled: Option<PhaseChangeAsset>,
+    /// Name of the voxel this turns into when it can't carry its load.
+    #[serde(default)]
+    pub crumbled: Option<String>,
+}
+
+/// [`PhaseChange`] with the voxel referenced by name.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PhaseChangeAsset {
+    pub temperature: i16,
+    pub becomes: String,
+}
+
+/// [`Reaction`] with voxels referenced by name.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReactionAsset {
+    pub with: String,
+    #[serde(default)]
+    pub becomes: Option<String>,
+    #[serde(default)]
+    pub other_becomes: Option<String>,
+    pub chance: f32,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MaterialAsset {
+    /// sRGBA.
+    pub base_color: Option<[f32; 4]>,
+    pub perceptual_roughness: Option<f32>,
+    pub reflectance: Option<f32>,
+    pub metallic: Option<f32>,
+    pub alpha_blend: Option<bool>,
+    /// Layer in `array_texture.png`.
+    pub texture_layer: Option<u32>,
+    pub texture_frames: Option<u32>,
+}
+
+impl VoxelDefinitionAsset {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ron::error::SpannedError> {
+        ron::Options::default()
+            .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+            .from_bytes(bytes)
+    }
+
+    /// Layer this asset over `base`.
+    pub fn to_definition(
+        &self,
+        base: &VoxelDefinition,
+        interactions: Interactions,
+    ) -> VoxelDefinition {
+        let material = &self.material;
+        VoxelDefinition {
+            voxel: voxel_for_id(self.id),
+            name: Cow::Owned(self.name.to_ascii_lowercase()),
+            simulation_kind: self.simulation_kind.unwrap_or(base.simulation_kind),
+            simulated: self.simulated.unwrap_or(base.simulated),
+            collidable: self.collidable.unwrap_or(base.collidable),
+            rendered: self.rendered.unwrap_or(base.rendered),
+            transparent: self.transparent.unwrap_or(base.transparent),
+            pickable: self.pickable.unwrap_or(base.pickable),
+            breakable: self.breakable.unwrap_or(base.breakable),
+            initial_health: self.initial_health.unwrap_or(base.initial_health),
+            density: self.density.unwrap_or(base.density),
+            strength: self.strength.unwrap_or(base.strength),
+            shadow_caster: self.shadow_caster.unwrap_or(base.shadow_caster),
+            shadow_receiver: self.shadow_receiver.unwrap_or(base.shadow_receiver),
+            interactions,
+            material: VoxelPbr {
+                base_color: match material.base_color {
+                    Some([r, g, b, a]) => Color::srgba(r, g, b, a),
+                    None => base.material.base_color,
+                },
+                perceptual_roughness: material
+                    .perceptual_roughness
+                    .unwrap_or(base.material.perceptual_roughness),
+                reflectance: material.reflectance.unwrap_or(base.material.reflectance),
+                metallic: material.metallic.unwrap_or(base.material.metallic),
+                alpha_blend: material.alpha_blend.unwrap_or(base.material.alpha_blend),
+                texture_layer: material.texture_layer.or(base.material.texture_layer),
+                texture_frames: material
+                    .texture_frames
+                    .unwrap_or(base.material.texture_frames)
+                    .max(1),
+            },
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum VoxelDefinitionLoaderError {
+    #[error("could not read voxel definition: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse voxel definition: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+}
+
+#[derive(Default, TypePath)]
+pub struct VoxelDefinitionLoader;
+
+impl AssetLoader for VoxelDefinitionLoader {
+    type Asset = VoxelDefinitionAsset;
+    type Error = VoxelDefinitionLoaderError;
+    type Settings = ();
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &Self::Settings,
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(VoxelDefinitionAsset::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["voxel.ron"]
+    }
+}
+
+/// Keeps the `assets/voxels` folder loaded.
+#[derive(Resource)]
+pub struct VoxelDefinitionFolder(pub Handle<LoadedFolder>);
+
+pub fn load_voxel_definitions(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(VoxelDefinitionFolder(asset_server.load_folder("voxels")));
+}
+
+pub fn rebuild_registry(
+    definitions: Res<Assets<VoxelDefinitionAsset>>,
+    mut registry: ResMut<VoxelRegistry>,
+) {
+    let (new_registry, errors) = VoxelRegistry::from_assets(definitions.iter().map(|(_, def)| def));
+    for error in &errors {
+        warn!("voxel registry: {error}");
+    }
+
+    new_registry.publish();
+    *registry = new_registry;
+    info!("rebuilt voxel registry: {} voxel types", registry.iter().count());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(src: &str) -> VoxelDefinitionAsset {
+        VoxelDefinitionAsset::from_bytes(src.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn parse_shipped_definitions() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../../assets/voxels");
+        let assets = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| parse(&std::fs::read_to_string(entry.unwrap().path()).unwrap()))
+            .collect::<Vec<_>>();
+
+        let (registry, errors) = VoxelRegistry::from_assets(&assets);
+        assert_eq!(errors, vec![]);
+        for def in VOXEL_DEFINITIONS {
+            assert_eq!(registry.from_name(&def.name), Some(def.voxel));
+        }
+
+        // custom powders have to sink like sand
+        let water = registry.get(Voxel::Water(default()).id() as VoxelId).unwrap();
+        for def in registry.iter().filter(|def| def.simulation_kind == SimKind::SemiSolid) {
+            assert!(def.density > water.density, "{} floats on water", def.name);
+        }
+    }
+
+    #[test]
+    fn override_layers_over_builtin() {
+        let sand = parse(r#"(id: 6, name: "sand", density: 60, material: (metallic: 0.5))"#);
+        let (registry, errors) = VoxelRegistry::from_assets([&sand]);
+        assert_eq!(errors, vec![]);
+
+        let builtin = VOXEL_DEFINITIONS[6];
+        let def = registry.get(6).unwrap();
+        assert_eq!(def.density, 60);
+        assert_eq!(def.material.metallic, 0.5);
+
+        let mut def = def.clone();
+        def.density = builtin.density;
+        def.material.metallic = builtin.material.metallic;
+        assert_eq!(&def, builtin);
+    }
+
+    #[test]
+    fn custom_voxel() {
+        let clay = parse(r#"(id: 32, name: "Clay", interactions: (burnt: Some("stone")))"#);
+        let deep_ore = parse(r#"(id: 200, name: "deep_ore")"#);
+        let (registry, errors) = VoxelRegistry::from_assets([&clay, &deep_ore]);
+        assert_eq!(errors, vec![]);
+
+        let def = registry.get(32).unwrap();
+        assert_eq!(def.voxel, Voxel::Custom(32));
+        assert_eq!(def.name, "clay");
+        assert_eq!(def.interactions.burnt, Some(Voxel::Stone));
+        assert_eq!(def.initial_health, MISSING_DEFINITION.initial_health);
+        assert_eq!(def.material, DEFAULT_PBR);
+        assert_eq!(registry.from_name("sand"), Some(Voxel::Sand));
+        assert_eq!(registry.from_name("deep_ore"), Some(Voxel::Custom(200)));
+    }
+
+    #[test]
+    fn override_builtin_keeps_variant() {
+        let water = parse(r#"(id: 7, name: "water", simulation_kind: Liquid, density: 3)"#);
+        let (registry, errors) = VoxelRegistry::from_assets([&water]);
+        assert_eq!(errors, vec![]);
+        assert!(matches!(registry.get(7).unwrap().voxel, Voxel::Water(..)));
+        assert_eq!(registry.get(7).unwrap().density, 3);
+    }
+
+    #[test]
+    fn rebuilds_reuse_definitions() {
+        let dirt = parse(r#"(id: 3, name: "dirt")"#);
+        let clay = parse(
+            r#"(id: 32, name: "clay", interactions: (reactions: [(with: "fire", chance: 0.5)]))"#,
+        );
+        let (first, errors) = VoxelRegistry::from_assets([&dirt, &clay]);
+        assert_eq!(errors, vec![]);
+        let (second, _) = VoxelRegistry::from_assets([&dirt, &clay]);
+
+        assert_eq!(first.get(3).unwrap(), VOXEL_DEFINITIONS[3]);
+        assert!(std::ptr::eq(first.get(32).unwrap(), second.get(32).unwrap()));
+    }
+
+    #[test]
+    fn reactions() {
+        let glass = parse(r#"(id: 40, name: "sea_glass", transparent: true)"#);
+        let sand = parse(
+            r#"(
+                id: 6,
+                name: "sand",
+                interactions: (
+                    reactions: [
+                        (with: "fire", becomes: Some("sea_glass"), chance: 0.1),
+                        (with: "magma", becomes: Some("sea_glass"), chance: 0.1),
+                    ],
+                ),
+            )"#,
+        );
+        let (registry, errors) = VoxelRegistry::from_assets([&glass, &sand]);
+        assert_eq!(errors, vec![RegistryError::UnknownReference {
+            name: "sand".to_owned(),
+            reference: "magma".to_owned(),
+        }]);
+
+        let reactions = registry.get(6).unwrap().interactions.reactions;
+        assert_eq!(reactions, &[Reaction {
+            with: Voxel::Fire { voxel_id: 0 },
+            becomes: Some(Voxel::Custom(40)),
+            other_becomes: None,
+            chance: 0.1,
+        }]);
+    }
+
+    #[test]
+    fn phase_changes() {
+        let glass = parse(r#"(id: 40, name: "sea_glass", transparent: true)"#);
+        let sand = parse(
+            r#"(
+                id: 6,
+                name: "sand",
+                interactions: (
+                    heated: Some((temperature: 1200, becomes: "sea_glass")),
+                ),
+            )"#,
+        );
+        let (registry, errors) = VoxelRegistry::from_assets([&glass, &sand]);
+        assert_eq!(errors, vec![]);
+
+        let interactions = registry.get(6).unwrap().interactions;
+        assert_eq!(
+            interactions.heated,
+            Some(PhaseChange { temperature: 1200, becomes: Voxel::Custom(40) })
+        );
+        assert_eq!(interactions.cooled, None);
+        assert_eq!(interactions.temperature, None);
+    }
+
+    #[test]
+    fn invalid_definitions() {
+        let a = parse(r#"(id: 32, name: "a")"#);
+        let b = parse(r#"(id: 32, name: "b")"#);
+        let c = parse(r#"(id: 33, name: "a")"#);
+        let d = parse(r#"(id: 34, name: "d", interactions: (burnt: Some("nope")))"#);
+        let e = parse(r#"(id: 256, name: "e")"#);
+        let f = parse(r#"(id: 20, name: "f")"#);
+        let (registry, errors) = VoxelRegistry::from_assets([&a, &b, &c, &d, &e, &f]);
+
+        assert_eq!(errors.len(), 5);
+        assert!(errors.contains(&RegistryError::DuplicateId {
+            id: 32,
+            first: "a".to_owned(),
+            second: "b".to_owned(),
+        }));
+        assert!(errors.contains(&RegistryError::DuplicateName {
+            name: "a".to_owned(),
+            first: 32,
+            second: 33,
+        }));
+        assert!(errors.contains(&RegistryError::UnknownReference {
+            name: "d".to_owned(),
+            reference: "nope".to_owned(),
+        }));
+        assert!(errors.iter().any(|e| matches!(e, RegistryError::IdOutOfRange { id: 256, .. })));
+        assert!(errors.contains(&RegistryError::ReservedId {
+            name: "f".to_owned(),
+            id: 20,
+            min: CUSTOM_VOXEL_ID_START,
+        }));
+
+        // Bad references still register the voxel, just without the interaction.
+        assert_eq!(registry.get(34).unwrap().interactions.burnt, None);
+        assert_eq!(registry.get(33), None);
+        assert_eq!(registry.get(20), None);
+    }
+}
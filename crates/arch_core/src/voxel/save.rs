@@ -0,0 +1,341 @@
+//! Versioned binary save format for a [`Voxels`] grid.
+//!
+//! Everything is little endian:
+//! - header: [`SAVE_MAGIC`], `u16` [`SAVE_VERSION`], `u8` root layer, `3 x i32`
+//!   root origin chunk point, `3 x i32` voxel size.
+//!
+//! Version 1 saves have no root origin, their root is always at zero.
+//! - tree: nodes depth first, each a `u8` tag (authority in the high bit)
+//!   followed by
+//!   - [`VoxelNode::Solid`]: `u16` packed voxel.
+//!   - [`VoxelNode::Children`]: [`TREE_LENGTH`] child nodes.
+//!   - [`VoxelNode::Leaf`]: `u16` run count, then `(u16 packed voxel, u16
+//!     length)` runs, see [`RLEChunk`].
+//! - damage: `u32` chunk count, each chunk is a `3 x i32` chunk point, `u16`
+//!   entry count and `(u16 leaf index, i16 health)` entries.
+//!
+//! Voxels are stored packed so liquid/gas state survives the round trip.
+
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use bevy::platform::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+use thiserror::Error;
+
+use crate::voxel::simulation::rle::RLEChunk;
+use crate::voxel::tree::{
+    CHUNK_LENGTH, ChunkDamage, MAX_ROOT_LAYER, SharedData, TREE_LENGTH, VoxelNode, VoxelTree,
+};
+use crate::voxel::voxel::{pack_voxel, unpack_voxel};
+use crate::voxel::{Voxel, Voxels};
+
+pub const SAVE_MAGIC: [u8; 4] = *b"CVOX";
+/// Bump when the layout changes, and keep a loader around for the old one.
+pub const SAVE_VERSION: u16 = 2;
+
+const TAG_SOLID: u8 = 0;
+const TAG_CHILDREN: u8 = 1;
+const TAG_LEAF: u8 = 2;
+const TAG_AUTHORITY: u8 = 1 << 7;
+
+#[derive(Error, Debug)]
+pub enum SaveError {
+    #[error("could not read/write voxel save: {0}")]
+    Io(#[from] io::Error),
+    #[error("not a voxel save, magic was {0:?}")]
+    BadMagic([u8; 4]),
+    #[error("unsupported voxel save version {0}, latest is {SAVE_VERSION}")]
+    UnsupportedVersion(u16),
+    #[error("invalid node tag {tag} at layer {layer}")]
+    InvalidNode { tag: u8, layer: usize },
+    #[error("leaf runs cover {0} voxels instead of {CHUNK_LENGTH}")]
+    InvalidLeaf(usize),
+    #[error("root layer {0} is above the maximum of {MAX_ROOT_LAYER}")]
+    InvalidRootLayer(usize),
+    #[error("damaged voxel index {0} is outside of the chunk")]
+    InvalidDamage(u16),
+}
+
+/// Write `voxels` to `writer` in the latest save format.
+pub fn save(voxels: &Voxels, writer: &mut impl Write) -> Result<(), SaveError> {
+    writer.write_all(&SAVE_MAGIC)?;
+    writer.write_all(&SAVE_VERSION.to_le_bytes())?;
+    writer.write_all(&[voxels.tree.root_layer() as u8])?;
+    write_ivec3(writer, voxels.tree.origin)?;
+    write_ivec3(writer, voxels.voxel_size)?;
+
+    write_node(writer, &voxels.tree.root)?;
+
+    writer.write_all(&(voxels.tree.damage.len() as u32).to_le_bytes())?;
+    for (chunk_point, chunk_damage) in voxels.tree.damage.iter() {
+        write_ivec3(writer, *chunk_point)?;
+        writer.write_all(&(chunk_damage.len() as u16).to_le_bytes())?;
+        for (voxel_index, health) in chunk_damage {
+            writer.write_all(&voxel_index.to_le_bytes())?;
+            writer.write_all(&health.to_le_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Read a [`Voxels`] grid written by [`save`].
+///
+/// Nothing is marked as changed, callers swapping the grid in are responsible
+/// for remeshing.
+pub fn load(reader: &mut impl Read) -> Result<Voxels, SaveError> {
+    let magic = read_array::<4>(reader)?;
+    if magic != SAVE_MAGIC {
+        return Err(SaveError::BadMagic(magic));
+    }
+
+    match u16::from_le_bytes(read_array(reader)?) {
+        1 => load_v1(reader),
+        2 => load_v2(reader),
+        version => Err(SaveError::UnsupportedVersion(version)),
+    }
+}
+
+pub fn save_file(voxels: &Voxels, path: impl AsRef<Path>) -> Result<(), SaveError> {
+    let mut writer = BufWriter::new(std::fs::File::create(path)?);
+    save(voxels, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn load_file(path: impl AsRef<Path>) -> Result<Voxels, SaveError> {
+    load(&mut BufReader::new(std::fs::File::open(path)?))
+}
+
+fn load_v1(reader: &mut impl Read) -> Result<Voxels, SaveError> {
+    let [root_layer] = read_array::<1>(reader)?;
+    load_tree(reader, root_layer as usize, IVec3::ZERO)
+}
+
+fn load_v2(reader: &mut impl Read) -> Result<Voxels, SaveError> {
+    let [root_layer] = read_array::<1>(reader)?;
+    let origin = read_ivec3(reader)?;
+    load_tree(reader, root_layer as usize, origin)
+}
+
+/// Everything after the root origin, shared by all versions so far.
+fn load_tree(
+    reader: &mut impl Read,
+    root_layer: usize,
+    origin: IVec3,
+) -> Result<Voxels, SaveError> {
+    if root_layer > MAX_ROOT_LAYER {
+        return Err(SaveError::InvalidRootLayer(root_layer));
+    }
+
+    let voxel_size = read_ivec3(reader)?;
+
+    let root = read_node(reader, root_layer)?;
+
+    let chunk_count = u32::from_le_bytes(read_array(reader)?);
+    let mut damage = HashMap::new();
+    for _ in 0..chunk_count {
+        let chunk_point = read_ivec3(reader)?;
+        let entry_count = u16::from_le_bytes(read_array(reader)?);
+        let mut chunk_damage = ChunkDamage::new();
+        for _ in 0..entry_count {
+            let voxel_index = u16::from_le_bytes(read_array(reader)?);
+            let health = i16::from_le_bytes(read_array(reader)?);
+            if voxel_index as usize >= CHUNK_LENGTH {
+                return Err(SaveError::InvalidDamage(voxel_index));
+            }
+            chunk_damage.insert(voxel_index, health);
+        }
+        damage.insert(chunk_point, chunk_damage);
+    }
+
+    let tree = VoxelTree {
+        root,
+        origin,
+        changed_chunks: HashSet::new(),
+        edited_chunks: HashSet::new(),
+        touched_chunks: HashSet::new(),
+        damage: Arc::new(damage),
+    };
+    Ok(Voxels { tree, voxel_size })
+}
+
+fn write_node(writer: &mut impl Write, node: &VoxelNode) -> Result<(), SaveError> {
+    let authority = if node.authority() { TAG_AUTHORITY } else { 0 };
+    match node {
+        VoxelNode::Solid { voxel, .. } => {
+            writer.write_all(&[TAG_SOLID | authority])?;
+            writer.write_all(&pack_voxel(*voxel).to_le_bytes())?;
+        },
+        VoxelNode::Children { children, .. } => {
+            writer.write_all(&[TAG_CHILDREN | authority])?;
+            for child in children.iter() {
+                write_node(writer, child)?;
+            }
+        },
+        VoxelNode::Leaf { leaf, .. } => {
+            writer.write_all(&[TAG_LEAF | authority])?;
+            let rle = RLEChunk::from_voxels(&leaf[..]);
+            writer.write_all(&(rle.runs_count() as u16).to_le_bytes())?;
+            for (voxel, run_count) in &rle.runs {
+                writer.write_all(&pack_voxel(*voxel).to_le_bytes())?;
+                writer.write_all(&run_count.to_le_bytes())?;
+            }
+        },
+    }
+
+    Ok(())
+}
+
+fn read_node(reader: &mut impl Read, layer: usize) -> Result<VoxelNode, SaveError> {
+    let [tag] = read_array::<1>(reader)?;
+    let shared = SharedData { layer, authority: tag & TAG_AUTHORITY != 0 };
+
+    match tag & !TAG_AUTHORITY {
+        TAG_SOLID => {
+            let voxel = unpack_voxel(u16::from_le_bytes(read_array(reader)?));
+            Ok(VoxelNode::Solid { shared, voxel })
+        },
+        TAG_CHILDREN if layer > 0 => {
+            let mut children = Vec::with_capacity(TREE_LENGTH);
+            for _ in 0..TREE_LENGTH {
+                children.push(read_node(reader, layer - 1)?);
+            }
+            let children: [VoxelNode; TREE_LENGTH] = children.try_into().unwrap();
+            let children = Arc::new(children);
+            Ok(VoxelNode::Children { shared, children })
+        },
+        TAG_LEAF if layer == 0 => {
+            let runs_count = u16::from_le_bytes(read_array(reader)?);
+            let mut rle = RLEChunk::new();
+            for _ in 0..runs_count {
+                let voxel = unpack_voxel(u16::from_le_bytes(read_array(reader)?));
+                let run_count = u16::from_le_bytes(read_array(reader)?);
+                rle.runs.push((voxel, run_count));
+            }
+
+            let mut leaf = Arc::new([Voxel::Air; CHUNK_LENGTH]);
+            if !rle.decode_into(&mut Arc::make_mut(&mut leaf)[..]) {
+                return Err(SaveError::InvalidLeaf(rle.voxel_count()));
+            }
+            Ok(VoxelNode::Leaf { shared, leaf })
+        },
+        _ => Err(SaveError::InvalidNode { tag, layer }),
+    }
+}
+
+fn write_ivec3(writer: &mut impl Write, point: IVec3) -> io::Result<()> {
+    for axis in point.to_array() {
+        writer.write_all(&axis.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_ivec3(reader: &mut impl Read) -> io::Result<IVec3> {
+    let x = i32::from_le_bytes(read_array(reader)?);
+    let y = i32::from_le_bytes(read_array(reader)?);
+    let z = i32::from_le_bytes(read_array(reader)?);
+    Ok(IVec3::new(x, y, z))
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::map::terrain::TerrainKind;
+    use crate::map::{Layers, TerrainParams};
+    use crate::voxel::VoxelAabb;
+    use crate::voxel::simulation::kinds::liquid::LiquidState;
+
+    fn hilly_map() -> Voxels {
+        let mut voxels = Voxels::new(IVec3::splat(1000));
+        let terrain = TerrainParams {
+            aabb: VoxelAabb { min: IVec3::ZERO, max: IVec3::new(64, 32, 64) },
+            kind: TerrainKind::Hilly,
+            layers: Layers {
+                layers: vec![(0.0, Voxel::Stone), (0.6, Voxel::Dirt), (0.9, Voxel::Grass)],
+            },
+        };
+        terrain.apply(&mut voxels).unwrap();
+        voxels.tree.compress();
+        voxels
+    }
+
+    #[test]
+    pub fn round_trip() {
+        let mut voxels = hilly_map();
+        // liquid state bits and damage should survive too
+        voxels.set_voxel(IVec3::new(5, 31, 5), Voxel::Water(default()));
+        voxels.set_voxel(IVec3::new(6, 31, 5), Voxel::Oil(LiquidState::from_bits(0b1011_0101)));
+        voxels.tree.damage_voxel(IVec3::new(5, 1, 5), 1);
+        // grown towards negative coordinates
+        voxels.set_voxel(IVec3::new(-3, 2, -40), Voxel::Stone);
+
+        let mut bytes = Vec::new();
+        save(&voxels, &mut bytes).unwrap();
+        let loaded = load(&mut bytes.as_slice()).unwrap();
+
+        assert_eq!(loaded.voxel_size, voxels.voxel_size);
+        assert_eq!(loaded.tree.origin, voxels.tree.origin);
+        assert!(loaded.tree.root == voxels.tree.root, "loaded tree differs");
+        assert_eq!(loaded.tree.damage, voxels.tree.damage);
+        assert_eq!(
+            loaded.get_voxel(IVec3::new(6, 31, 5)),
+            Voxel::Oil(LiquidState::from_bits(0b1011_0101))
+        );
+    }
+
+    #[test]
+    pub fn loads_v1() {
+        let voxels = hilly_map();
+        let mut bytes = Vec::new();
+        save(&voxels, &mut bytes).unwrap();
+
+        // v1 is v2 without the root origin
+        bytes[4..6].copy_from_slice(&1u16.to_le_bytes());
+        bytes.drain(7..19);
+        let loaded = load(&mut bytes.as_slice()).unwrap();
+
+        assert_eq!(loaded.tree.origin, IVec3::ZERO);
+        assert_eq!(loaded.voxel_size, voxels.voxel_size);
+        assert!(loaded.tree.root == voxels.tree.root, "loaded tree differs");
+    }
+
+    #[test]
+    pub fn rejects_bad_saves() {
+        let voxels = hilly_map();
+        let mut bytes = Vec::new();
+        save(&voxels, &mut bytes).unwrap();
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert!(matches!(load(&mut bad_magic.as_slice()), Err(SaveError::BadMagic(..))));
+
+        let mut future = bytes.clone();
+        future[4..6].copy_from_slice(&(SAVE_VERSION + 1).to_le_bytes());
+        assert!(matches!(load(&mut future.as_slice()), Err(SaveError::UnsupportedVersion(..))));
+
+        let truncated = &bytes[..bytes.len() / 2];
+        assert!(matches!(load(&mut &truncated[..]), Err(SaveError::Io(..))));
+
+        let mut too_tall = bytes.clone();
+        too_tall[6] = MAX_ROOT_LAYER as u8 + 1;
+        assert!(matches!(load(&mut too_tall.as_slice()), Err(SaveError::InvalidRootLayer(..))));
+
+        // the damage entries are last, `(u16 leaf index, i16 health)`
+        let mut voxels = voxels;
+        voxels.tree.damage_voxel(IVec3::new(5, 1, 5), 1);
+        let mut bad_damage = Vec::new();
+        save(&voxels, &mut bad_damage).unwrap();
+        let index = bad_damage.len() - 4;
+        bad_damage[index..index + 2].copy_from_slice(&(CHUNK_LENGTH as u16).to_le_bytes());
+        assert!(matches!(load(&mut bad_damage.as_slice()), Err(SaveError::InvalidDamage(..))));
+    }
+}
@@ -0,0 +1,319 @@
+//! Rewinding the simulation to earlier ticks.
+//!
+//! Every grid keeps a ring buffer of [`Checkpoint`]s, one from the start of
+//! each tick. Chunks are stored run length encoded and shared with the previous
+//! checkpoint when the tick before didn't change them, so a checkpoint only
+//! costs as much as the chunks that were modified.
+//!
+//! Only simulated chunks are restored, edits to the tree outside of the sim
+//! stay where they are. Rewinds aren't part of [recordings](super::replay).
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+
+use crate::voxel::simulation::data::{CHUNK_LENGTH, ChunkPoint, SimChunk, SimChunks};
+use crate::voxel::simulation::heat::HEAT_LENGTH;
+use crate::voxel::simulation::rle::RLEChunk;
+use crate::voxel::simulation::{FallingSandTick, SimRun, SimSettings};
+use crate::voxel::{Voxel, VoxelTree, Voxels};
+
+pub fn plugin(app: &mut App) {
+    app.register_type::<SimRewind>();
+}
+
+/// Rewind the simulation to a [`Checkpoint`], see [`SimSettings::rewind`].
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Reflect)]
+#[reflect(Default, Clone, Debug)]
+pub enum SimRewind {
+    #[default]
+    None,
+    /// Undo the last N ticks.
+    Ticks(u32),
+    /// Jump back to the start of this tick.
+    ToTick(u32),
+}
+
+/// Compressed copy of a simulated chunk.
+#[derive(Debug, Clone)]
+pub struct ChunkCheckpoint {
+    pub voxels: RLEChunk,
+    pub temperature: [i16; HEAT_LENGTH],
+}
+
+impl ChunkCheckpoint {
+    pub fn new(sim_chunk: &SimChunk) -> Self {
+        Self { voxels: RLEChunk::from_sim(sim_chunk), temperature: sim_chunk.heat.temperature }
+    }
+
+    pub fn decode(&self) -> [Voxel; CHUNK_LENGTH] {
+        let mut voxels = [Voxel::Air; CHUNK_LENGTH];
+        self.voxels.decode_into(&mut voxels);
+        voxels
+    }
+
+    /// Whether `sim_chunk` is still in this state.
+    pub fn matches(&self, sim_chunk: &SimChunk) -> bool {
+        self.temperature == sim_chunk.heat.temperature && self.decode() == sim_chunk.voxels
+    }
+}
+
+/// Simulated chunks at the start of a tick.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    /// [`FallingSandTick`] before the tick ran.
+    pub tick: u32,
+    pub margolus_offset: usize,
+    pub chunks: HashMap<IVec3, Arc<ChunkCheckpoint>>,
+}
+
+/// Ring buffer of [`Checkpoint`]s for a grid, oldest first.
+#[derive(Component, Debug, Default, Clone)]
+pub struct SimCheckpoints {
+    pub checkpoints: VecDeque<Checkpoint>,
+}
+
+impl SimCheckpoints {
+    /// Checkpoint `sim_chunks` before `tick` runs, keeping at most `capacity`
+    /// checkpoints.
+    ///
+    /// Chunks the last tick didn't modify are shared with the previous
+    /// checkpoint.
+    pub fn push(&mut self, tick: FallingSandTick, sim_chunks: &SimChunks, capacity: usize) {
+        let previous = self.checkpoints.back();
+        let chunks = sim_chunks
+            .chunks
+            .values()
+            .map(|sim_chunk| {
+                let chunk_point = sim_chunk.chunk_point.0;
+                let unchanged = previous
+                    .and_then(|previous| previous.chunks.get(&chunk_point))
+                    .filter(|previous| {
+                        !sim_chunk.modified.any_set()
+                            && previous.temperature == sim_chunk.heat.temperature
+                    });
+
+                let chunk = match unchanged {
+                    Some(previous) => previous.clone(),
+                    None => Arc::new(ChunkCheckpoint::new(sim_chunk)),
+                };
+                (chunk_point, chunk)
+            })
+            .collect();
+
+        self.checkpoints.push_back(Checkpoint {
+            tick: tick.0,
+            margolus_offset: sim_chunks.margolus_offset,
+            chunks,
+        });
+
+        while self.checkpoints.len() > capacity {
+            self.checkpoints.pop_front();
+        }
+    }
+
+    /// Index of the checkpoint to restore for `rewind`.
+    pub fn find(&self, rewind: SimRewind) -> Option<usize> {
+        match rewind {
+            SimRewind::None => None,
+            SimRewind::Ticks(ticks) => {
+                let newest = self.checkpoints.len().checked_sub(1)?;
+                Some(newest.saturating_sub(ticks.max(1) as usize - 1))
+            },
+            SimRewind::ToTick(tick) => {
+                self.checkpoints.iter().position(|checkpoint| checkpoint.tick == tick)
+            },
+        }
+    }
+
+    /// Restore `sim_chunks` and the matching `tree` chunks to the checkpoint at
+    /// `index`, dropping the checkpoints after it.
+    ///
+    /// Chunks pulled into the sim after the checkpoint are removed from it and
+    /// put back into the tree the way they were when they were pulled in.
+    pub fn restore(
+        &mut self,
+        index: usize,
+        sim_chunks: &mut SimChunks,
+        tree: &mut VoxelTree,
+    ) -> FallingSandTick {
+        let newer = self.checkpoints.split_off(index + 1);
+        let checkpoint = &self.checkpoints[index];
+
+        let active = sim_chunks.from_chunk_point.keys().copied().collect::<Vec<_>>();
+        for chunk_point in active {
+            if checkpoint.chunks.contains_key(&chunk_point.0) {
+                continue;
+            }
+
+            sim_chunks.remove_chunk(chunk_point);
+            let pulled = newer.iter().find_map(|newer| newer.chunks.get(&chunk_point.0));
+            if let Some(pulled) = pulled {
+                tree.set_chunk_data(chunk_point.0, pulled.decode());
+            }
+        }
+
+        for (chunk_point, chunk) in &checkpoint.chunks {
+            let chunk_point = ChunkPoint(*chunk_point);
+            let current = sim_chunks
+                .chunk_key_from_point(chunk_point)
+                .map(|(chunk_key, _)| &sim_chunks.chunks[chunk_key]);
+            if current.is_some_and(|current| chunk.matches(current)) {
+                continue;
+            }
+
+            // re-adding wakes the whole chunk up
+            let voxels = chunk.decode();
+            sim_chunks.remove_chunk(chunk_point);
+            sim_chunks.add_chunk(chunk_point, voxels);
+            let (chunk_key, _) = sim_chunks.chunk_key_from_point(chunk_point).unwrap();
+            sim_chunks.chunks[chunk_key].heat.temperature = chunk.temperature;
+            tree.set_chunk_data(*chunk_point, voxels);
+        }
+
+        sim_chunks.margolus_offset = checkpoint.margolus_offset;
+        sim_chunks.pending_activation.clear();
+        FallingSandTick(checkpoint.tick)
+    }
+}
+
+/// Checkpoint every grid before the tick runs, see
+/// [`SimSettings::checkpoints`].
+pub fn save_checkpoints(
+    mut commands: Commands,
+    settings: Res<SimSettings>,
+    tick: Res<FallingSandTick>,
+    mut grids: Query<(Entity, &SimChunks, Option<&mut SimCheckpoints>)>,
+) {
+    for (grid_entity, sim_chunks, checkpoints) in &mut grids {
+        match checkpoints {
+            Some(mut checkpoints) => {
+                checkpoints.push(*tick, sim_chunks, settings.checkpoints);
+            },
+            None if settings.checkpoints > 0 => {
+                let mut checkpoints = SimCheckpoints::default();
+                checkpoints.push(*tick, sim_chunks, settings.checkpoints);
+                commands.entity(grid_entity).insert(checkpoints);
+            },
+            None => {},
+        }
+    }
+}
+
+/// Apply [`SimSettings::rewind`], pausing a continuously running sim so it can
+/// be stepped from the checkpoint.
+pub fn rewind(
+    mut settings: ResMut<SimSettings>,
+    mut tick: ResMut<FallingSandTick>,
+    mut grids: Query<(&mut Voxels, &mut SimChunks, &mut SimCheckpoints)>,
+) {
+    if settings.rewind == SimRewind::None {
+        return;
+    }
+
+    let rewind = std::mem::take(&mut settings.rewind);
+    for (mut voxels, mut sim_chunks, mut checkpoints) in &mut grids {
+        let Some(index) = checkpoints.find(rewind) else {
+            warn!("no checkpoint to {:?}", rewind);
+            continue;
+        };
+
+        *tick = checkpoints.restore(index, &mut sim_chunks, &mut voxels.tree);
+    }
+
+    if settings.step == SimRun::Continuous {
+        settings.step = SimRun::Step;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::voxel::VoxelAabb;
+    use crate::voxel::simulation::replay::{RecordingLog, ReplayRun, SimRecording, SimReplay};
+
+    fn replay() -> SimReplay {
+        let mut grid = Voxels::new(IVec3::splat(32));
+        grid.fill_aabb(VoxelAabb::new(IVec3::ZERO, IVec3::new(31, 0, 31)), Voxel::Base);
+        grid.fill_aabb(VoxelAabb::new(IVec3::new(4, 18, 4), IVec3::new(8, 24, 8)), Voxel::Sand);
+
+        let mut replay = SimReplay::new(&SimRecording {
+            grid,
+            log: RecordingLog {
+                // the sand falls into the chunk below
+                active_chunks: vec![IVec3::Y],
+                sleep_after: 128,
+                ..default()
+            },
+        });
+        replay.app.world_mut().resource_mut::<SimSettings>().checkpoints = 16;
+        replay
+    }
+
+    fn rewind(replay: &mut SimReplay, rewind: SimRewind) {
+        replay.app.world_mut().resource_mut::<SimSettings>().rewind = rewind;
+        replay.app.world_mut().run_schedule(ReplayRun);
+    }
+
+    #[test]
+    pub fn rewind_ticks() {
+        let mut replay = replay();
+        let mut hashes = vec![replay.hash()];
+        let mut trees = vec![replay.voxels().tree.snapshot()];
+        for _ in 0..20 {
+            hashes.push(replay.step());
+            trees.push(replay.voxels().tree.snapshot());
+        }
+
+        // undoing 5 ticks goes back to the end of the 15th
+        rewind(&mut replay, SimRewind::Ticks(5));
+        assert_eq!(replay.hash(), hashes[15]);
+        assert!(crate::voxel::diff::diff(&trees[15], &replay.voxels().tree).is_empty());
+
+        let settings = replay.app.world().resource::<SimSettings>();
+        assert_eq!(settings.rewind, SimRewind::None);
+        assert_eq!(settings.step, SimRun::Step);
+
+        // running again ends up in the same place
+        replay.app.world_mut().resource_mut::<SimSettings>().step = SimRun::Continuous;
+        for tick in 16..=20 {
+            assert_eq!(replay.step(), hashes[tick]);
+        }
+    }
+
+    #[test]
+    pub fn rewind_before_pull() {
+        let mut replay = replay();
+        let start = replay.hash();
+        let tree = replay.voxels().tree.snapshot();
+        for _ in 0..12 {
+            replay.step();
+        }
+        assert!(replay.hash().chunks.iter().any(|(chunk_point, _)| *chunk_point == IVec3::ZERO));
+
+        rewind(&mut replay, SimRewind::ToTick(start.tick));
+        assert_eq!(replay.hash(), start);
+        assert!(crate::voxel::diff::diff(&tree, &replay.voxels().tree).is_empty());
+    }
+
+    #[test]
+    pub fn unchanged_chunks_are_shared() {
+        let mut replay = replay();
+        for _ in 0..60 {
+            replay.step();
+        }
+
+        let grid_entity = replay.grid_entity;
+        let checkpoints = replay.app.world().get::<SimCheckpoints>(grid_entity).unwrap();
+        assert_eq!(checkpoints.checkpoints.len(), 16);
+
+        // the sand has settled by now
+        let mut newest = checkpoints.checkpoints.iter().rev();
+        let (newest, previous) = (newest.next().unwrap(), newest.next().unwrap());
+        for (chunk_point, chunk) in &newest.chunks {
+            assert!(Arc::ptr_eq(chunk, &previous.chunks[chunk_point]));
+        }
+    }
+}
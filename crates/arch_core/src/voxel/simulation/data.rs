@@ -1,8 +1,8 @@
 use std::hash::{Hash, Hasher};
 use std::sync::{Arc, Mutex};
 
-use bevy::platform::collections::HashMap;
 use bevy::platform::collections::hash_map::Entry;
+use bevy::platform::collections::{HashMap, HashSet};
 use bevy::prelude::*;
 use bevy_math::bounding::Aabb3d;
 use slotmap::SlotMap;
@@ -11,11 +11,13 @@ use tracing::*;
 
 use crate::sdf::Sdf;
 use crate::sdf::voxel_rasterize::PointIter;
-use crate::voxel::Voxel;
-use crate::voxel::simulation::FallingSandTick;
+use crate::voxel::simulation::heat::HeatField;
 use crate::voxel::simulation::kinds::VoxelPosition;
+use crate::voxel::simulation::kinds::liquid::LiquidMode;
 use crate::voxel::simulation::set::ChunkSet;
+use crate::voxel::simulation::{FallingSandTick, reactions};
 use crate::voxel::voxel::VoxelSet;
+use crate::voxel::{Voxel, VoxelAabb, VoxelNode, VoxelTree};
 
 pub const CHUNK_WIDTH_BITSHIFT: usize = 4;
 pub const CHUNK_WIDTH_BITSHIFT_Y: usize = CHUNK_WIDTH_BITSHIFT * 2;
@@ -42,6 +44,13 @@ pub struct SimChunk {
     pub modified: ChunkSet,
 
     pub voxels: [Voxel; CHUNK_LENGTH],
+
+    /// Coarse temperature of the chunk.
+    pub heat: HeatField,
+
+    /// Ticks in a row nothing has happened in this chunk, see
+    /// [`SimChunks::tick_quiet`].
+    pub quiet_ticks: u32,
 }
 
 impl SimChunk {
@@ -50,7 +59,14 @@ impl SimChunk {
     }
 
     pub fn fill(chunk_point: ChunkPoint, voxel: Voxel) -> Self {
-        Self { chunk_point, modified: ChunkSet::empty(), voxels: [voxel; CHUNK_LENGTH] }
+        let voxels = [voxel; CHUNK_LENGTH];
+        Self {
+            chunk_point,
+            modified: ChunkSet::empty(),
+            heat: HeatField::from_voxels(&voxels),
+            voxels,
+            quiet_ticks: 0,
+        }
     }
 
     pub fn set(&mut self, voxel_index: usize, voxel: Voxel) {
@@ -62,6 +78,7 @@ impl SimChunk {
 
         if current_voxel != voxel {
             self.modified.set(voxel_index);
+            self.heat.replace(voxel_index, current_voxel, voxel);
 
             if cfg!(feature = "safe-bounds") {
                 self.voxels[voxel_index] = voxel;
@@ -72,6 +89,11 @@ impl SimChunk {
             }
         }
     }
+
+    /// Hash of the voxels and temperature of this chunk, stable across runs.
+    pub fn state_hash(&self) -> u64 {
+        fxhash::hash64(&(&self.voxels[..], &self.heat.temperature[..]))
+    }
 }
 
 slotmap::new_key_type! { pub struct ChunkKey; }
@@ -103,6 +125,14 @@ pub struct SimChunks {
 
     #[reflect(ignore)]
     pub spread_list: Arc<Mutex<SpreadList>>,
+
+    /// Chunks to pull from the tree next time the sim does, see
+    /// [`SimChunks::activate_aabb`].
+    #[reflect(ignore)]
+    pub pending_activation: HashSet<ChunkPoint>,
+
+    /// How liquids in this grid flow.
+    pub liquid_mode: LiquidMode,
 }
 
 #[derive(Default)]
@@ -219,6 +249,8 @@ impl SimChunks {
             blocks: std::array::from_fn(|_| SlotMap::with_key()),
             margolus_offset: 0,
             spread_list: Arc::new(Mutex::new(SpreadList::new())),
+            pending_activation: HashSet::new(),
+            liquid_mode: LiquidMode::default(),
         }
     }
 
@@ -261,8 +293,13 @@ impl SimChunks {
                 existing_chunk.set(index, voxel);
             }
         } else {
-            let chunk_key =
-                self.chunks.insert(SimChunk { chunk_point, modified: ChunkSet::filled(), voxels });
+            let chunk_key = self.chunks.insert(SimChunk {
+                chunk_point,
+                modified: ChunkSet::filled(),
+                heat: HeatField::from_voxels(&voxels),
+                voxels,
+                quiet_ticks: 0,
+            });
             let dirty_key = self.dirty.insert(ChunkSet::filled()); // this doesn't really matter, it'll get overwritten later
             self.from_chunk_point.insert(chunk_point, (chunk_key, dirty_key));
 
@@ -293,7 +330,143 @@ impl SimChunks {
         }
     }
 
-    pub fn remove_chunk(&mut self, chunk_point: ChunkPoint) {}
+    /// Remove a chunk from the simulation, returning it if it was there.
+    pub fn remove_chunk(&mut self, chunk_point: ChunkPoint) -> Option<SimChunk> {
+        let (chunk_key, dirty_key) = self.from_chunk_point.remove(&chunk_point)?;
+        self.dirty.remove(dirty_key);
+        self.spread_list.lock().unwrap().spread_list.remove(&chunk_point.0);
+
+        for offset_index in 0..8 {
+            let offset = MARGOLUS_OFFSETS[offset_index];
+            let corner = ((*chunk_point + offset) / 2) * 2 - offset;
+            let chunk_index = ChunkView::linearize_chunk(*chunk_point - corner);
+
+            let Some(block_key) =
+                self.to_block_index[offset_index].get(&ChunkPoint(corner)).copied()
+            else {
+                continue;
+            };
+
+            let block = self.blocks[offset_index].get_mut(block_key).unwrap();
+            block.keys[chunk_index] = None;
+            if block.keys.iter().all(Option::is_none) {
+                self.blocks[offset_index].remove(block_key);
+                self.to_block_index[offset_index].remove(&ChunkPoint(corner));
+            }
+        }
+
+        self.chunks.remove(chunk_key)
+    }
+
+    /// Queue the chunks overlapping `aabb` to be pulled from the tree, see
+    /// [`SimChunks::pull_chunk`].
+    pub fn activate_aabb(&mut self, aabb: VoxelAabb) {
+        let min = chunk_point(aabb.min).0;
+        let max = chunk_point(aabb.max).0;
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                for z in min.z..=max.z {
+                    self.pending_activation.insert(ChunkPoint(IVec3::new(x, y, z)));
+                }
+            }
+        }
+    }
+
+    /// Copy a chunk from the tree into the simulation, chunks that are already
+    /// simulated are only kept awake.
+    ///
+    /// Returns false if the tree doesn't have the chunk.
+    pub fn pull_chunk(&mut self, tree: &VoxelTree, chunk_point: ChunkPoint) -> bool {
+        if let Some((chunk_key, _)) = self.chunk_key_from_point(chunk_point) {
+            self.chunks[chunk_key].quiet_ticks = 0;
+            return true;
+        }
+
+        let voxels = match tree.get_chunk(*chunk_point) {
+            Some(VoxelNode::Solid { voxel, .. }) => [*voxel; CHUNK_LENGTH],
+            Some(VoxelNode::Leaf { leaf, .. }) => **leaf,
+            _ => return false,
+        };
+
+        self.add_chunk(chunk_point, voxels);
+        true
+    }
+
+    /// Chunks next to simulated voxels on the edges of dirty chunks, these
+    /// need to be simulated for the voxels to move into them.
+    pub fn touched_chunks(&self) -> HashSet<ChunkPoint> {
+        let last = CHUNK_WIDTH as i32 - 1;
+        let mut touched = HashSet::new();
+        for (chunk_point, (chunk_key, dirty_key)) in &self.from_chunk_point {
+            let dirty = &self.dirty[*dirty_key];
+            if !dirty.any_set() {
+                continue;
+            }
+
+            let chunk = &self.chunks[*chunk_key];
+            for voxel_index in dirty.iter() {
+                let point = delinearize(voxel_index);
+                if point.min_element() > 0 && point.max_element() < last {
+                    continue;
+                }
+
+                if !chunk.voxels[voxel_index].is_simulated() {
+                    continue;
+                }
+
+                // faces, edges and corners this voxel sits on
+                let steps = |axis: i32| match axis {
+                    0 => -1..=0,
+                    axis if axis == last => 0..=1,
+                    _ => 0..=0,
+                };
+                for y in steps(point.y) {
+                    for x in steps(point.x) {
+                        for z in steps(point.z) {
+                            let offset = IVec3::new(x, y, z);
+                            if offset != IVec3::ZERO {
+                                touched.insert(ChunkPoint(chunk_point.0 + offset));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        touched
+    }
+
+    /// Count quiet ticks for every chunk, returning the chunks that have been
+    /// quiet for at least `sleep_after` ticks.
+    pub fn tick_quiet(&mut self, sleep_after: u32) -> Vec<ChunkPoint> {
+        let mut sleeping = Vec::new();
+        for (chunk_point, (chunk_key, dirty_key)) in &self.from_chunk_point {
+            let chunk = &mut self.chunks[*chunk_key];
+            if chunk.modified.any_set() || self.dirty[*dirty_key].any_set() {
+                chunk.quiet_ticks = 0;
+                continue;
+            }
+
+            chunk.quiet_ticks = chunk.quiet_ticks.saturating_add(1);
+            if chunk.quiet_ticks >= sleep_after {
+                sleeping.push(*chunk_point);
+            }
+        }
+        sleeping
+    }
+
+    /// [`SimChunk::state_hash`] of every simulated chunk, sorted by chunk
+    /// point.
+    pub fn chunk_hashes(&self) -> Vec<(IVec3, u64)> {
+        let mut hashes = self
+            .from_chunk_point
+            .iter()
+            .map(|(chunk_point, (chunk_key, _))| {
+                (chunk_point.0, self.chunks[*chunk_key].state_hash())
+            })
+            .collect::<Vec<_>>();
+        hashes.sort_by_key(|(chunk_point, _)| chunk_point.to_array());
+        hashes
+    }
 
     #[inline]
     pub fn chunk_key_from_point(&self, chunk_point: ChunkPoint) -> Option<(ChunkKey, DirtyKey)> {
@@ -377,6 +550,7 @@ impl SimChunks {
                 start_chunk_point: keys.start_chunk_point,
                 chunks: ChunkView { chunks: std::array::from_fn(|_| None) },
                 dirty_sets: std::array::from_fn(|_| None),
+                liquid_mode: self.liquid_mode,
             })
             .collect::<Vec<_>>();
 
@@ -551,6 +725,21 @@ impl SimChunks {
 
         spread_list.spread_list.clear();
     }
+
+    /// Run a single simulation step on this thread, same ordering as the
+    /// `spread_updates` and `simulate` systems.
+    pub fn step(&mut self, tick: FallingSandTick) {
+        self.spread_updates();
+        self.margolus_offset = (self.margolus_offset + 1) % 8;
+
+        let spread_list = self.spread_list.clone();
+        for mut block_view in self.chunk_views() {
+            block_view.simulate(spread_list.clone(), tick);
+        }
+
+        self.update_heat();
+        self.level_liquids();
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -565,6 +754,7 @@ pub struct BlockView<'a> {
     // chunk, dirty set
     chunks: ChunkView<'a>,
     dirty_sets: [Option<&'a ChunkSet>; CHUNK_VIEW_LENGTH],
+    liquid_mode: LiquidMode,
 }
 
 impl<'a> BlockView<'a> {
@@ -616,12 +806,23 @@ impl<'a> BlockView<'a> {
                     voxel_data
                 };
 
+                let position = VoxelPosition::from_indices(chunk_index, voxel_index);
+                if reactions::react(&mut self.chunks, self.start_chunk_point, position, voxel, tick)
+                {
+                    continue;
+                }
+
                 if !voxel.is_simulated() {
                     continue;
                 }
 
-                let position = VoxelPosition::from_indices(chunk_index, voxel_index);
-                voxel.simulate(&mut self.chunks, position, tick);
+                voxel.simulate(
+                    &mut self.chunks,
+                    self.start_chunk_point,
+                    position,
+                    tick,
+                    self.liquid_mode,
+                );
             }
 
             if self.chunks.chunks[chunk_index].as_ref().unwrap().modified.any_set()
@@ -717,6 +918,21 @@ impl<'a> ChunkView<'a> {
         }
     }
 
+    /// Keep this voxel dirty for the next tick without changing it.
+    pub fn mark_modified(&mut self, voxel_position: VoxelPosition) {
+        if let Some(chunk) = &mut self.chunks[voxel_position.chunk_index] {
+            chunk.modified.set(voxel_position.voxel_index);
+        }
+    }
+
+    /// Has this voxel already been changed this tick.
+    pub fn is_modified(&self, voxel_position: VoxelPosition) -> bool {
+        match &self.chunks[voxel_position.chunk_index] {
+            Some(chunk) => chunk.modified.get(voxel_position.voxel_index),
+            None => false,
+        }
+    }
+
     pub fn get_relative_voxel(
         &self,
         voxel_position: VoxelPosition,
@@ -862,6 +1078,56 @@ mod tests {
     //     // assert_eq!(updates.next(), None);
     // }
 
+    #[test]
+    fn remove_chunk() {
+        let mut sim = SimChunks::new();
+        sim.add_chunk(ChunkPoint(ivec3(0, 0, 0)), [Voxel::Dirt; CHUNK_LENGTH]);
+        sim.add_chunk(ChunkPoint(ivec3(1, 0, 0)), [Voxel::Air; CHUNK_LENGTH]);
+
+        let removed = sim.remove_chunk(ChunkPoint(ivec3(0, 0, 0))).unwrap();
+        assert_eq!(removed.voxels, [Voxel::Dirt; CHUNK_LENGTH]);
+        assert_eq!(sim.get_voxel(ivec3(0, 0, 0)), None);
+        assert_eq!(sim.get_voxel(ivec3(16, 0, 0)), Some(Voxel::Air));
+        assert!(sim.remove_chunk(ChunkPoint(ivec3(0, 0, 0))).is_none());
+        assert_eq!(sim.chunk_views().len(), 1);
+
+        sim.remove_chunk(ChunkPoint(ivec3(1, 0, 0)));
+        assert!(sim.chunks.is_empty() && sim.dirty.is_empty());
+        assert!(sim.blocks.iter().all(|blocks| blocks.is_empty()));
+        assert!(sim.to_block_index.iter().all(|index| index.is_empty()));
+    }
+
+    #[test]
+    fn activate_and_sleep() {
+        let mut tree = VoxelTree::new();
+        tree.grow_n_layers(1);
+        tree.fill_aabb(VoxelAabb::new(ivec3(0, 0, 0), ivec3(31, 15, 31)), Voxel::Stone);
+        // sand on the floor of the chunk above
+        tree.set_voxel(ivec3(4, 16, 15), Voxel::Sand);
+
+        let mut sim = SimChunks::new();
+        sim.activate_aabb(VoxelAabb::new(ivec3(4, 16, 4), ivec3(4, 16, 4)));
+        for chunk_point in std::mem::take(&mut sim.pending_activation) {
+            assert!(sim.pull_chunk(&tree, chunk_point));
+        }
+        assert_eq!(sim.get_voxel(ivec3(4, 16, 15)), Some(Voxel::Sand));
+
+        // the sand touches the chunks below and beside it
+        let touched = sim.touched_chunks();
+        assert!(touched.contains(&ChunkPoint(ivec3(0, 0, 0))));
+        assert!(touched.contains(&ChunkPoint(ivec3(0, 1, 1))));
+        assert!(touched.contains(&ChunkPoint(ivec3(0, 0, 1))));
+        assert!(!touched.contains(&ChunkPoint(ivec3(1, 1, 0))));
+
+        // nothing is out of the tree
+        assert!(!sim.pull_chunk(&tree, ChunkPoint(ivec3(-1, 0, 0))));
+
+        sim.dirty.values_mut().for_each(ChunkSet::clear);
+        sim.chunks.values_mut().for_each(|chunk| chunk.modified.clear());
+        assert!(sim.tick_quiet(2).is_empty());
+        assert_eq!(sim.tick_quiet(2), vec![ChunkPoint(ivec3(0, 1, 0))]);
+    }
+
     #[test]
     fn test_shift() {
         // reference so i stop fucking up directions of bitshifts lmao
@@ -3,10 +3,14 @@ use bevy::prelude::*;
 use crate::voxel::GRID_SCALE;
 use crate::voxel::simulation::SimSettings;
 use crate::voxel::simulation::data::{CHUNK_WIDTH, SimChunks, delinearize};
+use crate::voxel::simulation::heat::{
+    AMBIENT_TEMPERATURE, HEAT_CELL_WIDTH, HEAT_LENGTH, HeatField,
+};
 
 pub fn plugin(app: &mut App) {
     app.add_systems(Update, display_cell_grids.run_if(resource_exists::<GizmoConfigStore>));
     app.add_systems(Update, display_dirty.run_if(resource_exists::<GizmoConfigStore>));
+    app.add_systems(Update, display_heat.run_if(resource_exists::<GizmoConfigStore>));
     // app.add_systems(Update, display_margolus_offset);
 }
 
@@ -84,6 +88,44 @@ pub fn display_dirty(sims: Query<(&SimChunks,)>, mut gizmos: Gizmos, settings: R
     }
 }
 
+pub fn display_heat(sims: Query<(&SimChunks,)>, mut gizmos: Gizmos, settings: Res<SimSettings>) {
+    if !settings.display_heat {
+        return;
+    }
+
+    for (chunks,) in sims {
+        for chunk in chunks.chunks.values() {
+            for cell in 0..HEAT_LENGTH {
+                let temperature = chunk.heat.temperature[cell];
+                if temperature == AMBIENT_TEMPERATURE {
+                    continue;
+                }
+
+                let cell_start = chunk.chunk_point.0 * IVec3::splat(CHUNK_WIDTH as i32)
+                    + HeatField::delinearize_cell(cell) * HEAT_CELL_WIDTH as i32;
+                let cell_scale = GRID_SCALE * HEAT_CELL_WIDTH as f32;
+
+                // blue when cold, red when hot
+                let heat = ((temperature - AMBIENT_TEMPERATURE) as f32 / 500.0).clamp(-1.0, 1.0);
+                let color = if heat > 0.0 {
+                    Color::srgba(1.0, 1.0 - heat, 0.0, 0.2 + heat * 0.3)
+                } else {
+                    Color::srgba(0.0, 1.0 + heat, 1.0, 0.2 - heat * 0.3)
+                };
+
+                gizmos.cuboid(
+                    Transform {
+                        scale: cell_scale,
+                        translation: cell_start.as_vec3() * GRID_SCALE + cell_scale / 2.0,
+                        ..default()
+                    },
+                    color,
+                );
+            }
+        }
+    }
+}
+
 pub fn display_margolus_offset(mut gizmos: Gizmos, chunks: Query<&SimChunks>) {
     for chunk in chunks {
         let offset = crate::voxel::simulation::data::MARGOLUS_OFFSETS[chunk.margolus_offset];
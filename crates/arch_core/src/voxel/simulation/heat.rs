@@ -0,0 +1,399 @@
+//! Coarse heat field for the falling sands sim.
+//!
+//! Each [`SimChunk`] keeps a [`HeatField`] of 4x4x4 voxel cells. Cells with
+//! heat sources (voxels with an [`Interactions::temperature`]) are held at the
+//! sources' average temperature, heat diffuses between neighbouring cells
+//! (across chunks too) and cells without sources drift back to
+//! [`AMBIENT_TEMPERATURE`]. Voxels in cells that changed temperature get their
+//! [`Interactions::heated`]/[`Interactions::cooled`] phase changes applied.
+//!
+//! [`Interactions::temperature`]: crate::voxel::voxel::Interactions::temperature
+//! [`Interactions::heated`]: crate::voxel::voxel::Interactions::heated
+//! [`Interactions::cooled`]: crate::voxel::voxel::Interactions::cooled
+
+use bevy::prelude::*;
+#[cfg(feature = "trace")]
+use tracing::*;
+
+use crate::voxel::Voxel;
+use crate::voxel::simulation::data::{
+    CHUNK_LENGTH, CHUNK_WIDTH, ChunkPoint, SimChunk, SimChunks, delinearize, linearize,
+};
+
+/// Width of a heat cell in voxels.
+pub const HEAT_CELL_WIDTH: usize = 4;
+/// Heat cells along each axis of a chunk.
+pub const HEAT_CELLS: usize = CHUNK_WIDTH / HEAT_CELL_WIDTH;
+pub const HEAT_LENGTH: usize = HEAT_CELLS * HEAT_CELLS * HEAT_CELLS;
+
+/// Temperature everything settles back to.
+pub const AMBIENT_TEMPERATURE: i16 = 20;
+/// Fraction of the difference to each neighbouring cell exchanged per tick,
+/// must stay above 6 for the diffusion to be stable.
+pub const DIFFUSION_DIVISOR: i32 = 8;
+/// Fraction of the difference to ambient lost per tick.
+pub const AMBIENT_DIVISOR: i32 = 64;
+/// Cells with heat sources only exchange this fraction of the usual heat with
+/// their neighbours, so a lone lava voxel doesn't instantly cool off.
+pub const SOURCE_INERTIA: i32 = 4;
+
+const HEAT_NEIGHBORS: [IVec3; 6] =
+    [IVec3::Y, IVec3::NEG_Y, IVec3::X, IVec3::NEG_X, IVec3::Z, IVec3::NEG_Z];
+
+/// Temperatures of the 4x4x4 voxel cells in a chunk, and the heat sources
+/// in them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Reflect)]
+pub struct HeatField {
+    pub temperature: [i16; HEAT_LENGTH],
+    /// Sum of the temperatures of the heat sources in each cell.
+    source_sum: [i32; HEAT_LENGTH],
+    /// Number of heat sources in each cell.
+    source_count: [u8; HEAT_LENGTH],
+}
+
+impl Default for HeatField {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeatField {
+    pub const fn new() -> Self {
+        Self {
+            temperature: [AMBIENT_TEMPERATURE; HEAT_LENGTH],
+            source_sum: [0; HEAT_LENGTH],
+            source_count: [0; HEAT_LENGTH],
+        }
+    }
+
+    pub fn from_voxels(voxels: &[Voxel; CHUNK_LENGTH]) -> Self {
+        let mut field = Self::new();
+        field.rebuild_sources(voxels);
+        field
+    }
+
+    /// Recount the heat sources from scratch, e.g. after the voxel
+    /// definitions changed. Temperatures are kept.
+    pub fn rebuild_sources(&mut self, voxels: &[Voxel; CHUNK_LENGTH]) {
+        self.source_sum = [0; HEAT_LENGTH];
+        self.source_count = [0; HEAT_LENGTH];
+        for (voxel_index, voxel) in voxels.iter().enumerate() {
+            self.add_source(voxel_index, *voxel);
+        }
+    }
+
+    #[inline]
+    pub const fn linearize_cell(cell_point: IVec3) -> usize {
+        cell_point.z as usize
+            + cell_point.x as usize * HEAT_CELLS
+            + cell_point.y as usize * HEAT_CELLS * HEAT_CELLS
+    }
+
+    #[inline]
+    pub const fn delinearize_cell(cell_index: usize) -> IVec3 {
+        let y = cell_index / (HEAT_CELLS * HEAT_CELLS);
+        let x = (cell_index / HEAT_CELLS) % HEAT_CELLS;
+        let z = cell_index % HEAT_CELLS;
+        ivec3(x as i32, y as i32, z as i32)
+    }
+
+    /// Heat cell a voxel is in.
+    #[inline]
+    pub fn cell_index(voxel_index: usize) -> usize {
+        Self::linearize_cell(delinearize(voxel_index) / HEAT_CELL_WIDTH as i32)
+    }
+
+    /// Voxel indices within a heat cell.
+    pub fn cell_voxels(cell_index: usize) -> impl Iterator<Item = usize> {
+        let start = Self::delinearize_cell(cell_index) * HEAT_CELL_WIDTH as i32;
+        let width = HEAT_CELL_WIDTH as i32;
+        (0..width).flat_map(move |y| {
+            (0..width).flat_map(move |x| (0..width).map(move |z| linearize(start + ivec3(x, y, z))))
+        })
+    }
+
+    /// Temperature of the cell this voxel is in.
+    #[inline]
+    pub fn temperature_at(&self, voxel_index: usize) -> i16 {
+        self.temperature[Self::cell_index(voxel_index)]
+    }
+
+    #[inline]
+    pub fn add_source(&mut self, voxel_index: usize, voxel: Voxel) {
+        if let Some(temperature) = voxel.definition().interactions.temperature {
+            let cell = Self::cell_index(voxel_index);
+            self.source_sum[cell] += temperature as i32;
+            self.source_count[cell] += 1;
+        }
+    }
+
+    #[inline]
+    pub fn remove_source(&mut self, voxel_index: usize, voxel: Voxel) {
+        if let Some(temperature) = voxel.definition().interactions.temperature {
+            let cell = Self::cell_index(voxel_index);
+            // the definition may have changed since this was added, until the
+            // sources are rebuilt don't underflow
+            self.source_count[cell] = self.source_count[cell].saturating_sub(1);
+            self.source_sum[cell] = match self.source_count[cell] {
+                0 => 0,
+                _ => self.source_sum[cell] - temperature as i32,
+            };
+        }
+    }
+
+    /// Keep the heat sources up to date when a voxel changes.
+    #[inline]
+    pub fn replace(&mut self, voxel_index: usize, previous: Voxel, voxel: Voxel) {
+        // only the state changed (e.g. liquids moving around)
+        if previous.id() == voxel.id() {
+            return;
+        }
+
+        self.remove_source(voxel_index, previous);
+        self.add_source(voxel_index, voxel);
+    }
+
+    /// Temperatures after one tick of diffusion.
+    ///
+    /// `neighbors` are the fields of the neighbouring chunks in
+    /// [`HEAT_NEIGHBORS`] order, missing chunks are treated as insulated.
+    pub fn diffuse(&self, neighbors: &[Option<&HeatField>; 6]) -> [i16; HEAT_LENGTH] {
+        let mut next = self.temperature;
+        for cell in 0..HEAT_LENGTH {
+            let cell_point = Self::delinearize_cell(cell);
+            let temperature = self.temperature[cell] as i32;
+
+            let mut exchange = 0;
+            for (neighbor_index, offset) in HEAT_NEIGHBORS.iter().enumerate() {
+                let neighbor_point = cell_point + offset;
+                let neighbor_field = if neighbor_point.min_element() < 0
+                    || neighbor_point.max_element() >= HEAT_CELLS as i32
+                {
+                    neighbors[neighbor_index]
+                } else {
+                    Some(self)
+                };
+
+                if let Some(field) = neighbor_field {
+                    let wrapped = neighbor_point.rem_euclid(IVec3::splat(HEAT_CELLS as i32));
+                    exchange +=
+                        field.temperature[Self::linearize_cell(wrapped)] as i32 - temperature;
+                }
+            }
+
+            let count = self.source_count[cell] as i32;
+            let next_temperature = if count > 0 {
+                let target = self.source_sum[cell] / count;
+                target + exchange / (DIFFUSION_DIVISOR * SOURCE_INERTIA)
+            } else {
+                // always make some progress back to ambient so cells actually settle
+                let ambient = AMBIENT_TEMPERATURE as i32 - temperature;
+                let cooling = match ambient / AMBIENT_DIVISOR {
+                    0 => ambient.signum(),
+                    cooling => cooling,
+                };
+                temperature + exchange / DIFFUSION_DIVISOR + cooling
+            };
+
+            next[cell] = next_temperature.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        }
+        next
+    }
+}
+
+impl SimChunk {
+    /// Apply phase changes to the voxels in a heat cell, returns true if
+    /// anything changed.
+    pub fn phase_changes(&mut self, cell_index: usize) -> bool {
+        let temperature = self.heat.temperature[cell_index];
+        let mut changed = false;
+        for voxel_index in HeatField::cell_voxels(cell_index) {
+            let interactions = self.voxels[voxel_index].definition().interactions;
+            let becomes = match (interactions.heated, interactions.cooled) {
+                (Some(heated), _) if temperature > heated.temperature => heated.becomes,
+                (_, Some(cooled)) if temperature < cooled.temperature => cooled.becomes,
+                _ => continue,
+            };
+
+            self.set(voxel_index, becomes);
+            changed = true;
+        }
+        changed
+    }
+}
+
+impl SimChunks {
+    /// Recount the heat sources of every chunk.
+    pub fn rebuild_heat_sources(&mut self) {
+        for chunk in self.chunks.values_mut() {
+            chunk.heat.rebuild_sources(&chunk.voxels);
+        }
+    }
+
+    /// Diffuse the heat field one tick and apply any phase changes.
+    pub fn update_heat(&mut self) {
+        #[cfg(feature = "trace")]
+        let update_heat_span = info_span!("update_heat").entered();
+
+        let mut updated = Vec::new();
+        for (chunk_key, chunk) in &self.chunks {
+            let neighbors = HEAT_NEIGHBORS.map(|offset| {
+                let (neighbor_key, _) =
+                    self.chunk_key_from_point(ChunkPoint(chunk.chunk_point.0 + offset))?;
+                self.chunks.get(neighbor_key).map(|neighbor| &neighbor.heat)
+            });
+
+            let next = chunk.heat.diffuse(&neighbors);
+            if next != chunk.heat.temperature {
+                updated.push((chunk_key, next));
+            }
+        }
+
+        let mut spread_list = self.spread_list.lock().unwrap();
+        for (chunk_key, next) in updated {
+            let chunk = self.chunks.get_mut(chunk_key).unwrap();
+            let previous = std::mem::replace(&mut chunk.heat.temperature, next);
+            chunk.quiet_ticks = 0;
+
+            let mut changed = false;
+            for cell in 0..HEAT_LENGTH {
+                if previous[cell] != next[cell] {
+                    changed |= chunk.phase_changes(cell);
+                }
+            }
+
+            if changed {
+                spread_list.mark(chunk.chunk_point.0);
+            }
+        }
+    }
+}
+
+/// Heat sources are counted with the temperatures of the definitions at the
+/// time, recount them when the registry is rebuilt.
+pub fn rebuild_heat_sources(mut grids: Query<&mut SimChunks>) {
+    for mut sim_chunks in &mut grids {
+        sim_chunks.rebuild_heat_sources();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::voxel::simulation::FallingSandTick;
+    use crate::voxel::simulation::kinds::fixtures::sim_with;
+    use crate::voxel::simulation::kinds::liquid::DEFAULT_LIQUID_STATE;
+
+    fn temperature(sim: &SimChunks, point: IVec3) -> i16 {
+        let chunk = sim.chunks.values().next().unwrap();
+        chunk.heat.temperature_at(linearize(point))
+    }
+
+    #[test]
+    pub fn cell_indices() {
+        assert_eq!(HeatField::cell_index(linearize(ivec3(0, 0, 0))), 0);
+        assert_eq!(HeatField::cell_index(linearize(ivec3(3, 3, 3))), 0);
+        assert_eq!(
+            HeatField::cell_index(linearize(ivec3(4, 8, 15))),
+            HeatField::linearize_cell(ivec3(1, 2, 3))
+        );
+
+        let voxels = HeatField::cell_voxels(5).collect::<Vec<_>>();
+        assert_eq!(voxels.len(), HEAT_CELL_WIDTH.pow(3));
+        assert!(voxels.iter().all(|&voxel_index| HeatField::cell_index(voxel_index) == 5));
+    }
+
+    #[test]
+    pub fn sources_tracked() {
+        let mut sim =
+            sim_with(Voxel::Stone, &[(ivec3(1, 1, 1), Voxel::Lava(DEFAULT_LIQUID_STATE))]);
+        let chunk = sim.chunks.values_mut().next().unwrap();
+        assert_eq!(chunk.heat.source_count[0], 1);
+
+        chunk.set(linearize(ivec3(1, 1, 1)), Voxel::Stone);
+        assert_eq!(chunk.heat.source_count[0], 0);
+        assert_eq!(chunk.heat.source_sum[0], 0);
+    }
+
+    #[test]
+    pub fn stale_sources_dont_underflow() {
+        let lava = Voxel::Lava(DEFAULT_LIQUID_STATE);
+        let mut voxels = [Voxel::Stone; CHUNK_LENGTH];
+        let mut field = HeatField::from_voxels(&voxels);
+
+        // lava that was added before it had a temperature
+        field.remove_source(0, lava);
+        assert_eq!(field.source_count[0], 0);
+        assert_eq!(field.source_sum[0], 0);
+
+        voxels[0] = lava;
+        field.rebuild_sources(&voxels);
+        assert_eq!(field.source_count[0], 1);
+        assert_eq!(field.source_sum[0], lava.definition().interactions.temperature.unwrap() as i32);
+    }
+
+    #[test]
+    pub fn diffuses_and_settles() {
+        let mut sim = sim_with(Voxel::Stone, &[]);
+        sim.chunks.values_mut().next().unwrap().heat.temperature[0] = 500;
+
+        sim.update_heat();
+        let neighbor = ivec3(4, 0, 0);
+        assert!(temperature(&sim, ivec3(0, 0, 0)) < 500);
+        assert!(temperature(&sim, neighbor) > AMBIENT_TEMPERATURE);
+
+        for _ in 0..2000 {
+            sim.update_heat();
+        }
+        assert_eq!(temperature(&sim, ivec3(0, 0, 0)), AMBIENT_TEMPERATURE);
+        assert_eq!(temperature(&sim, neighbor), AMBIENT_TEMPERATURE);
+    }
+
+    #[test]
+    pub fn lava_boils_water() {
+        // lava with a layer of water on top, sharing the bottom row of heat cells
+        let mut voxels = Vec::new();
+        for x in 0..16 {
+            for z in 0..16 {
+                for y in 0..3 {
+                    voxels.push((ivec3(x, y, z), Voxel::Lava(DEFAULT_LIQUID_STATE)));
+                }
+                voxels.push((ivec3(x, 3, z), Voxel::Water(DEFAULT_LIQUID_STATE)));
+            }
+        }
+        let mut sim = sim_with(Voxel::Stone, &voxels);
+
+        sim.step(FallingSandTick(1));
+        let chunk = sim.chunks.values().next().unwrap();
+        assert!(chunk.voxels[linearize(ivec3(0, 3, 0))].is_gas());
+        assert!(chunk.voxels[linearize(ivec3(0, 0, 0))].is_liquid());
+        assert!(temperature(&sim, ivec3(0, 0, 0)) > 600, "lava cooled down");
+    }
+
+    #[test]
+    pub fn phase_changes() {
+        let mut sim = sim_with(Voxel::Stone, &[
+            // ice next to fire
+            (ivec3(1, 1, 1), Voxel::Ice),
+            (ivec3(2, 1, 1), Voxel::Fire { voxel_id: 0 }),
+            // lone lava
+            (ivec3(5, 5, 5), Voxel::Lava(DEFAULT_LIQUID_STATE)),
+            // lava buried in ice
+            (ivec3(13, 1, 1), Voxel::Lava(DEFAULT_LIQUID_STATE)),
+            (ivec3(12, 1, 1), Voxel::Ice),
+            (ivec3(14, 1, 1), Voxel::Ice),
+            (ivec3(13, 2, 1), Voxel::Ice),
+            (ivec3(13, 1, 2), Voxel::Ice),
+        ]);
+
+        for _ in 0..10 {
+            sim.update_heat();
+        }
+
+        let chunk = sim.chunks.values().next().unwrap();
+        assert_ne!(chunk.voxels[linearize(ivec3(1, 1, 1))], Voxel::Ice);
+        assert!(chunk.voxels[linearize(ivec3(5, 5, 5))].is_liquid());
+        assert_eq!(chunk.voxels[linearize(ivec3(13, 1, 1))], Voxel::Stone);
+        assert!(chunk.voxels[linearize(ivec3(12, 1, 1))].is_liquid());
+    }
+}
@@ -0,0 +1,414 @@
+//! Make islands of voxels fall if unsupported.
+//!
+//! Solid voxels in recently edited chunks of the [`WorldGrid`] are flood
+//! filled, clusters that can't reach an [anchor](ANCHORS) are cut out of the
+//! grid and spawned as a
+//! [`FallingIsland`], a dynamic rigid body carrying its own small [`Voxels`]
+//! grid which is meshed like any other grid. Once an island comes to rest it
+//! can be put back into the grid it fell from, see
+//! [`IslandSettings::revoxelize`].
+
+use std::collections::VecDeque;
+
+use avian3d::prelude::*;
+use bevy::platform::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+
+use crate::voxel::mesh::SurfaceNet;
+use crate::voxel::simulation::data::SimChunks;
+use crate::voxel::tree::{CHUNK_WIDTH, from_leaf_index};
+use crate::voxel::{Voxel, VoxelAabb, VoxelNode, VoxelSet, VoxelTree, Voxels, WorldGrid};
+
+/// Voxels that hold up everything connected to them.
+pub const ANCHORS: VoxelSet = VoxelSet::from_list([Voxel::Base, Voxel::Barrier]);
+
+/// Face neighbours, voxels are only connected through faces.
+pub const NEIGHBORS: [IVec3; 6] =
+    [IVec3::Y, IVec3::NEG_Y, IVec3::X, IVec3::NEG_X, IVec3::Z, IVec3::NEG_Z];
+
+pub fn plugin(app: &mut App) {
+    app.register_type::<IslandSettings>().register_type::<FallingIsland>();
+    app.init_resource::<IslandSettings>();
+
+    app.add_systems(Update, (detach_islands, settle_islands).chain());
+}
+
+#[derive(Resource, Clone, Debug, Reflect)]
+#[reflect(Resource)]
+pub struct IslandSettings {
+    /// Clusters bigger than this are assumed to be supported, keeps large
+    /// terrain from being searched every time it's modified.
+    pub max_island_voxels: usize,
+
+    /// How many edited chunks to search each frame.
+    pub chunks_per_frame: usize,
+
+    /// Put islands back into the grid they fell from once they come to rest.
+    pub revoxelize: bool,
+
+    /// Islands slower than this (linear and angular) are resting.
+    pub rest_speed: f32,
+
+    /// Seconds an island has to rest before it is re-voxelized.
+    pub rest_time: f32,
+}
+
+impl Default for IslandSettings {
+    fn default() -> Self {
+        Self {
+            max_island_voxels: 8192,
+            chunks_per_frame: 16,
+            revoxelize: true,
+            rest_speed: 0.05,
+            rest_time: 1.0,
+        }
+    }
+}
+
+/// Cluster of solid voxels that isn't connected to any [`ANCHORS`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Island {
+    /// Bounds of the voxels.
+    pub aabb: VoxelAabb,
+    pub voxels: Vec<(IVec3, Voxel)>,
+}
+
+/// Falling rigid body cut out of `grid_entity`.
+#[derive(Component, Debug, Clone, Reflect)]
+#[reflect(Component)]
+pub struct FallingIsland {
+    pub grid_entity: Entity,
+    /// Seconds this island has been resting for.
+    pub resting: f32,
+}
+
+/// Find the islands with solid voxels in `chunks`.
+///
+/// Clusters are searched with 6-connectivity, anything past
+/// `max_island_voxels` is treated as supported.
+pub fn find_islands(
+    tree: &VoxelTree,
+    chunks: impl IntoIterator<Item = IVec3>,
+    max_island_voxels: usize,
+) -> Vec<Island> {
+    let mut islands = Vec::new();
+    let mut visited = HashSet::new();
+    let mut cluster = HashSet::new();
+    let mut queue = VecDeque::new();
+
+    let chunk_width = IVec3::splat(CHUNK_WIDTH as i32);
+    for chunk_point in chunks {
+        let chunk_min = chunk_point * chunk_width;
+        let seeds: Vec<IVec3> = match tree.get_chunk(chunk_point) {
+            // every voxel in a solid chunk is connected, one seed covers it
+            Some(VoxelNode::Solid { voxel, .. }) if voxel.is_solid() => vec![chunk_min],
+            Some(VoxelNode::Leaf { leaf, .. }) => leaf
+                .iter()
+                .enumerate()
+                .filter(|(_, voxel)| voxel.is_solid())
+                .map(|(index, _)| chunk_min + from_leaf_index(index))
+                .collect(),
+            _ => continue,
+        };
+
+        for seed in seeds {
+            if visited.contains(&seed) {
+                continue;
+            }
+
+            cluster.clear();
+            queue.clear();
+            cluster.insert(seed);
+            queue.push_back(seed);
+
+            let mut supported = false;
+            'flood: while let Some(point) = queue.pop_front() {
+                if ANCHORS.contains(tree.get_voxel(point)) || cluster.len() > max_island_voxels {
+                    supported = true;
+                    break;
+                }
+
+                for offset in NEIGHBORS {
+                    let neighbor = point + offset;
+                    if cluster.contains(&neighbor) || !tree.get_voxel(neighbor).is_solid() {
+                        continue;
+                    }
+
+                    // islands are closed off, so anything visited by an earlier
+                    // search is supported
+                    if visited.contains(&neighbor) {
+                        supported = true;
+                        break 'flood;
+                    }
+
+                    cluster.insert(neighbor);
+                    queue.push_back(neighbor);
+                }
+            }
+
+            visited.extend(cluster.iter().copied());
+            if supported {
+                continue;
+            }
+
+            let mut voxels =
+                cluster.iter().map(|point| (*point, tree.get_voxel(*point))).collect::<Vec<_>>();
+            voxels.sort_by_key(|(point, _)| point.to_array());
+
+            let (min, max) =
+                voxels.iter().fold((IVec3::MAX, IVec3::MIN), |(min, max), (point, _)| {
+                    (min.min(*point), max.max(*point))
+                });
+            islands.push(Island { aabb: VoxelAabb::new(min, max), voxels });
+        }
+    }
+
+    islands
+}
+
+/// Solid voxels of a grid, used to put an island back.
+pub fn solid_voxels(tree: &VoxelTree) -> Vec<(IVec3, Voxel)> {
+    let (min, max) = tree.voxel_bounds();
+    let mut voxels = Vec::new();
+    for (node_aabb, node) in tree.iter_nodes_in(VoxelAabb::new(min, max - IVec3::ONE)) {
+        match node {
+            VoxelNode::Solid { voxel, .. } if voxel.is_solid() => {
+                for y in node_aabb.min.y..=node_aabb.max.y {
+                    for x in node_aabb.min.x..=node_aabb.max.x {
+                        for z in node_aabb.min.z..=node_aabb.max.z {
+                            voxels.push((IVec3::new(x, y, z), *voxel));
+                        }
+                    }
+                }
+            },
+            VoxelNode::Leaf { leaf, .. } => {
+                for (index, voxel) in leaf.iter().enumerate() {
+                    if voxel.is_solid() {
+                        voxels.push((node_aabb.min + from_leaf_index(index), *voxel));
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+    voxels
+}
+
+/// Search recently edited chunks for islands and turn them into
+/// [`FallingIsland`]s.
+///
+/// Only the [`WorldGrid`] has anchors, other grids hold themselves together.
+/// Changes made by the simulation aren't edits, see
+/// [`VoxelTree::edited_chunks`].
+pub fn detach_islands(
+    mut commands: Commands,
+    settings: Res<IslandSettings>,
+    mut grids: Query<
+        (Entity, &mut Voxels, &GlobalTransform, Option<&mut SimChunks>),
+        With<WorldGrid>,
+    >,
+) {
+    for (grid_entity, mut voxels, grid_transform, mut sim_chunks) in &mut grids {
+        let edited = &mut voxels.tree.edited_chunks;
+        let chunks = edited.iter().take(settings.chunks_per_frame).copied().collect::<Vec<_>>();
+        for chunk_point in &chunks {
+            edited.remove(chunk_point);
+        }
+
+        for island in find_islands(&voxels.tree, chunks, settings.max_island_voxels) {
+            let mut island_voxels = Voxels::new(island.aabb.size());
+            let mut grid_coordinates = Vec::with_capacity(island.voxels.len());
+            for (point, voxel) in &island.voxels {
+                voxels.set_voxel(*point, Voxel::Air);
+                if let Some(sim_chunks) = &mut sim_chunks {
+                    sim_chunks.set_voxel(*point, Voxel::Air);
+                }
+
+                let local_point = *point - island.aabb.min;
+                island_voxels.set_voxel(local_point, *voxel);
+                grid_coordinates.push(local_point);
+            }
+
+            let transform = grid_transform
+                .mul_transform(Transform::from_translation(island.aabb.min.as_vec3()))
+                .compute_transform();
+            commands.spawn((
+                Name::new(format!("Falling Island ({} voxels)", island.voxels.len())),
+                FallingIsland { grid_entity, resting: 0.0 },
+                island_voxels,
+                transform,
+                SurfaceNet::default(),
+                RigidBody::Dynamic,
+                Collider::voxels(Vec3::ONE, &grid_coordinates),
+            ));
+        }
+    }
+}
+
+/// Put resting islands back into their grid, see
+/// [`IslandSettings::revoxelize`].
+pub fn settle_islands(
+    mut commands: Commands,
+    settings: Res<IslandSettings>,
+    time: Res<Time>,
+    mut islands: Query<(
+        Entity,
+        &mut FallingIsland,
+        &Voxels,
+        &GlobalTransform,
+        &LinearVelocity,
+        &AngularVelocity,
+    )>,
+    mut grids: Query<
+        (&mut Voxels, &GlobalTransform, Option<&mut SimChunks>),
+        Without<FallingIsland>,
+    >,
+) {
+    if !settings.revoxelize {
+        return;
+    }
+
+    for (island_entity, mut island, island_voxels, island_transform, linear, angular) in
+        &mut islands
+    {
+        if linear.length() > settings.rest_speed || angular.length() > settings.rest_speed {
+            island.resting = 0.0;
+            continue;
+        }
+
+        island.resting += time.delta_secs();
+        if island.resting < settings.rest_time {
+            continue;
+        }
+
+        let Ok((mut voxels, grid_transform, mut sim_chunks)) = grids.get_mut(island.grid_entity)
+        else {
+            // nothing to settle into
+            continue;
+        };
+
+        let to_grid = grid_transform.affine().inverse() * island_transform.affine();
+        for (local_point, voxel) in solid_voxels(&island_voxels.tree) {
+            let center = to_grid.transform_point3(local_point.as_vec3() + Vec3::splat(0.5));
+            let mut point = center.floor().as_ivec3();
+
+            // don't overwrite anything the island landed on, above the tree is air
+            let (_, max) = voxels.voxel_bounds();
+            while point.y < max.y && !VoxelSet::AIR.contains(voxels.get_voxel(point)) {
+                point.y += 1;
+            }
+
+            voxels.set_voxel(point, voxel);
+            if let Some(sim_chunks) = &mut sim_chunks {
+                sim_chunks.set_voxel(point, voxel);
+            }
+        }
+
+        commands.entity(island_entity).despawn();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+    use crate::voxel::simulation::data::{CHUNK_LENGTH, ChunkPoint};
+    use crate::voxel::simulation::propagate_to_tree;
+
+    fn spawned_islands(world: &mut World) -> Vec<Entity> {
+        world.query::<&FallingIsland>().iter(world).map(|island| island.grid_entity).collect()
+    }
+
+    #[test]
+    pub fn cut_pillar() {
+        let mut tree = VoxelTree::new();
+        tree.grow_n_layers(1);
+        tree.fill_aabb(VoxelAabb::new(IVec3::ZERO, IVec3::new(31, 0, 31)), Voxel::Base);
+        tree.fill_aabb(VoxelAabb::new(IVec3::new(4, 1, 4), IVec3::new(5, 20, 5)), Voxel::Stone);
+        // floating on its own
+        tree.fill_aabb(VoxelAabb::new(IVec3::new(20, 10, 20), IVec3::new(22, 12, 22)), Voxel::Dirt);
+        // sand isn't solid, doesn't hold anything up
+        tree.set_voxel(IVec3::new(20, 9, 20), Voxel::Sand);
+
+        let all_chunks = [IVec3::ZERO, IVec3::X, IVec3::Z, IVec3::new(1, 0, 1), IVec3::Y];
+        let islands = find_islands(&tree, all_chunks, 4096);
+        assert_eq!(islands.len(), 1);
+        assert_eq!(islands[0].aabb, VoxelAabb::new(IVec3::new(20, 10, 20), IVec3::new(22, 12, 22)));
+        assert_eq!(islands[0].voxels.len(), 27);
+        assert!(islands[0].voxels.iter().all(|(_, voxel)| *voxel == Voxel::Dirt));
+
+        // cut through the pillar
+        tree.fill_aabb(VoxelAabb::new(IVec3::new(4, 8, 4), IVec3::new(5, 8, 5)), Voxel::Air);
+        let islands = find_islands(&tree, [IVec3::ZERO], 4096);
+        assert_eq!(islands.len(), 1);
+        assert_eq!(islands[0].aabb, VoxelAabb::new(IVec3::new(4, 9, 4), IVec3::new(5, 20, 5)));
+        assert_eq!(islands[0].voxels.len(), 2 * 12 * 2);
+
+        // too big to be an island
+        assert!(find_islands(&tree, [IVec3::ZERO], 10).is_empty());
+    }
+
+    #[test]
+    pub fn solid_voxels_of_grid() {
+        let mut tree = VoxelTree::new();
+        tree.fill_aabb(VoxelAabb::new(IVec3::ZERO, IVec3::new(2, 0, 0)), Voxel::Stone);
+        tree.set_voxel(IVec3::new(0, 1, 0), Voxel::Water(default()));
+
+        let voxels = solid_voxels(&tree);
+        assert_eq!(voxels, vec![
+            (IVec3::new(0, 0, 0), Voxel::Stone),
+            (IVec3::new(1, 0, 0), Voxel::Stone),
+            (IVec3::new(2, 0, 0), Voxel::Stone),
+        ]);
+    }
+
+    #[test]
+    pub fn only_world_grid_detaches() {
+        let mut world = World::new();
+        world.init_resource::<IslandSettings>();
+
+        // nothing is anchored in either grid
+        let mut voxels = Voxels::new(IVec3::splat(16));
+        voxels.fill_aabb(VoxelAabb::new(IVec3::splat(4), IVec3::splat(6)), Voxel::Stone);
+        let prop = world.spawn((voxels.clone(), GlobalTransform::IDENTITY)).id();
+        let terrain = world.spawn((voxels, GlobalTransform::IDENTITY, WorldGrid)).id();
+
+        world.run_system_once(detach_islands).unwrap();
+        assert_eq!(spawned_islands(&mut world), vec![terrain]);
+        assert_eq!(world.get::<Voxels>(terrain).unwrap().get_voxel(IVec3::splat(5)), Voxel::Air);
+
+        let prop_voxels = world.get::<Voxels>(prop).unwrap();
+        assert_eq!(prop_voxels.get_voxel(IVec3::splat(5)), Voxel::Stone);
+    }
+
+    #[test]
+    pub fn sim_changes_arent_edits() {
+        let mut world = World::new();
+        world.init_resource::<IslandSettings>();
+
+        let mut sim_chunks = SimChunks::new();
+        sim_chunks.add_chunk(ChunkPoint(IVec3::ZERO), [Voxel::Air; CHUNK_LENGTH]);
+        sim_chunks.set_voxel(IVec3::splat(5), Voxel::Stone);
+        let grid = world
+            .spawn((
+                Voxels::new(IVec3::splat(16)),
+                sim_chunks,
+                GlobalTransform::IDENTITY,
+                WorldGrid,
+            ))
+            .id();
+
+        world.run_system_once(propagate_to_tree).unwrap();
+        world.run_system_once(detach_islands).unwrap();
+        let voxels = world.get::<Voxels>(grid).unwrap();
+        assert_eq!(voxels.get_voxel(IVec3::splat(5)), Voxel::Stone);
+        assert!(voxels.tree.edited_chunks.is_empty());
+        assert!(spawned_islands(&mut world).is_empty());
+
+        // an edit next to it does
+        world.get_mut::<Voxels>(grid).unwrap().set_voxel(IVec3::new(5, 6, 5), Voxel::Dirt);
+        world.run_system_once(detach_islands).unwrap();
+        assert_eq!(spawned_islands(&mut world), vec![grid]);
+    }
+}
@@ -0,0 +1,209 @@
+use bevy::prelude::*;
+
+use crate::voxel::Voxel;
+use crate::voxel::simulation::FallingSandTick;
+use crate::voxel::simulation::data::ChunkView;
+use crate::voxel::simulation::kinds::VoxelPosition;
+use crate::voxel::simulation::reactions::{REACTION_NEIGHBORS, roll_chance};
+use crate::voxel::voxel::VoxelId;
+
+/// Chance per tick for fire to catch onto each flammable neighbour.
+pub const SPREAD_CHANCE: f32 = 0.25;
+/// Chance per tick for a burning voxel to throw a flame into the air above it.
+pub const FLAME_CHANCE: f32 = 0.3;
+/// Chance per tick for a free flame (burning nothing) to die out.
+pub const FLAME_BURNOUT_CHANCE: f32 = 0.25;
+/// Ticks of burning per point of the burning voxel's starting health, on
+/// average.
+pub const BURN_TIME_SCALE: f32 = 4.0;
+/// Liquids cooler than this put fire out, hotter ones (lava) don't.
+pub const IGNITION_TEMPERATURE: i16 = 300;
+
+/// What a fire burning `voxel_id` leaves behind once it is done.
+#[inline]
+pub fn burnt_remains(voxel_id: VoxelId) -> Voxel {
+    Voxel::from_id(voxel_id as u16).and_then(|voxel| voxel.definition().interactions.burnt)
+        // unknown/non-flammable voxels just burn away
+        .unwrap_or(Voxel::Air)
+}
+
+/// Whether `voxel` puts out fire next to it, cool non-flammable liquids.
+#[inline]
+pub fn extinguishes(voxel: Voxel) -> bool {
+    voxel.is_liquid()
+        && ignite(voxel).is_none()
+        && voxel
+            .definition()
+            .interactions
+            .temperature
+            .is_none_or(|temperature| temperature < IGNITION_TEMPERATURE)
+}
+
+/// Set a voxel on fire if it is flammable.
+#[inline]
+pub fn ignite(voxel: Voxel) -> Option<Voxel> {
+    match voxel {
+        Voxel::Fire { .. } => None,
+        _ => voxel
+            .definition()
+            .interactions
+            .burnt
+            .map(|_| Voxel::Fire { voxel_id: voxel.id() as VoxelId }),
+    }
+}
+
+/// Fire consumes the voxel it is burning (`voxel_id`), spreads to flammable
+/// neighbours, and throws flames which rise through the air.
+#[inline]
+pub fn simulate_fire(
+    view: &mut ChunkView<'_>,
+    block_origin: IVec3,
+    position: VoxelPosition,
+    voxel_id: VoxelId,
+    tick: FallingSandTick,
+) {
+    #[cfg(feature = "trace")]
+    let simulate_fire_span = info_span!("simulate_fire").entered();
+
+    // flames that just moved or caught this tick wait for the next one
+    if view.is_modified(position) {
+        return;
+    }
+
+    let point = position.world_point(block_origin);
+    let burning = Voxel::from_id(voxel_id as u16).unwrap_or(Voxel::Air);
+    let neighbors = REACTION_NEIGHBORS.map(|check| view.get_relative_voxel(position, check));
+
+    // cool non-flammable liquids put us out
+    if neighbors.iter().flatten().any(|(_, neighbor)| extinguishes(*neighbor)) {
+        view.set_voxel(position, burnt_remains(voxel_id));
+        return;
+    }
+
+    // a free flame, rises and dies out quickly
+    if burning == Voxel::Air {
+        if roll_chance(tick, point, 0, FLAME_BURNOUT_CHANCE) {
+            view.set_voxel(position, Voxel::Air);
+            return;
+        }
+
+        match neighbors[0] {
+            Some((above_position, above)) if above == Voxel::Air => {
+                view.set_voxel(above_position, Voxel::Fire { voxel_id });
+                view.set_voxel(position, Voxel::Air);
+            },
+            _ => view.mark_modified(position),
+        }
+        return;
+    }
+
+    for (index, neighbor) in neighbors.iter().enumerate() {
+        let Some((neighbor_position, neighbor)) = *neighbor else {
+            continue;
+        };
+
+        if let Some(ignited) = ignite(neighbor) {
+            if roll_chance(tick, point, 1 + index as u32, SPREAD_CHANCE) {
+                view.set_voxel(neighbor_position, ignited);
+            }
+        }
+    }
+
+    if let Some((above_position, above)) = neighbors[0] {
+        if above == Voxel::Air && roll_chance(tick, point, 7, FLAME_CHANCE) {
+            view.set_voxel(above_position, Voxel::Fire { voxel_id: Voxel::Air.id() as VoxelId });
+        }
+    }
+
+    // tougher voxels burn longer
+    let burnout_chance = 1.0 / (burning.starting_health().max(1) as f32 * BURN_TIME_SCALE);
+    if roll_chance(tick, point, 8, burnout_chance) {
+        view.set_voxel(position, burnt_remains(voxel_id));
+    } else {
+        view.mark_modified(position);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::voxel::simulation::kinds::fixtures::{find, sim_with};
+
+    #[test]
+    pub fn oil_seam_burns_out() {
+        // line of oil in a stone seam
+        let oil = (1..7).map(|x| (ivec3(x, 1, 1), Voxel::Oil(default()))).collect::<Vec<_>>();
+        let mut sim = sim_with(Voxel::Stone, &oil);
+        sim.set_voxel(ivec3(1, 1, 1), Voxel::Fire { voxel_id: Voxel::Oil(default()).id() as u8 });
+
+        let mut caught = false;
+        // the sim tick is bumped before simulating, so it starts at 1
+        for tick in 1..1000 {
+            sim.step(FallingSandTick(tick));
+            caught |= sim.get_voxel(ivec3(6, 1, 1))
+                == Some(Voxel::Fire { voxel_id: Voxel::Oil(default()).id() as u8 });
+        }
+
+        assert!(caught, "fire never spread down the seam");
+        // oil burns into air
+        assert_eq!(
+            find(&sim, |voxel| matches!(voxel, Voxel::Oil(..) | Voxel::Fire { .. })).len(),
+            0
+        );
+        assert_eq!(find(&sim, |voxel| voxel == Voxel::Air).len(), 6);
+    }
+
+    #[test]
+    pub fn grass_leaves_dirt() {
+        let mut sim = sim_with(Voxel::Stone, &[(ivec3(1, 1, 1), Voxel::Grass)]);
+        sim.set_voxel(ivec3(1, 1, 1), Voxel::Fire { voxel_id: Voxel::Grass.id() as u8 });
+
+        for tick in 0..1000 {
+            sim.step(FallingSandTick(tick));
+        }
+
+        assert_eq!(sim.get_voxel(ivec3(1, 1, 1)), Some(Voxel::Dirt));
+    }
+
+    #[test]
+    pub fn extinguished_by_liquid() {
+        let mut sim = sim_with(Voxel::Stone, &[
+            (ivec3(1, 1, 1), Voxel::Water(default())),
+            (ivec3(2, 1, 1), Voxel::Fire { voxel_id: Voxel::Grass.id() as u8 }),
+        ]);
+
+        sim.step(FallingSandTick(0));
+        // either the water reaction or the fire itself puts it out
+        assert!(!matches!(sim.get_voxel(ivec3(2, 1, 1)), Some(Voxel::Fire { .. })));
+    }
+
+    #[test]
+    pub fn lava_doesnt_extinguish() {
+        let fire = Voxel::Fire { voxel_id: Voxel::Grass.id() as u8 };
+        let mut sim = sim_with(Voxel::Stone, &[
+            (ivec3(1, 1, 1), Voxel::Lava(default())),
+            (ivec3(2, 1, 1), fire),
+        ]);
+
+        sim.step(FallingSandTick(0));
+        assert_eq!(sim.get_voxel(ivec3(2, 1, 1)), Some(fire));
+    }
+
+    #[test]
+    pub fn flames_rise() {
+        let mut sim = sim_with(Voxel::Air, &[(ivec3(4, 1, 4), Voxel::Fire { voxel_id: 0 })]);
+
+        let mut highest = 1;
+        for tick in 0..64 {
+            sim.step(FallingSandTick(tick));
+            for y in 0..16 {
+                if sim.get_voxel(ivec3(4, y, 4)) == Some(Voxel::Fire { voxel_id: 0 }) {
+                    highest = highest.max(y);
+                }
+            }
+        }
+
+        assert!(highest > 1, "flame never rose");
+        assert_eq!(find(&sim, |voxel| matches!(voxel, Voxel::Fire { .. })).len(), 0);
+    }
+}
@@ -0,0 +1,261 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::voxel::Voxel;
+use crate::voxel::simulation::FallingSandTick;
+use crate::voxel::simulation::data::ChunkView;
+use crate::voxel::simulation::kinds::VoxelPosition;
+use crate::voxel::simulation::kinds::liquid::Direction;
+use crate::voxel::simulation::reactions::roll;
+
+/// Lifetime is ticked down once every this many ticks.
+pub const DISSIPATE_RATE: u32 = 4;
+
+#[derive(Copy, Clone, Debug)]
+pub enum GasVoxel {
+    Steam(GasState),
+    Smoke(GasState),
+    Methane(GasState),
+}
+
+impl GasVoxel {
+    #[inline]
+    pub fn state(&self) -> &GasState {
+        match self {
+            Self::Steam(state) | Self::Smoke(state) | Self::Methane(state) => state,
+        }
+    }
+
+    #[inline]
+    pub fn state_mut(&mut self) -> &mut GasState {
+        match self {
+            Self::Steam(state) | Self::Smoke(state) | Self::Methane(state) => state,
+        }
+    }
+
+    #[inline]
+    pub fn from_voxel(voxel: Voxel) -> Self {
+        match voxel {
+            Voxel::Steam(state) => Self::Steam(state),
+            Voxel::Smoke(state) => Self::Smoke(state),
+            Voxel::Methane(state) => Self::Methane(state),
+            _ => panic!("Voxel was not a gas voxel: {:?}", voxel),
+        }
+    }
+
+    #[inline]
+    pub fn to_voxel(self) -> Voxel {
+        match self {
+            Self::Steam(state) => Voxel::Steam(state),
+            Self::Smoke(state) => Voxel::Smoke(state),
+            Self::Methane(state) => Voxel::Methane(state),
+        }
+    }
+}
+
+/// Remaining lifetime of a gas voxel, packed into a u8.
+///
+/// [`GasState::PERMANENT`] never dissipates.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Reflect, Serialize, Deserialize)]
+pub struct GasState(u8);
+
+pub const DEFAULT_GAS_STATE: GasState = GasState::new(64);
+
+impl Default for GasState {
+    fn default() -> Self {
+        DEFAULT_GAS_STATE
+    }
+}
+
+impl GasState {
+    pub const PERMANENT: GasState = GasState(u8::MAX);
+
+    #[inline]
+    pub const fn new(lifetime: u8) -> Self {
+        Self(lifetime)
+    }
+
+    #[inline]
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits)
+    }
+
+    #[inline]
+    pub const fn bits(&self) -> u8 {
+        self.0
+    }
+
+    #[inline]
+    pub const fn lifetime(&self) -> u8 {
+        self.0
+    }
+
+    #[inline]
+    pub const fn is_permanent(&self) -> bool {
+        self.0 == u8::MAX
+    }
+
+    /// Tick the lifetime down, returns false once the gas has dissipated.
+    #[inline]
+    pub fn dissipate(&mut self) -> bool {
+        if self.is_permanent() {
+            return true;
+        }
+
+        self.0 = self.0.saturating_sub(1);
+        self.0 > 0
+    }
+}
+
+/// Gases rise through anything denser than them, spread out sideways when
+/// they can't and dissipate once their lifetime runs out.
+#[inline]
+pub fn simulate_gas(
+    view: &mut ChunkView<'_>,
+    block_origin: IVec3,
+    position: VoxelPosition,
+    sim_voxel: Voxel,
+    tick: FallingSandTick,
+) {
+    #[cfg(feature = "trace")]
+    let simulate_gas_span = info_span!("simulate_gas").entered();
+
+    // already moved this tick
+    if view.is_modified(position) {
+        return;
+    }
+
+    let mut gas_voxel = GasVoxel::from_voxel(sim_voxel);
+    if tick.0 % DISSIPATE_RATE == 0 && !gas_voxel.state_mut().dissipate() {
+        view.set_voxel(position, Voxel::Air);
+        return;
+    }
+
+    let swappable =
+        |target: Voxel| (target.is_gas() || target.is_liquid()) && target.denser(sim_voxel);
+
+    let point = position.world_point(block_origin);
+    let directions = Direction::directions();
+    let first = roll(tick, point, 0) as usize;
+    let directions = (0..4).map(|index| directions[(first + index) % 4].as_ivec3());
+
+    // rise, then rise diagonally, then spread out
+    let checks = std::iter::once(IVec3::Y)
+        .chain(directions.clone().map(|direction| IVec3::Y + direction))
+        .chain(directions);
+    for check in checks {
+        let Some((check_position, check_voxel)) = view.get_relative_voxel(position, check) else {
+            continue;
+        };
+
+        // only bubble up through liquids, don't slide along under them
+        if check.y == 0 && check_voxel.is_liquid() {
+            continue;
+        }
+
+        if swappable(check_voxel) {
+            view.set_voxel(check_position, gas_voxel.to_voxel());
+            view.set_voxel(position, check_voxel);
+            return;
+        }
+    }
+
+    // stuck, keeps dissipating or sleeps until something next to it changes
+    if gas_voxel.to_voxel() != sim_voxel {
+        view.set_voxel(position, gas_voxel.to_voxel());
+    } else if !gas_voxel.state().is_permanent() {
+        view.mark_modified(position);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::voxel::simulation::kinds::fixtures::{find, stone_box};
+
+    #[test]
+    pub fn rises_and_spreads() {
+        let steam = (0..3)
+            .map(|x| (ivec3(6 + x, 1, 7), Voxel::Steam(GasState::PERMANENT)))
+            .collect::<Vec<_>>();
+        let mut sim = stone_box(&steam);
+
+        for tick in 1..200 {
+            sim.step(FallingSandTick(tick));
+        }
+
+        let steam = find(&sim, |voxel| matches!(voxel, Voxel::Steam(..)));
+        assert_eq!(steam.len(), 3);
+        // all of it collected against the ceiling
+        assert!(steam.iter().all(|point| point.y == 14), "{steam:?}");
+    }
+
+    #[test]
+    pub fn stuck_permanent_gas_sleeps() {
+        // sealed in by stone, diagonals included
+        let mut voxels = vec![(ivec3(7, 1, 7), Voxel::Methane(GasState::PERMANENT))];
+        for offset in [IVec3::X, IVec3::NEG_X, IVec3::Z, IVec3::NEG_Z] {
+            voxels.push((ivec3(7, 1, 7) + offset, Voxel::Stone));
+            voxels.push((ivec3(7, 2, 7) + offset, Voxel::Stone));
+        }
+        voxels.push((ivec3(7, 2, 7), Voxel::Stone));
+        let mut sim = stone_box(&voxels);
+
+        for tick in 1..16 {
+            sim.step(FallingSandTick(tick));
+        }
+
+        let chunk = sim.chunks.values().next().unwrap();
+        assert!(!chunk.modified.any_set());
+        assert_eq!(find(&sim, |voxel| matches!(voxel, Voxel::Methane(..))), vec![ivec3(7, 1, 7)]);
+    }
+
+    #[test]
+    pub fn dissipates() {
+        let mut sim = stone_box(&[(ivec3(7, 1, 7), Voxel::Steam(GasState::new(8)))]);
+
+        for tick in 1..(8 * DISSIPATE_RATE + 1) {
+            sim.step(FallingSandTick(tick));
+        }
+
+        assert_eq!(find(&sim, |voxel| voxel.is_gas() && voxel != Voxel::Air), vec![]);
+    }
+
+    #[test]
+    pub fn bubbles_through_water() {
+        let mut voxels = vec![(ivec3(7, 1, 7), Voxel::Smoke(GasState::PERMANENT))];
+        // column of water above it, walled in so it can't spill out
+        for y in 2..6 {
+            voxels.push((ivec3(7, y, 7), Voxel::Water(default())));
+        }
+        for y in 1..15 {
+            for offset in [IVec3::X, IVec3::NEG_X, IVec3::Z, IVec3::NEG_Z] {
+                voxels.push((ivec3(7, y, 7) + offset, Voxel::Stone));
+            }
+        }
+        let mut sim = stone_box(&voxels);
+
+        for tick in 1..100 {
+            sim.step(FallingSandTick(tick));
+        }
+
+        assert_eq!(find(&sim, |voxel| matches!(voxel, Voxel::Smoke(..))), vec![ivec3(7, 14, 7)]);
+        assert_eq!(find(&sim, |voxel| voxel.is_liquid()).len(), 4);
+    }
+
+    #[test]
+    pub fn lighter_gas_rises_above() {
+        let mut sim = stone_box(&[
+            (ivec3(7, 1, 7), Voxel::Methane(GasState::PERMANENT)),
+            (ivec3(7, 2, 7), Voxel::Steam(GasState::PERMANENT)),
+        ]);
+
+        for tick in 1..100 {
+            sim.step(FallingSandTick(tick));
+        }
+
+        let methane = find(&sim, |voxel| matches!(voxel, Voxel::Methane(..)));
+        assert_eq!(methane.len(), 1);
+        assert_eq!(methane[0].y, 14);
+    }
+}
@@ -9,20 +9,21 @@ use crate::voxel::simulation::{FallingSandTick, SimChunks};
 pub enum LiquidVoxel {
     Water(LiquidState),
     Oil(LiquidState),
+    Lava(LiquidState),
 }
 
 impl LiquidVoxel {
     #[inline]
     pub fn state(&self) -> &LiquidState {
         match self {
-            Self::Water(state) | Self::Oil(state) => state,
+            Self::Water(state) | Self::Oil(state) | Self::Lava(state) => state,
         }
     }
 
     #[inline]
     pub fn state_mut(&mut self) -> &mut LiquidState {
         match self {
-            Self::Water(state) | Self::Oil(state) => state,
+            Self::Water(state) | Self::Oil(state) | Self::Lava(state) => state,
         }
     }
 
@@ -41,6 +42,7 @@ impl LiquidVoxel {
         match voxel {
             Voxel::Water(state) => Self::Water(state),
             Voxel::Oil(state) => Self::Oil(state),
+            Voxel::Lava(state) => Self::Lava(state),
             _ => panic!("Voxel was not a liquid voxel: {:?}", voxel),
         }
     }
@@ -50,6 +52,7 @@ impl LiquidVoxel {
         match self {
             Self::Water(state) => Voxel::Water(state),
             Self::Oil(state) => Voxel::Oil(state),
+            Self::Lava(state) => Voxel::Lava(state),
         }
     }
 
@@ -59,6 +62,19 @@ impl LiquidVoxel {
     }
 }
 
+/// How liquids flow, set per grid on [`SimChunks::liquid_mode`].
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Reflect, Serialize, Deserialize)]
+#[reflect(Default, Clone, Debug)]
+pub enum LiquidMode {
+    /// Liquid wanders sideways using the direction and energy in its
+    /// [`LiquidState`], evaporating once it runs out of energy.
+    #[default]
+    Classic,
+    /// Liquid only falls on its own, connected bodies are levelled by
+    /// [`SimChunks::level_liquids`]. Liquid is never lost and comes to rest.
+    Pressure,
+}
+
 // packed into a u8
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Reflect, Serialize, Deserialize)]
 pub struct LiquidState(u8);
@@ -165,6 +181,7 @@ pub fn simulate_liquid(
     point: VoxelPosition,
     sim_voxel: Voxel,
     tick: FallingSandTick,
+    mode: LiquidMode,
 ) {
     #[cfg(feature = "trace")]
     let simulate_liquid_span = info_span!("simulate_liquid").entered();
@@ -179,9 +196,7 @@ pub fn simulate_liquid(
         _ => unreachable!(),
     };
 
-    let swappable = |target: Voxel, current: Voxel| {
-        target.is_gas() || (target.is_liquid() && current.denser(target))
-    };
+    let swappable = |target: Voxel, current: Voxel| current.sinks_through(target);
 
     const STARTING_ENERGY: u8 = 16;
 
@@ -190,6 +205,32 @@ pub fn simulate_liquid(
         return;
     };
 
+    if mode == LiquidMode::Pressure {
+        // the state is left alone so liquid at rest stays exactly the same
+        if swappable(below_voxel, sim_voxel) {
+            view.set_voxel(below_point, sim_voxel);
+            view.set_voxel(point, below_voxel);
+            return;
+        }
+
+        let directions = Direction::directions();
+        for direction in directions.iter().cycle().skip((tick.0 % 4) as usize).take(4) {
+            let Some((diagonal_point, diagonal_voxel)) =
+                view.get_relative_voxel(point, direction.as_ivec3() - IVec3::Y)
+            else {
+                continue;
+            };
+
+            if swappable(diagonal_voxel, sim_voxel) {
+                view.set_voxel(diagonal_point, sim_voxel);
+                view.set_voxel(point, diagonal_voxel);
+                return;
+            }
+        }
+
+        return;
+    }
+
     if swappable(below_voxel, sim_voxel) {
         liquid_voxel.set_state(new_direction, STARTING_ENERGY);
 
@@ -3,9 +3,12 @@ use bevy::prelude::*;
 use crate::voxel::Voxel;
 use crate::voxel::simulation::data::{CHUNK_WIDTH, ChunkView, delinearize, linearize};
 // use crate::voxel::simulation::kinds::liquid::LiquidState;
+use crate::voxel::simulation::kinds::liquid::LiquidMode;
 use crate::voxel::simulation::{FallingSandTick, SimChunks};
+use crate::voxel::voxel::SimKind;
 
-// pub mod fire;
+pub mod fire;
+pub mod gas;
 pub mod liquid;
 pub mod semisolid;
 
@@ -30,6 +33,12 @@ impl VoxelPosition {
         Self { chunk_index, voxel_index, chunk_point, voxel_point }
     }
 
+    /// Grid space point of this voxel in the block starting at `block_origin`.
+    #[inline]
+    pub fn world_point(&self, block_origin: IVec3) -> IVec3 {
+        (block_origin + self.chunk_point) * CHUNK_WIDTH as i32 + self.voxel_point
+    }
+
     // pub fn left(mut self) -> Self {
     //     self.voxel_point.x -= 1;
     //     if self.voxel_point.x < 0 {
@@ -48,19 +57,25 @@ impl Voxel {
     pub fn simulate(
         &self,
         view: &mut ChunkView<'_>,
+        block_origin: IVec3,
         voxel_position: VoxelPosition,
         tick: FallingSandTick,
+        liquid_mode: LiquidMode,
     ) {
         match self {
-            Voxel::Sand => {
-                semisolid::simulate_semisolid(view, voxel_position, *self, tick);
+            Voxel::Water(..) | Voxel::Oil(..) | Voxel::Lava(..) => {
+                liquid::simulate_liquid(view, voxel_position, *self, tick, liquid_mode);
             },
-            Voxel::Water(..) | Voxel::Oil(..) => {
-                liquid::simulate_liquid(view, voxel_position, *self, tick);
+            Voxel::Steam(..) | Voxel::Smoke(..) | Voxel::Methane(..) => {
+                gas::simulate_gas(view, block_origin, voxel_position, *self, tick);
+            },
+            Voxel::Fire { voxel_id } => {
+                fire::simulate_fire(view, block_origin, voxel_position, *voxel_id, tick);
+            },
+            // Registry defined voxels only get the simulation kinds that don't need extra data.
+            _ if self.definition().simulation_kind == SimKind::SemiSolid => {
+                semisolid::simulate_semisolid(view, voxel_position, *self, tick);
             },
-            // Voxel::Fire { .. } => {
-            //     fire::simulate_fire(chunks, point, self, &tick);
-            // }
             // Voxel::Dirt => {
             // let point =
             //     SimChunks::point_from_chunk_and_voxel_indices(chunk_point, voxel_index);
@@ -70,3 +85,101 @@ impl Voxel {
         }
     }
 }
+
+/// Single chunk sims shared by the simulation tests.
+#[cfg(test)]
+pub mod fixtures {
+    use bevy::prelude::*;
+
+    use crate::voxel::Voxel;
+    use crate::voxel::simulation::data::{
+        CHUNK_LENGTH, CHUNK_WIDTH, ChunkPoint, SimChunks, linearize,
+    };
+
+    /// Sim of a single chunk at the origin filled with `fill`, with `voxels`
+    /// placed on top.
+    pub fn sim_with(fill: Voxel, voxels: &[(IVec3, Voxel)]) -> SimChunks {
+        let mut chunk = [fill; CHUNK_LENGTH];
+        for (point, voxel) in voxels {
+            chunk[linearize(*point)] = *voxel;
+        }
+
+        let mut sim = SimChunks::new();
+        sim.add_chunk(ChunkPoint(IVec3::ZERO), chunk);
+        sim
+    }
+
+    /// Stone box with air inside from 1..15 on each axis.
+    pub fn stone_box(voxels: &[(IVec3, Voxel)]) -> SimChunks {
+        let mut inside = Vec::new();
+        for x in 1..15 {
+            for y in 1..15 {
+                for z in 1..15 {
+                    inside.push((ivec3(x, y, z), Voxel::Air));
+                }
+            }
+        }
+
+        inside.extend_from_slice(voxels);
+        sim_with(Voxel::Stone, &inside)
+    }
+
+    /// Points in the origin chunk matching `predicate`.
+    pub fn find(sim: &SimChunks, predicate: impl Fn(Voxel) -> bool) -> Vec<IVec3> {
+        let width = CHUNK_WIDTH as i32;
+        let mut found = Vec::new();
+        for x in 0..width {
+            for y in 0..width {
+                for z in 0..width {
+                    let point = ivec3(x, y, z);
+                    if predicate(sim.get_voxel(point).unwrap()) {
+                        found.push(point);
+                    }
+                }
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::voxel::simulation::data::{CHUNK_LENGTH, ChunkPoint};
+    use crate::voxel::simulation::kinds::gas::GasState;
+
+    #[test]
+    pub fn mixed_column_stratifies() {
+        let steam = Voxel::Steam(GasState::PERMANENT);
+        let (water, oil) = (Voxel::Water(default()), Voxel::Oil(default()));
+        // walled in column from y = 1..15, every layer out of order
+        let mixed = [steam, oil, water, Voxel::Sand, steam, Voxel::Air, oil, water, Voxel::Sand];
+
+        for liquid_mode in [LiquidMode::Classic, LiquidMode::Pressure] {
+            let mut chunk = [Voxel::Stone; CHUNK_LENGTH];
+            for y in 1..15 {
+                chunk[linearize(ivec3(7, y, 7))] = Voxel::Air;
+            }
+            for (y, voxel) in (1..).zip(mixed) {
+                chunk[linearize(ivec3(7, y, 7))] = voxel;
+            }
+
+            let mut sim = SimChunks::new();
+            sim.liquid_mode = liquid_mode;
+            sim.add_chunk(ChunkPoint(IVec3::ZERO), chunk);
+            for tick in 1..100 {
+                sim.step(FallingSandTick(tick));
+            }
+
+            let ids =
+                (1..15).map(|y| sim.get_voxel(ivec3(7, y, 7)).unwrap().id()).collect::<Vec<_>>();
+            // heaviest at the bottom
+            let expected = [(Voxel::Sand, 2), (water, 2), (oil, 2), (Voxel::Air, 6), (steam, 2)];
+            let expected = expected
+                .iter()
+                .flat_map(|(voxel, count)| std::iter::repeat_n(voxel.id(), *count))
+                .collect::<Vec<_>>();
+            assert_eq!(ids, expected, "{liquid_mode:?}");
+        }
+    }
+}
@@ -27,7 +27,14 @@ pub fn simulate_semisolid(
         if let Some((relative_voxel_position, relative_voxel)) =
             view.get_relative_voxel(voxel_position, check)
         {
-            if relative_voxel.is_gas() || (check == IVec3::NEG_Y && relative_voxel.is_liquid()) {
+            // only sink straight down through liquids, piles still form under them
+            let sinks = if check == IVec3::NEG_Y {
+                sim_voxel.sinks_through(relative_voxel)
+            } else {
+                relative_voxel.is_gas()
+            };
+
+            if sinks {
                 view.set_voxel(relative_voxel_position, sim_voxel);
                 view.set_voxel(voxel_position, relative_voxel);
                 return;
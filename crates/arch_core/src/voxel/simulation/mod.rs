@@ -6,23 +6,32 @@
 use bevy::ecs::intern::Interned;
 use bevy::ecs::schedule::ScheduleLabel;
 use bevy::prelude::*;
-use bevy_inspector_egui::quick::ResourceInspectorPlugin;
 pub use data::{SimChunk, SimChunks};
 #[cfg(feature = "trace")]
 use tracing::*;
 
 use crate::voxel::commands::SetVoxelParams;
+use crate::voxel::registry::{self, VoxelRegistry};
+use crate::voxel::simulation::checkpoint::SimRewind;
 use crate::voxel::simulation::data::{CHUNK_LENGTH, ChunkPoint};
-use crate::voxel::tree::{DebugTree, VoxelNode};
-use crate::voxel::{Voxel, VoxelCommand, VoxelSet, Voxels};
+use crate::voxel::simulation::kinds::liquid::LiquidMode;
+use crate::voxel::tree::VoxelNode;
+use crate::voxel::{GridCommand, Voxel, VoxelCommand, VoxelSet, Voxels};
 
+pub mod checkpoint;
 pub mod data;
 pub mod debug_dirty;
 pub mod gpu;
+pub mod heat;
+pub mod islands;
 pub mod kinds;
 pub mod morton;
+pub mod pressure;
+pub mod reactions;
+pub mod replay;
 pub mod rle;
 pub mod set;
+pub mod structure;
 
 #[derive(SystemSet, Default, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Reflect)]
 #[reflect(Default, Clone, Debug)]
@@ -58,7 +67,8 @@ impl Plugin for SimPlugin {
             .register_type::<SimSettings>()
             .register_type::<SimChunks>()
             .register_type::<SimStep>()
-            .register_type::<SimRun>();
+            .register_type::<SimRun>()
+            .register_type::<LiquidMode>();
 
         app.insert_resource(FallingSandTick(0));
         app.insert_resource(SimSettings {
@@ -68,12 +78,13 @@ impl Plugin for SimPlugin {
             step_once: false,
             display_modified: false,
             display_flagged: false,
+            display_heat: false,
             sim_threads: 4,
+            sleep_after: 128,
+            checkpoints: 64,
+            rewind: SimRewind::None,
         });
 
-        app.add_plugins(ResourceInspectorPlugin::<SimSettings>::default());
-        app.add_plugins(ResourceInspectorPlugin::<DebugTree>::default());
-
         app.configure_sets(
             self.sim_schedule,
             (
@@ -87,28 +98,43 @@ impl Plugin for SimPlugin {
                 .run_if(SimRun::should_run),
         );
 
-        app.add_systems(self.sim_run_schedule, SimRun::advance_step);
+        app.add_systems(self.sim_run_schedule, (checkpoint::rewind, SimRun::advance_step).chain());
 
-        app.add_systems(self.sim_schedule, spread_updates.in_set(SimStep::FlagDirty))
-            .add_systems(self.sim_schedule, simulate.in_set(SimStep::Simulate))
-            .add_systems(self.sim_schedule, pull_from_tree.in_set(SimStep::PullFromTree))
-            .add_systems(self.sim_schedule, add_sand.in_set(SimStep::AddVoxelsToSim))
-            .add_systems(self.sim_schedule, propagate_to_tree.in_set(SimStep::PropagateToTree));
+        app.add_systems(
+            self.sim_schedule,
+            (checkpoint::save_checkpoints, spread_updates).chain().in_set(SimStep::FlagDirty),
+        )
+        .add_systems(self.sim_schedule, simulate.in_set(SimStep::Simulate))
+        .add_systems(self.sim_schedule, pull_from_tree.in_set(SimStep::PullFromTree))
+        .add_systems(self.sim_schedule, add_sand.in_set(SimStep::AddVoxelsToSim))
+        .add_systems(
+            self.sim_schedule,
+            (propagate_to_tree, evict_quiet_chunks, replay::start_recording, replay::record_hashes)
+                .chain()
+                .in_set(SimStep::PropagateToTree),
+        );
 
         app.add_systems(First, sim_settings.run_if(resource_exists::<ButtonInput<KeyCode>>));
+        app.add_systems(
+            PreUpdate,
+            heat::rebuild_heat_sources
+                .after(registry::rebuild_registry)
+                .run_if(resource_exists_and_changed::<VoxelRegistry>),
+        );
 
         app.add_systems(Startup, || {
             info!("available parallelism: {:?}", std::thread::available_parallelism());
         });
 
+        app.add_plugins(checkpoint::plugin);
         app.add_plugins(data::plugin);
         app.add_plugins(debug_dirty::plugin);
+        app.add_plugins(islands::plugin);
+        app.add_plugins(structure::plugin);
+        app.add_plugins(replay::plugin);
     }
 }
 
-// Make islands of voxels fall if unsupported.
-pub fn islands(mut grids: Query<&mut Voxels>) {}
-
 #[derive(Resource, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Reflect)]
 #[reflect(Resource)]
 pub struct FallingSandTick(pub u32);
@@ -174,8 +200,22 @@ pub struct SimSettings {
     /// Display voxels marked for updates.
     pub display_flagged: bool,
 
+    /// Display heat cells that aren't at ambient temperature.
+    pub display_heat: bool,
+
     /// How many threads for the simulation.
     pub sim_threads: usize,
+
+    /// Ticks a chunk has to be quiet for before it is evicted from the
+    /// simulation back to the tree.
+    pub sleep_after: u32,
+
+    /// Ticks of [checkpoints](checkpoint) to keep for rewinding, 0 turns them
+    /// off.
+    pub checkpoints: usize,
+
+    /// Rewind to an earlier checkpoint, reset once the sim has been rewound.
+    pub rewind: SimRewind,
 }
 
 impl Default for SimSettings {
@@ -189,7 +229,11 @@ impl Default for SimSettings {
             step_once: false,
             display_modified: false,
             display_flagged: false,
+            display_heat: false,
             sim_threads: threads,
+            sleep_after: 128,
+            checkpoints: 64,
+            rewind: SimRewind::None,
         }
     }
 }
@@ -204,45 +248,17 @@ pub fn sim_settings(mut sim_settings: ResMut<SimSettings>, input: Res<ButtonInpu
     }
 }
 
-// Pull relevant chunks from the 64tree into our linear array on startup
-pub fn pull_from_tree(
-    mut grids: Query<(Entity, &Voxels, &mut SimChunks)>,
-    // tick: Res<FallingSandTick>,
-    mut added: Local<bool>,
-) {
-    if *added {
-        return;
-    }
-
-    // TODO: Stop doing this on every chunk every frame, should only do this on
-    // modified chunks.
-    for (_grid_entity, voxels, mut sim_chunks) in &mut grids {
-        // let sim_bounds = IVec3::new(4, 2, 4);
-        let sim_bounds = IVec3::splat(16);
-        for z in 0..sim_bounds.z {
-            for x in 0..sim_bounds.x {
-                for y in 0..sim_bounds.y {
-                    let chunk_point = IVec3::new(x, y, z);
-                    if sim_chunks.from_chunk_point.contains_key(&ChunkPoint(chunk_point)) {
-                        continue;
-                    }
-
-                    let voxels = match voxels.tree.root.get_chunk(chunk_point) {
-                        VoxelNode::Solid { voxel, .. } => Some([*voxel; CHUNK_LENGTH]),
-                        VoxelNode::Leaf { leaf, .. } => Some(**leaf),
-                        _ => None,
-                    };
+/// Pull chunks into the simulation from the tree when a command or a
+/// simulated voxel next to them touches them.
+pub fn pull_from_tree(mut grids: Query<(&Voxels, &mut SimChunks)>) {
+    for (voxels, mut sim_chunks) in &mut grids {
+        let mut activate = std::mem::take(&mut sim_chunks.pending_activation);
+        activate.extend(sim_chunks.touched_chunks());
 
-                    if let Some(voxels) = voxels {
-                        // info!("added chunk to sim: {:?}", chunk_point);
-                        sim_chunks.add_chunk(ChunkPoint(chunk_point), voxels);
-                    }
-                }
-            }
+        for chunk_point in activate {
+            sim_chunks.pull_chunk(&voxels.tree, chunk_point);
         }
     }
-
-    *added = true;
 }
 
 pub fn propagate_to_tree(mut grids: Query<(Entity, &mut Voxels, &SimChunks)>) {
@@ -261,37 +277,57 @@ pub fn propagate_to_tree(mut grids: Query<(Entity, &mut Voxels, &SimChunks)>) {
                 continue;
             }
 
-            match voxels.tree.get_chunk_mut(*chunk_point) {
-                VoxelNode::Solid { .. } => {
-                    voxels.tree.set_chunk_data(*chunk_point, sim_chunk.voxels);
-                },
-                VoxelNode::Leaf { leaf, .. } => {
-                    for voxel_index in sim_chunk.modified.iter() {
-                        leaf[voxel_index] = sim_chunk.voxels[voxel_index];
-                    }
-
-                    // TODO: Be smarter about which chunks need to be updated here
-                    for x in -1..=1 {
-                        for y in -1..=1 {
-                            for z in -1..=1 {
-                                let offset = IVec3::new(x, y, z);
-                                voxels.tree.changed_chunks.insert(chunk_point + offset);
-                            }
-                        }
-                    }
-                },
-                _ => {},
+            voxels.tree.write_sim_chunk(*chunk_point, &sim_chunk.voxels, sim_chunk.modified.iter());
+        }
+    }
+}
+
+/// Evict chunks that have been quiet for [`SimSettings::sleep_after`] ticks
+/// back to the tree.
+pub fn evict_quiet_chunks(
+    settings: Res<SimSettings>,
+    mut grids: Query<(&mut Voxels, &mut SimChunks)>,
+) {
+    for (mut voxels, mut sim_chunks) in &mut grids {
+        for chunk_point in sim_chunks.tick_quiet(settings.sleep_after) {
+            let Some(sim_chunk) = sim_chunks.remove_chunk(chunk_point) else {
+                continue;
+            };
+
+            // changes are propagated every tick, this only catches anything that slipped by
+            let chunk = voxels.tree.get_chunk(*chunk_point);
+            let out_of_sync = (0..CHUNK_LENGTH)
+                .filter(|&voxel_index| {
+                    let tree_voxel = match chunk {
+                        Some(VoxelNode::Solid { voxel, .. }) => Some(*voxel),
+                        Some(VoxelNode::Leaf { leaf, .. }) => Some(leaf[voxel_index]),
+                        _ => None,
+                    };
+                    tree_voxel != Some(sim_chunk.voxels[voxel_index])
+                })
+                .collect::<Vec<_>>();
+            if !out_of_sync.is_empty() {
+                voxels.tree.write_sim_chunk(
+                    *chunk_point,
+                    &sim_chunk.voxels,
+                    out_of_sync.into_iter(),
+                );
             }
         }
     }
 }
 
-pub fn add_sand(mut voxel_commands: MessageWriter<VoxelCommand>) {
-    voxel_commands.write(VoxelCommand::SetVoxel {
-        point: IVec3::new(10, 20, 10),
-        voxel: Voxel::Sand,
-        params: SetVoxelParams { can_replace: VoxelSet::AIR },
-    });
+pub fn add_sand(
+    grids: Query<Entity, With<SimChunks>>,
+    mut voxel_commands: MessageWriter<GridCommand>,
+) {
+    for grid_entity in &grids {
+        voxel_commands.write(GridCommand::new(grid_entity, VoxelCommand::SetVoxel {
+            point: IVec3::new(10, 20, 10),
+            voxel: Voxel::Sand,
+            params: SetVoxelParams { can_replace: VoxelSet::AIR },
+        }));
+    }
 }
 
 pub fn spread_updates(mut grids: Query<(Entity, &mut SimChunks)>) {
@@ -325,5 +361,8 @@ pub fn simulate(mut grids: Query<(Entity, &mut SimChunks)>, mut sim_tick: ResMut
         //     let block_span = info_span!("block_simulation").entered();
         //     chunk_view.simulate(*sim_tick);
         // });
+
+        sim_chunks.update_heat();
+        sim_chunks.level_liquids();
     }
 }
@@ -0,0 +1,228 @@
+//! Levelling liquids for [`LiquidMode::Pressure`].
+//!
+//! Liquid in this mode only falls on its own. Each tick the connected bodies of
+//! liquid that had something happen to them are flood filled, and voxels on
+//! their surface are swapped with the lowest open cells around the body as long
+//! as those are strictly lower. Levels even out through U-bends and between
+//! connected basins, the amount of liquid never changes and a body stops moving
+//! once its surface is within a voxel of level.
+
+use std::cmp::Reverse;
+use std::collections::VecDeque;
+
+use bevy::platform::collections::HashSet;
+use bevy::prelude::*;
+#[cfg(feature = "trace")]
+use tracing::*;
+
+use crate::voxel::simulation::SimChunks;
+use crate::voxel::simulation::islands::NEIGHBORS;
+use crate::voxel::simulation::kinds::liquid::LiquidMode;
+
+/// Bodies bigger than this are assumed to be level already, keeps seas from
+/// being searched every tick.
+pub const MAX_BODY_VOXELS: usize = 1 << 16;
+
+/// Most voxels moved in a body each tick, so levelling takes a little while
+/// instead of happening all at once.
+pub const MOVES_PER_TICK: usize = 8;
+
+/// Connected voxels of a single liquid.
+#[derive(Debug, Clone, Default)]
+pub struct LiquidBody {
+    /// Liquid voxels with gas right above them.
+    pub surface: Vec<IVec3>,
+    /// Gas next to the body that the liquid could move into.
+    pub open: Vec<IVec3>,
+}
+
+/// Flood fill the body of liquid at `seed`, marking its voxels in `visited`.
+///
+/// `None` if `seed` isn't a liquid or the body is bigger than
+/// [`MAX_BODY_VOXELS`].
+pub fn liquid_body(
+    sim_chunks: &SimChunks,
+    seed: IVec3,
+    visited: &mut HashSet<IVec3>,
+) -> Option<LiquidBody> {
+    let liquid = sim_chunks.get_voxel(seed).filter(|voxel| voxel.is_liquid())?;
+    visited.insert(seed);
+
+    let mut body = LiquidBody::default();
+    let mut open = HashSet::new();
+    let mut queue = VecDeque::from([seed]);
+    let mut size = 0;
+    while let Some(point) = queue.pop_front() {
+        size += 1;
+        if size > MAX_BODY_VOXELS {
+            return None;
+        }
+
+        for offset in NEIGHBORS {
+            let neighbor = point + offset;
+            // chunks outside of the sim are walls
+            let Some(voxel) = sim_chunks.get_voxel(neighbor) else {
+                continue;
+            };
+
+            if voxel.id() == liquid.id() {
+                if visited.insert(neighbor) {
+                    queue.push_back(neighbor);
+                }
+            } else if voxel.is_gas() {
+                if open.insert(neighbor) {
+                    body.open.push(neighbor);
+                }
+
+                if offset == IVec3::Y {
+                    body.surface.push(point);
+                }
+            }
+        }
+    }
+
+    Some(body)
+}
+
+impl SimChunks {
+    /// Level the liquid bodies that were updated this tick, see the [module
+    /// docs](self).
+    pub fn level_liquids(&mut self) {
+        #[cfg(feature = "trace")]
+        let level_liquids_span = info_span!("level_liquids").entered();
+
+        if self.liquid_mode != LiquidMode::Pressure {
+            return;
+        }
+
+        let mut seeds = Vec::new();
+        for (chunk_point, (chunk_key, dirty_key)) in &self.from_chunk_point {
+            let chunk = &self.chunks[*chunk_key];
+            for voxel_index in self.dirty[*dirty_key].iter().chain(chunk.modified.iter()) {
+                if chunk.voxels[voxel_index].is_liquid() {
+                    seeds.push(Self::point_from_chunk_and_voxel_indices(*chunk_point, voxel_index));
+                }
+            }
+        }
+
+        // bodies are levelled in a fixed order so the sim stays deterministic
+        seeds.sort_by_key(|point| point.to_array());
+        seeds.dedup();
+
+        let mut visited = HashSet::new();
+        for seed in seeds {
+            if visited.contains(&seed) {
+                continue;
+            }
+
+            let Some(mut body) = liquid_body(self, seed, &mut visited) else {
+                continue;
+            };
+
+            body.surface.sort_by_key(|point| (Reverse(point.y), point.x, point.z));
+            body.open.sort_by_key(|point| (point.y, point.x, point.z));
+            for (from, to) in body.surface.iter().zip(&body.open).take(MOVES_PER_TICK) {
+                if to.y >= from.y {
+                    break;
+                }
+
+                let liquid = self.get_voxel(*from).unwrap();
+                let gas = self.get_voxel(*to).unwrap();
+                self.set_voxel(*to, liquid);
+                self.set_voxel(*from, gas);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::voxel::simulation::FallingSandTick;
+    use crate::voxel::simulation::data::ChunkPoint;
+    use crate::voxel::{Voxel, VoxelAabb, Voxels};
+
+    fn pressure_sim(grid: &Voxels, chunk_points: &[IVec3]) -> SimChunks {
+        let mut sim_chunks = SimChunks::new();
+        sim_chunks.liquid_mode = LiquidMode::Pressure;
+        for chunk_point in chunk_points {
+            sim_chunks.pull_chunk(&grid.tree, ChunkPoint(*chunk_point));
+        }
+        sim_chunks
+    }
+
+    fn water_count(sim_chunks: &SimChunks) -> usize {
+        sim_chunks
+            .chunks
+            .values()
+            .flat_map(|chunk| chunk.voxels.iter())
+            .filter(|voxel| matches!(voxel, Voxel::Water(..)))
+            .count()
+    }
+
+    /// Highest water in the 2x2 column at `x`.
+    fn level(sim_chunks: &SimChunks, x: i32) -> i32 {
+        (0..32)
+            .rev()
+            .find(|y| {
+                (x..=x + 1).any(|x| {
+                    (1..=2).any(|z| {
+                        matches!(sim_chunks.get_voxel(IVec3::new(x, *y, z)), Some(Voxel::Water(..)))
+                    })
+                })
+            })
+            .unwrap()
+    }
+
+    #[test]
+    pub fn u_tube_levels() {
+        let water = Voxel::Water(default());
+        let mut grid = Voxels::new(IVec3::splat(32));
+        grid.fill_aabb(VoxelAabb::new(IVec3::ZERO, IVec3::new(15, 28, 3)), Voxel::Base);
+        // arms at x = 2 and x = 10 joined by a channel along the bottom
+        grid.fill_aabb(VoxelAabb::new(IVec3::new(2, 2, 1), IVec3::new(3, 28, 2)), Voxel::Air);
+        grid.fill_aabb(VoxelAabb::new(IVec3::new(10, 2, 1), IVec3::new(11, 28, 2)), Voxel::Air);
+        grid.fill_aabb(VoxelAabb::new(IVec3::new(2, 2, 1), IVec3::new(11, 3, 2)), water);
+        grid.fill_aabb(VoxelAabb::new(IVec3::new(2, 4, 1), IVec3::new(3, 20, 2)), water);
+
+        let mut sim_chunks = pressure_sim(&grid, &[IVec3::ZERO, IVec3::Y]);
+        let before = water_count(&sim_chunks);
+        for tick in 1..=100 {
+            sim_chunks.step(FallingSandTick(tick));
+            assert_eq!(water_count(&sim_chunks), before);
+        }
+
+        let (left, right) = (level(&sim_chunks, 2), level(&sim_chunks, 10));
+        assert!(left.abs_diff(right) <= 1, "left arm at {left}, right arm at {right}");
+        assert!(left < 20);
+
+        // and stays there
+        let settled = sim_chunks.chunk_hashes();
+        for tick in 101..=120 {
+            sim_chunks.step(FallingSandTick(tick));
+        }
+        assert_eq!(sim_chunks.chunk_hashes(), settled);
+    }
+
+    #[test]
+    pub fn pile_flattens() {
+        let water = Voxel::Water(default());
+        let mut grid = Voxels::new(IVec3::splat(16));
+        grid.fill_aabb(VoxelAabb::new(IVec3::ZERO, IVec3::new(15, 0, 15)), Voxel::Base);
+        grid.fill_aabb(VoxelAabb::new(IVec3::new(6, 1, 6), IVec3::new(8, 3, 8)), water);
+
+        let mut sim_chunks = pressure_sim(&grid, &[IVec3::ZERO]);
+        for tick in 1..=100 {
+            sim_chunks.step(FallingSandTick(tick));
+        }
+
+        assert_eq!(water_count(&sim_chunks), 27);
+        for chunk in sim_chunks.chunks.values() {
+            for (voxel_index, voxel) in chunk.voxels.iter().enumerate() {
+                if matches!(voxel, Voxel::Water(..)) {
+                    assert_eq!(crate::voxel::simulation::data::delinearize(voxel_index).y, 1);
+                }
+            }
+        }
+    }
+}
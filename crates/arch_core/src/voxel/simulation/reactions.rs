@@ -0,0 +1,266 @@
+//! Declarative reactions between neighbouring voxels, see
+//! [`Interactions::reactions`](crate::voxel::voxel::Interactions::reactions).
+
+use bevy::prelude::*;
+
+use crate::voxel::Voxel;
+use crate::voxel::simulation::FallingSandTick;
+use crate::voxel::simulation::data::ChunkView;
+use crate::voxel::simulation::kinds::VoxelPosition;
+use crate::voxel::simulation::kinds::fire::ignite;
+use crate::voxel::voxel::Reaction;
+
+/// Face neighbours checked for reactions.
+pub const REACTION_NEIGHBORS: [IVec3; 6] =
+    [IVec3::Y, IVec3::NEG_Y, IVec3::X, IVec3::NEG_X, IVec3::Z, IVec3::NEG_Z];
+
+/// Deterministic pseudo-random number for a voxel on a given tick.
+///
+/// Same tick + point + salt always gives the same roll, so the simulation
+/// can be replayed.
+#[inline]
+pub fn roll(tick: FallingSandTick, point: IVec3, salt: u32) -> u32 {
+    // based on the `lowbias32` integer hash
+    let mut hash = tick.0
+        ^ (point.x as u32).wrapping_mul(0x8DA6_B343)
+        ^ (point.y as u32).wrapping_mul(0xD816_3841)
+        ^ (point.z as u32).wrapping_mul(0xCB1A_B31F)
+        ^ salt.wrapping_mul(0x165_667B1);
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x7FEB_352D);
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x846C_A68B);
+    hash ^= hash >> 16;
+    hash
+}
+
+/// Roll against a `0.0..=1.0` chance.
+#[inline]
+pub fn roll_chance(tick: FallingSandTick, point: IVec3, salt: u32, chance: f32) -> bool {
+    if chance >= 1.0 {
+        return true;
+    }
+
+    (roll(tick, point, salt) as f32 / u32::MAX as f32) < chance
+}
+
+/// Try each of the voxel's reactions against its neighbours.
+///
+/// Returns true if a reaction happened. Voxels that could react but lost the
+/// roll are kept awake so they try again next tick.
+#[inline]
+pub fn react(
+    view: &mut ChunkView<'_>,
+    block_origin: IVec3,
+    position: VoxelPosition,
+    voxel: Voxel,
+    tick: FallingSandTick,
+) -> bool {
+    let reactions = voxel.definition().interactions.reactions;
+    react_with(view, block_origin, position, reactions, tick)
+}
+
+/// [`react`] with an explicit reaction table.
+#[inline]
+pub fn react_with(
+    view: &mut ChunkView<'_>,
+    block_origin: IVec3,
+    position: VoxelPosition,
+    reactions: &[Reaction],
+    tick: FallingSandTick,
+) -> bool {
+    if reactions.is_empty() {
+        return false;
+    }
+
+    #[cfg(feature = "trace")]
+    let react_span = info_span!("react").entered();
+
+    let world_point = position.world_point(block_origin);
+
+    let mut candidate = false;
+    for (neighbor_index, &offset) in REACTION_NEIGHBORS.iter().enumerate() {
+        let Some((neighbor_position, neighbor)) = view.get_relative_voxel(position, offset) else {
+            continue;
+        };
+
+        for (reaction_index, reaction) in reactions.iter().enumerate() {
+            if reaction.with.id() != neighbor.id() {
+                continue;
+            }
+
+            candidate = true;
+            let salt = (reaction_index * REACTION_NEIGHBORS.len() + neighbor_index) as u32;
+            if !roll_chance(tick, world_point, salt, reaction.chance) {
+                continue;
+            }
+
+            if let Some(other_becomes) = reaction.other_becomes {
+                view.set_voxel(neighbor_position, other_becomes);
+            }
+            if let Some(becomes) = reaction.becomes {
+                let becomes = match becomes {
+                    // catching fire keeps track of what is burning
+                    Voxel::Fire { .. } => {
+                        view.get_voxel(position).and_then(ignite).unwrap_or(becomes)
+                    },
+                    _ => becomes,
+                };
+                view.set_voxel(position, becomes);
+            }
+            return true;
+        }
+    }
+
+    if candidate {
+        view.mark_modified(position);
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::voxel::simulation::data::{ChunkPoint, SimChunk, SimChunks, linearize};
+    use crate::voxel::simulation::kinds::fixtures::sim_with;
+
+    /// Stone box with water on lava, nothing can move so only the reaction
+    /// changes anything.
+    fn water_on_lava() -> SimChunks {
+        sim_with(Voxel::Stone, &[
+            (ivec3(1, 1, 1), Voxel::Lava(default())),
+            (ivec3(1, 2, 1), Voxel::Water(default())),
+        ])
+    }
+
+    /// Run until the lava reacts, returning the tick it happened on.
+    fn ticks_until_cooled(sim: &mut SimChunks, seed: u32) -> Option<u32> {
+        for tick in seed..seed + 64 {
+            sim.step(FallingSandTick(tick));
+            if sim.get_voxel(ivec3(1, 1, 1)) == Some(Voxel::Stone) {
+                return Some(tick);
+            }
+        }
+
+        None
+    }
+
+    const SAND_TO_STONE: Reaction = Reaction {
+        with: Voxel::Fire { voxel_id: 0 },
+        becomes: Some(Voxel::Stone),
+        other_becomes: None,
+        chance: 0.1,
+    };
+
+    /// Sand next to fire in an otherwise empty chunk.
+    fn sand_by_fire() -> SimChunk {
+        let mut chunk = SimChunk::new(ChunkPoint(IVec3::ZERO));
+        chunk.set(linearize(ivec3(1, 1, 1)), Voxel::Sand);
+        chunk.set(linearize(ivec3(2, 1, 1)), Voxel::Fire { voxel_id: 0 });
+        chunk.modified.clear();
+        chunk
+    }
+
+    /// React every tick starting at `seed` until the sand changes, returning
+    /// the tick it happened on.
+    fn ticks_until_reaction(chunk: &mut SimChunk, seed: u32) -> Option<u32> {
+        let position = VoxelPosition::from_points(IVec3::ZERO, ivec3(1, 1, 1));
+        for tick in seed..seed + 256 {
+            let mut view = ChunkView { chunks: std::array::from_fn(|_| None) };
+            view.chunks[0] = Some(&mut *chunk);
+            if react_with(&mut view, IVec3::ZERO, position, &[SAND_TO_STONE], FallingSandTick(tick))
+            {
+                return Some(tick);
+            }
+        }
+
+        None
+    }
+
+    #[test]
+    pub fn roll_deterministic() {
+        let point = ivec3(5, -3, 12);
+        assert_eq!(roll(FallingSandTick(7), point, 0), roll(FallingSandTick(7), point, 0));
+        assert_ne!(roll(FallingSandTick(7), point, 0), roll(FallingSandTick(8), point, 0));
+        assert_ne!(roll(FallingSandTick(7), point, 0), roll(FallingSandTick(7), point, 1));
+        assert!(roll_chance(FallingSandTick(7), point, 0, 1.0));
+        assert!(!roll_chance(FallingSandTick(7), point, 0, 0.0));
+    }
+
+    #[test]
+    pub fn water_cools_lava() {
+        let mut sim = water_on_lava();
+        let tick = ticks_until_cooled(&mut sim, 0).expect("water never reacted with lava");
+        // fixed seed, fixed outcome
+        assert_eq!(tick, 0);
+
+        assert_eq!(sim.get_voxel(ivec3(1, 1, 1)), Some(Voxel::Stone));
+        assert!(matches!(sim.get_voxel(ivec3(1, 2, 1)), Some(Voxel::Steam(..))));
+
+        // same seed, same result
+        let mut replay = water_on_lava();
+        assert_eq!(ticks_until_cooled(&mut replay, 0), Some(tick));
+    }
+
+    #[test]
+    pub fn reaction_waits_for_roll() {
+        let mut sim = water_on_lava();
+        let tick = ticks_until_cooled(&mut sim, 1000).unwrap();
+
+        // nothing happened before the winning roll, even though the voxels have
+        // nothing else to do
+        let mut sim = water_on_lava();
+        for before in 1000..tick {
+            sim.step(FallingSandTick(before));
+            assert!(matches!(sim.get_voxel(ivec3(1, 1, 1)), Some(Voxel::Lava(..))));
+        }
+        sim.step(FallingSandTick(tick));
+        assert_eq!(sim.get_voxel(ivec3(1, 1, 1)), Some(Voxel::Stone));
+    }
+
+    #[test]
+    pub fn reaction_fires_deterministically() {
+        let mut chunk = sand_by_fire();
+        let tick = ticks_until_reaction(&mut chunk, 0).expect("sand never reacted with fire");
+        // fixed seed, fixed outcome
+        assert_eq!(tick, 13);
+        assert_eq!(chunk.voxels[linearize(ivec3(1, 1, 1))], Voxel::Stone);
+        assert_eq!(chunk.voxels[linearize(ivec3(2, 1, 1))], Voxel::Fire { voxel_id: 0 });
+
+        let mut replay = sand_by_fire();
+        assert_eq!(ticks_until_reaction(&mut replay, 0), Some(tick));
+    }
+
+    #[test]
+    pub fn no_reaction_without_neighbor() {
+        let mut chunk = sand_by_fire();
+        chunk.set(linearize(ivec3(2, 1, 1)), Voxel::Air);
+        chunk.modified.clear();
+        assert_eq!(ticks_until_reaction(&mut chunk, 0), None);
+        assert!(!chunk.modified.any_set());
+    }
+
+    #[test]
+    pub fn lost_roll_stays_awake() {
+        let mut chunk = sand_by_fire();
+        let tick = ticks_until_reaction(&mut chunk, 0).unwrap();
+
+        let mut chunk = sand_by_fire();
+        let position = VoxelPosition::from_points(IVec3::ZERO, ivec3(1, 1, 1));
+        for before in 0..tick {
+            let mut view = ChunkView { chunks: std::array::from_fn(|_| None) };
+            view.chunks[0] = Some(&mut chunk);
+            let reacted = react_with(
+                &mut view,
+                IVec3::ZERO,
+                position,
+                &[SAND_TO_STONE],
+                FallingSandTick(before),
+            );
+            assert!(!reacted);
+            assert!(chunk.modified.get(position.voxel_index));
+            assert_eq!(chunk.voxels[position.voxel_index], Voxel::Sand);
+        }
+    }
+}
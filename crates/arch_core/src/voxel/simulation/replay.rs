@@ -0,0 +1,547 @@
+//! Recording and replaying the falling sand simulation.
+//!
+//! The simulation is deterministic: blocks are simulated in parallel but never
+//! share chunks, and randomness comes from the tick and voxel position. Given
+//! the same starting grid and the same commands on the same ticks it ends every
+//! tick in the same state, no matter how rayon schedules the blocks.
+//!
+//! [`SimRecorder`] captures the [`VoxelCommand`]s applied to a grid along with
+//! the [`FallingSandTick`] they were applied on, [`SimReplay`] runs them back
+//! through a headless [`SimPlugin`], and the [`TickHash`]es of both can be
+//! compared with [`first_divergence`] to find the tick and chunks where they
+//! split.
+//!
+//! Only commands are recorded, systems that edit the grid directly (like
+//! [islands](super::islands) and [structure](super::structure)) will show up as
+//! a divergence.
+
+use std::collections::VecDeque;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use bevy::ecs::schedule::{ExecutorKind, ScheduleLabel};
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::voxel::commands::{apply_grid_tree, update_command_messages};
+use crate::voxel::save::{self, SaveError};
+use crate::voxel::simulation::data::ChunkPoint;
+use crate::voxel::simulation::kinds::liquid::LiquidMode;
+use crate::voxel::simulation::{FallingSandTick, SimChunks, SimPlugin, SimSettings, SimStep};
+use crate::voxel::tree::VoxelTree;
+use crate::voxel::{GridCommand, VoxelCommand, Voxels};
+
+pub fn plugin(app: &mut App) {
+    app.init_resource::<SimRecorder>();
+}
+
+/// When a recorded command was applied.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecordPhase {
+    /// Applied to the sim during [`SimStep::AddVoxelsToSim`] of the tick.
+    Sim,
+    /// Applied to the tree after the tick, see
+    /// [`apply_tree`](crate::voxel::commands::apply_tree).
+    Tree,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedCommand {
+    pub tick: u32,
+    pub phase: RecordPhase,
+    pub command: VoxelCommand,
+}
+
+/// State of the simulation at the end of a tick.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TickHash {
+    pub tick: u32,
+    /// Hash of all of `chunks`.
+    pub hash: u64,
+    /// See [`SimChunks::chunk_hashes`].
+    pub chunks: Vec<(IVec3, u64)>,
+}
+
+impl TickHash {
+    pub fn new(tick: FallingSandTick, sim_chunks: &SimChunks) -> Self {
+        let chunks = sim_chunks.chunk_hashes();
+        Self { tick: tick.0, hash: fxhash::hash64(&chunks), chunks }
+    }
+
+    /// Chunks that hash differently in `other`, including the ones only one of
+    /// them is simulating.
+    pub fn diverged_chunks(&self, other: &TickHash) -> Vec<IVec3> {
+        let ours = self.chunks.iter().copied().collect::<HashMap<_, _>>();
+        let theirs = other.chunks.iter().copied().collect::<HashMap<_, _>>();
+
+        let mut diverged = ours
+            .iter()
+            .filter(|(chunk_point, hash)| theirs.get(*chunk_point) != Some(*hash))
+            .map(|(chunk_point, _)| *chunk_point)
+            .chain(theirs.keys().filter(|chunk_point| !ours.contains_key(*chunk_point)).copied())
+            .collect::<Vec<_>>();
+        diverged.sort_by_key(|chunk_point| chunk_point.to_array());
+        diverged
+    }
+}
+
+/// Where two runs of the simulation stopped matching.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub tick: u32,
+    pub chunks: Vec<IVec3>,
+}
+
+/// First tick where `expected` and `actual` differ, ticks only one of them has
+/// are skipped.
+pub fn first_divergence(expected: &[TickHash], actual: &[TickHash]) -> Option<Divergence> {
+    let actual = actual.iter().map(|hash| (hash.tick, hash)).collect::<HashMap<_, _>>();
+    for expected in expected {
+        let Some(actual) = actual.get(&expected.tick) else {
+            continue;
+        };
+
+        if expected.hash != actual.hash {
+            return Some(Divergence {
+                tick: expected.tick,
+                chunks: expected.diverged_chunks(actual),
+            });
+        }
+    }
+
+    None
+}
+
+#[derive(Error, Debug)]
+pub enum RecordingError {
+    #[error("could not read/write sim recording: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid sim recording grid: {0}")]
+    Grid(#[from] SaveError),
+    #[error("could not write sim recording log: {0}")]
+    Serialize(#[from] ron::Error),
+    #[error("could not parse sim recording log: {0}")]
+    Deserialize(#[from] ron::error::SpannedError),
+}
+
+/// Everything needed to replay a grid's simulation.
+#[derive(Debug, Clone)]
+pub struct SimRecording {
+    /// Grid at the start of the recording.
+    pub grid: Voxels,
+    pub log: RecordingLog,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecordingLog {
+    /// Tick the recording started at the end of.
+    pub start_tick: u32,
+    /// Chunks being simulated at the start, see [`restart_sim`].
+    pub active_chunks: Vec<IVec3>,
+    /// [`SimSettings::sleep_after`] while recording.
+    pub sleep_after: u32,
+    /// [`SimChunks::liquid_mode`] of the grid.
+    #[serde(default)]
+    pub liquid_mode: LiquidMode,
+    /// Commands in the order they were applied.
+    pub commands: Vec<RecordedCommand>,
+    /// Hash at the end of every tick, starting with `start_tick`.
+    pub hashes: Vec<TickHash>,
+}
+
+impl SimRecording {
+    /// Write the grid in the [save](save::save) format followed by the log as
+    /// ron.
+    pub fn save(&self, writer: &mut impl Write) -> Result<(), RecordingError> {
+        save::save(&self.grid, writer)?;
+        writer.write_all(ron::to_string(&self.log)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Read a recording written by [`SimRecording::save`].
+    pub fn load(reader: &mut impl Read) -> Result<Self, RecordingError> {
+        let grid = save::load(reader)?;
+        let mut log = String::new();
+        reader.read_to_string(&mut log)?;
+        Ok(Self { grid, log: ron::from_str(&log)? })
+    }
+
+    pub fn save_file(&self, path: impl AsRef<Path>) -> Result<(), RecordingError> {
+        let mut writer = BufWriter::new(std::fs::File::create(path)?);
+        self.save(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load_file(path: impl AsRef<Path>) -> Result<Self, RecordingError> {
+        Self::load(&mut BufReader::new(std::fs::File::open(path)?))
+    }
+}
+
+/// Records the commands applied to a single grid, see the [module
+/// docs](self).
+#[derive(Resource, Default)]
+pub struct SimRecorder {
+    grid_entity: Option<Entity>,
+    recording: Option<SimRecording>,
+}
+
+impl SimRecorder {
+    /// Start recording `grid_entity` at the end of the next sim tick, any
+    /// current recording is dropped.
+    ///
+    /// The grid's sim is restarted from its tree when the recording starts,
+    /// see [`restart_sim`].
+    pub fn start(&mut self, grid_entity: Entity) {
+        self.grid_entity = Some(grid_entity);
+        self.recording = None;
+    }
+
+    /// Stop recording, returning what was recorded.
+    pub fn stop(&mut self) -> Option<SimRecording> {
+        self.grid_entity = None;
+        self.recording.take()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Record `command` being applied to `grid_entity`, ignored unless that
+    /// grid is being recorded.
+    pub fn record(
+        &mut self,
+        grid_entity: Entity,
+        phase: RecordPhase,
+        tick: FallingSandTick,
+        command: &VoxelCommand,
+    ) {
+        if self.grid_entity != Some(grid_entity) {
+            return;
+        }
+
+        if let Some(recording) = &mut self.recording {
+            recording.log.commands.push(RecordedCommand {
+                tick: tick.0,
+                phase,
+                command: command.clone(),
+            });
+        }
+    }
+}
+
+/// Fresh sim with `active_chunks` pulled from `tree`, flowing liquids the
+/// `liquid_mode` way.
+///
+/// Heat and pending updates aren't part of the tree, so recordings restart the
+/// sim from here on both sides.
+pub fn restart_sim(
+    tree: &VoxelTree,
+    active_chunks: &[IVec3],
+    liquid_mode: LiquidMode,
+) -> SimChunks {
+    let mut sim_chunks = SimChunks::new();
+    sim_chunks.liquid_mode = liquid_mode;
+    for chunk_point in active_chunks {
+        sim_chunks.pull_chunk(tree, ChunkPoint(*chunk_point));
+    }
+    sim_chunks
+}
+
+/// Begin a requested recording at the end of the tick.
+pub fn start_recording(
+    mut recorder: ResMut<SimRecorder>,
+    tick: Res<FallingSandTick>,
+    settings: Res<SimSettings>,
+    mut grids: Query<(&Voxels, &mut SimChunks)>,
+) {
+    let Some(grid_entity) = recorder.grid_entity else {
+        return;
+    };
+
+    if recorder.recording.is_some() {
+        return;
+    }
+
+    let Ok((voxels, mut sim_chunks)) = grids.get_mut(grid_entity) else {
+        warn!("cannot record grid {:?} without a sim", grid_entity);
+        recorder.grid_entity = None;
+        return;
+    };
+
+    let mut active_chunks = sim_chunks
+        .from_chunk_point
+        .keys()
+        .chain(&sim_chunks.pending_activation)
+        .map(|chunk_point| chunk_point.0)
+        .collect::<Vec<_>>();
+    active_chunks.sort_by_key(|chunk_point| chunk_point.to_array());
+    active_chunks.dedup();
+
+    let liquid_mode = sim_chunks.liquid_mode;
+    *sim_chunks = restart_sim(&voxels.tree, &active_chunks, liquid_mode);
+    recorder.recording = Some(SimRecording {
+        grid: voxels.snapshot(),
+        log: RecordingLog {
+            start_tick: tick.0,
+            active_chunks,
+            sleep_after: settings.sleep_after,
+            liquid_mode,
+            commands: Vec::new(),
+            hashes: Vec::new(),
+        },
+    });
+}
+
+/// Hash the recorded grid at the end of every tick.
+pub fn record_hashes(
+    mut recorder: ResMut<SimRecorder>,
+    tick: Res<FallingSandTick>,
+    grids: Query<&SimChunks>,
+) {
+    let Some(grid_entity) = recorder.grid_entity else {
+        return;
+    };
+
+    let Some(recording) = &mut recorder.recording else {
+        return;
+    };
+
+    if let Ok(sim_chunks) = grids.get(grid_entity) {
+        recording.log.hashes.push(TickHash::new(*tick, sim_chunks));
+    }
+}
+
+#[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ReplayTick;
+
+#[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ReplayRun;
+
+/// Recorded commands that haven't been replayed yet.
+#[derive(Resource)]
+struct ReplayCommands {
+    grid_entity: Entity,
+    commands: VecDeque<RecordedCommand>,
+}
+
+impl ReplayCommands {
+    /// Pop the commands for `phase` of `tick`.
+    fn take(&mut self, phase: RecordPhase, tick: FallingSandTick) -> Vec<VoxelCommand> {
+        let mut commands = Vec::new();
+        while let Some(next) = self.commands.front() {
+            if next.phase != phase || next.tick > tick.0 {
+                break;
+            }
+
+            commands.push(self.commands.pop_front().unwrap().command);
+        }
+        commands
+    }
+}
+
+fn apply_replayed_sim(
+    tick: Res<FallingSandTick>,
+    mut replay: ResMut<ReplayCommands>,
+    mut grids: Query<&mut SimChunks>,
+) {
+    let Ok(mut sim_chunks) = grids.get_mut(replay.grid_entity) else {
+        return;
+    };
+
+    for command in replay.take(RecordPhase::Sim, *tick) {
+        command.apply_sim(&mut sim_chunks);
+    }
+}
+
+/// Headless [`SimPlugin`] driven by a [`SimRecording`].
+pub struct SimReplay {
+    pub app: App,
+    pub grid_entity: Entity,
+}
+
+impl SimReplay {
+    pub fn new(recording: &SimRecording) -> Self {
+        let log = &recording.log;
+
+        let mut app = App::new();
+        // systems run on this thread so the sim uses whichever rayon pool it's in
+        app.init_schedule(ReplayTick);
+        app.edit_schedule(ReplayTick, |schedule| {
+            schedule.set_executor_kind(ExecutorKind::SingleThreaded);
+        });
+        app.add_plugins(SimPlugin {
+            sim_schedule: ReplayTick.intern(),
+            sim_run_schedule: ReplayRun.intern(),
+        });
+
+        // the sim's own commands were recorded, just drop them
+        app.init_resource::<Messages<GridCommand>>();
+        app.add_systems(
+            ReplayTick,
+            (apply_replayed_sim, update_command_messages).in_set(SimStep::AddVoxelsToSim),
+        );
+
+        app.insert_resource(FallingSandTick(log.start_tick));
+        app.insert_resource(SimSettings {
+            sleep_after: log.sleep_after,
+            checkpoints: 0,
+            ..default()
+        });
+
+        let grid = recording.grid.snapshot();
+        let sim_chunks = restart_sim(&grid.tree, &log.active_chunks, log.liquid_mode);
+        let grid_entity = app.world_mut().spawn((grid, sim_chunks)).id();
+        app.insert_resource(ReplayCommands {
+            grid_entity,
+            commands: log.commands.iter().cloned().collect(),
+        });
+
+        Self { app, grid_entity }
+    }
+
+    /// Replay a whole recording, returning a hash for every tick like
+    /// [`RecordingLog::hashes`].
+    pub fn run(recording: &SimRecording) -> Vec<TickHash> {
+        let mut replay = Self::new(recording);
+        let mut hashes = vec![replay.hash()];
+        while hashes.len() < recording.log.hashes.len() {
+            hashes.push(replay.step());
+        }
+        hashes
+    }
+
+    /// Apply the tree commands from after the last tick, then run the next
+    /// tick.
+    pub fn step(&mut self) -> TickHash {
+        let world = self.app.world_mut();
+        let tick = *world.resource::<FallingSandTick>();
+        let commands = world.resource_mut::<ReplayCommands>().take(RecordPhase::Tree, tick);
+
+        let mut grids = world.query::<(&mut Voxels, &mut SimChunks)>();
+        let (mut voxels, mut sim_chunks) = grids.get_mut(world, self.grid_entity).unwrap();
+        let mut broken = Vec::new();
+        for command in commands {
+            apply_grid_tree(&mut voxels, Some(&mut *sim_chunks), &command, &mut broken);
+            broken.clear();
+        }
+
+        world.run_schedule(ReplayTick);
+        world.run_schedule(ReplayRun);
+        self.hash()
+    }
+
+    /// Hash of the sim as it is now.
+    pub fn hash(&self) -> TickHash {
+        let world = self.app.world();
+        let tick = *world.resource::<FallingSandTick>();
+        TickHash::new(tick, world.get::<SimChunks>(self.grid_entity).unwrap())
+    }
+
+    pub fn voxels(&self) -> &Voxels {
+        self.app.world().get::<Voxels>(self.grid_entity).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sdf::{self, Sdf};
+    use crate::voxel::commands::SetVoxelsSdfParams;
+    use crate::voxel::{Voxel, VoxelAabb, VoxelSet};
+
+    fn recording() -> SimRecording {
+        let mut grid = Voxels::new(IVec3::splat(32));
+        grid.fill_aabb(VoxelAabb::new(IVec3::ZERO, IVec3::new(31, 0, 31)), Voxel::Base);
+        grid.fill_aabb(VoxelAabb::new(IVec3::new(4, 1, 4), IVec3::new(12, 10, 12)), Voxel::Sand);
+        grid.fill_aabb(
+            VoxelAabb::new(IVec3::new(18, 4, 18), IVec3::new(26, 12, 26)),
+            Voxel::Water(default()),
+        );
+
+        // commands reach the tree after a tick and the sim on the next one
+        let sphere = |tick: u32, voxel: Voxel| {
+            let command = VoxelCommand::SetVoxelsSdf {
+                origin: IVec3::splat(24),
+                sdf: sdf::Sphere { radius: 3.0 }.as_node(),
+                voxel,
+                params: SetVoxelsSdfParams { within: 0.0, can_replace: VoxelSet::AIR },
+            };
+            [
+                RecordedCommand { tick, phase: RecordPhase::Tree, command: command.clone() },
+                RecordedCommand { tick: tick + 1, phase: RecordPhase::Sim, command },
+            ]
+        };
+
+        SimRecording {
+            grid,
+            log: RecordingLog {
+                start_tick: 10,
+                active_chunks: vec![IVec3::ZERO, IVec3::new(1, 0, 1)],
+                sleep_after: 128,
+                commands: sphere(11, Voxel::Sand)
+                    .into_iter()
+                    .chain(sphere(20, Voxel::Water(default())))
+                    .collect(),
+                ..default()
+            },
+        }
+    }
+
+    fn run_ticks(recording: &SimRecording, ticks: usize) -> Vec<TickHash> {
+        let mut replay = SimReplay::new(recording);
+        let mut hashes = vec![replay.hash()];
+        hashes.extend((1..ticks).map(|_| replay.step()));
+        hashes
+    }
+
+    #[test]
+    pub fn replay_is_deterministic() {
+        let recording = recording();
+        let run_with_threads = |threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| run_ticks(&recording, 40))
+        };
+
+        let expected = run_with_threads(1);
+        assert_eq!(expected[0].tick, 10);
+        assert_eq!(expected[39].tick, 49);
+        // the sim actually did something
+        assert!(expected.windows(2).any(|ticks| ticks[0].hash != ticks[1].hash));
+        // commands pulled the chunk they landed in into the sim
+        assert!(expected[39].chunks.iter().any(|(chunk_point, _)| *chunk_point == IVec3::ONE));
+
+        for threads in [2, 4, 8] {
+            assert_eq!(first_divergence(&expected, &run_with_threads(threads)), None);
+        }
+    }
+
+    #[test]
+    pub fn divergence_is_pinpointed() {
+        let mut recording = recording();
+        let expected = run_ticks(&recording, 30);
+
+        // drop the water
+        recording.log.commands.truncate(2);
+        let actual = run_ticks(&recording, 30);
+
+        let divergence = first_divergence(&expected, &actual).unwrap();
+        assert_eq!(divergence, Divergence { tick: 21, chunks: vec![IVec3::ONE] });
+    }
+
+    #[test]
+    pub fn recording_round_trip() {
+        let mut recording = recording();
+        recording.log.hashes = run_ticks(&recording, 30);
+
+        let mut bytes = Vec::new();
+        recording.save(&mut bytes).unwrap();
+        let loaded = SimRecording::load(&mut bytes.as_slice()).unwrap();
+
+        assert!(crate::voxel::diff::diff(&recording.grid.tree, &loaded.grid.tree).is_empty());
+        assert_eq!(loaded.log.commands.len(), recording.log.commands.len());
+        assert_eq!(first_divergence(&recording.log.hashes, &SimReplay::run(&loaded)), None);
+    }
+}
@@ -46,14 +46,19 @@ impl RLEChunk {
     }
 
     pub fn from_sim(sim: &SimChunk) -> Self {
+        // TODO: read from sim chunk and translate into a morton/z order curve
+        Self::from_voxels(&sim.voxels)
+    }
+
+    /// Encode voxels in the order they are stored.
+    pub fn from_voxels(voxels: &[Voxel]) -> Self {
         let mut rle = RLEChunk::new();
 
-        // for now read from sim chunk in a linearized fashion
-        let mut iter = sim.voxels.iter().enumerate();
-        let mut run = iter.next().map(|(_, voxel_packed)| *voxel_packed).unwrap();
+        let mut iter = voxels.iter();
+        let mut run = *iter.next().unwrap();
         let mut run_count = 1;
 
-        for (_voxel_index, voxel) in iter {
+        for voxel in iter {
             if *voxel == run {
                 run_count += 1;
             } else {
@@ -64,11 +69,31 @@ impl RLEChunk {
         }
 
         rle.runs.push((run, run_count));
-
-        // TODO: read from sim chunk and translate into a morton/z order curve
         rle
     }
 
+    /// Total number of voxels covered by the runs.
+    pub fn voxel_count(&self) -> usize {
+        self.runs.iter().map(|(_, run_count)| *run_count as usize).sum()
+    }
+
+    /// Decode back into `voxels`, returns false if the runs don't cover it
+    /// exactly.
+    pub fn decode_into(&self, voxels: &mut [Voxel]) -> bool {
+        if self.voxel_count() != voxels.len() {
+            return false;
+        }
+
+        let mut voxel_index = 0;
+        for (run, run_count) in &self.runs {
+            let run_end = voxel_index + *run_count as usize;
+            voxels[voxel_index..run_end].fill(*run);
+            voxel_index = run_end;
+        }
+
+        true
+    }
+
     /*
     pub fn to_sim(&self) -> SimChunk {
         let mut chunk = SimChunk::new();
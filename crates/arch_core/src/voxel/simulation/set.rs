@@ -6,6 +6,7 @@ use crate::voxel::simulation::data::{CHUNK_LENGTH, linearize};
 
 pub const SET_LEN: usize = CHUNK_LENGTH / 64;
 
+#[derive(Clone)]
 pub struct SetReader<'a> {
     set: &'a ChunkSet,
     mask_index: usize,
@@ -0,0 +1,229 @@
+//! Structural load of solid voxels.
+//!
+//! Support is the cheapest path from an [anchor](ANCHORS) through solid
+//! voxels. Stepping straight up is free, stepping sideways or down costs more
+//! the weaker the voxel being held up is, see [`VoxelDefinition::strength`].
+//! Voxels whose support costs more than [`SUPPORTED`] are over-stressed and the
+//! ones that can [crumble](crate::voxel::voxel::Interactions::crumbled) turn
+//! into falling semi-solids.
+//!
+//! Unlike [islands](super::islands) this catches material that is still
+//! connected to the ground but hangs too far out.
+//!
+//! [`VoxelDefinition::strength`]: crate::voxel::voxel::VoxelDefinition::strength
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use bevy::platform::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+
+use crate::voxel::mesh::ChangedChunk;
+use crate::voxel::simulation::data::SimChunks;
+use crate::voxel::simulation::islands::{ANCHORS, NEIGHBORS};
+use crate::voxel::tree::{CHUNK_WIDTH, from_leaf_index};
+use crate::voxel::{Voxel, VoxelAabb, VoxelNode, VoxelTree, Voxels};
+
+/// Most load a voxel can be under before it's over-stressed.
+pub const SUPPORTED: u32 = 256;
+
+pub fn plugin(app: &mut App) {
+    app.register_type::<StructureSettings>();
+    app.init_resource::<StructureSettings>();
+
+    app.add_systems(Update, crumble_overstressed.before(super::islands::detach_islands));
+}
+
+#[derive(Resource, Clone, Debug, Reflect)]
+#[reflect(Resource)]
+pub struct StructureSettings {
+    /// Run the structural pass on modified chunks, off by default.
+    pub enabled: bool,
+
+    /// How many modified chunks to check each frame.
+    pub chunks_per_frame: usize,
+
+    /// Voxels around each chunk searched for support, solid voxels on the edge
+    /// of the search are assumed to be supported. Should be at least the
+    /// highest strength so spans out of the chunk aren't cut short.
+    pub search_margin: i32,
+}
+
+impl Default for StructureSettings {
+    fn default() -> Self {
+        Self { enabled: false, chunks_per_frame: 4, search_margin: 16 }
+    }
+}
+
+/// Load from stepping sideways or down into `voxel`.
+pub fn step_load(voxel: Voxel) -> u32 {
+    match voxel.definition().strength {
+        0 => SUPPORTED + 1,
+        strength => SUPPORTED.div_ceil(strength as u32),
+    }
+}
+
+/// Load of the solid voxels in `aabb` and `margin` voxels around it.
+///
+/// Voxels that aren't connected to any support are left out, those are
+/// [islands](super::islands::find_islands).
+pub fn support_load(tree: &VoxelTree, aabb: VoxelAabb, margin: i32) -> HashMap<IVec3, u32> {
+    let region = VoxelAabb::new(aabb.min - IVec3::splat(margin), aabb.max + IVec3::splat(margin));
+
+    let mut load = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    for y in region.min.y..=region.max.y {
+        for x in region.min.x..=region.max.x {
+            for z in region.min.z..=region.max.z {
+                let point = IVec3::new(x, y, z);
+                let voxel = tree.get_voxel(point);
+                if !voxel.is_solid() {
+                    continue;
+                }
+
+                let edge = point.cmpeq(region.min).any() || point.cmpeq(region.max).any();
+                if edge || ANCHORS.contains(voxel) {
+                    load.insert(point, 0);
+                    queue.push(Reverse((0u32, point.to_array())));
+                }
+            }
+        }
+    }
+
+    while let Some(Reverse((point_load, point))) = queue.pop() {
+        let point = IVec3::from_array(point);
+        if load.get(&point).is_some_and(|current| *current < point_load) {
+            continue;
+        }
+
+        for offset in NEIGHBORS {
+            let neighbor = point + offset;
+            if !region.contains(neighbor) {
+                continue;
+            }
+
+            let voxel = tree.get_voxel(neighbor);
+            if !voxel.is_solid() {
+                continue;
+            }
+
+            // resting directly on a supported voxel is free
+            let step = if offset == IVec3::Y { 0 } else { step_load(voxel) };
+            let neighbor_load = point_load.saturating_add(step);
+            if load.get(&neighbor).is_none_or(|current| neighbor_load < *current) {
+                load.insert(neighbor, neighbor_load);
+                queue.push(Reverse((neighbor_load, neighbor.to_array())));
+            }
+        }
+    }
+
+    load
+}
+
+/// Over-stressed voxels in `chunk_point` that crumble, along with what they
+/// crumble into.
+pub fn overstressed(tree: &VoxelTree, chunk_point: IVec3, margin: i32) -> Vec<(IVec3, Voxel)> {
+    let chunk_min = chunk_point * IVec3::splat(CHUNK_WIDTH as i32);
+    let crumbles =
+        |voxel: Voxel| voxel.is_solid() && voxel.definition().interactions.crumbled.is_some();
+    let candidates: Vec<IVec3> = match tree.get_chunk(chunk_point) {
+        Some(VoxelNode::Leaf { leaf, .. }) => leaf
+            .iter()
+            .enumerate()
+            .filter(|(_, voxel)| crumbles(**voxel))
+            .map(|(index, _)| chunk_min + from_leaf_index(index))
+            .collect(),
+        // a solid chunk is either buried or only the edges could be over-stressed,
+        // those get a leaf as soon as something around them is dug out
+        _ => return Vec::new(),
+    };
+
+    if candidates.is_empty() {
+        return Vec::new();
+    }
+
+    let chunk_aabb = VoxelAabb::from_size(chunk_min, IVec3::splat(CHUNK_WIDTH as i32));
+    let load = support_load(tree, chunk_aabb, margin);
+
+    let mut crumbled = Vec::new();
+    for point in candidates {
+        if load.get(&point).is_some_and(|load| *load > SUPPORTED) {
+            let voxel = tree.get_voxel(point);
+            crumbled.push((point, voxel.definition().interactions.crumbled.unwrap()));
+        }
+    }
+    crumbled
+}
+
+/// Check recently modified chunks and crumble over-stressed voxels, see
+/// [`StructureSettings`].
+pub fn crumble_overstressed(
+    settings: Res<StructureSettings>,
+    mut changed_chunks: MessageReader<ChangedChunk>,
+    mut pending: Local<HashSet<(Entity, IVec3)>>,
+    mut grids: Query<(&mut Voxels, Option<&mut SimChunks>)>,
+) {
+    if !settings.enabled {
+        changed_chunks.clear();
+        pending.clear();
+        return;
+    }
+
+    for ChangedChunk { grid_entity, chunk_point } in changed_chunks.read() {
+        pending.insert((*grid_entity, chunk_point.0));
+    }
+
+    let batch = pending.iter().take(settings.chunks_per_frame).copied().collect::<Vec<_>>();
+    for (grid_entity, chunk_point) in batch {
+        pending.remove(&(grid_entity, chunk_point));
+        let Ok((mut voxels, mut sim_chunks)) = grids.get_mut(grid_entity) else {
+            continue;
+        };
+
+        for (point, voxel) in overstressed(&voxels.tree, chunk_point, settings.search_margin) {
+            voxels.set_voxel(point, voxel);
+            if let Some(sim_chunks) = &mut sim_chunks {
+                sim_chunks.set_voxel(point, voxel);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn overhang_crumbles() {
+        let mut tree = VoxelTree::new();
+        tree.grow_n_layers(1);
+        tree.fill_aabb(VoxelAabb::new(IVec3::ZERO, IVec3::new(31, 0, 31)), Voxel::Base);
+        // dirt pillar with an overhang 8 voxels out
+        tree.fill_aabb(VoxelAabb::new(IVec3::new(4, 1, 4), IVec3::new(4, 8, 4)), Voxel::Dirt);
+        tree.fill_aabb(VoxelAabb::new(IVec3::new(5, 8, 4), IVec3::new(12, 8, 4)), Voxel::Dirt);
+
+        let crumbled = overstressed(&tree, IVec3::ZERO, 16);
+        let strength = Voxel::Dirt.definition().strength as i32;
+        let mut points = crumbled.iter().map(|(point, _)| *point).collect::<Vec<_>>();
+        points.sort_by_key(|point| point.x);
+        assert_eq!(
+            points,
+            (4 + strength + 1..=12).map(|x| IVec3::new(x, 8, 4)).collect::<Vec<_>>()
+        );
+        assert!(crumbled.iter().all(|(_, voxel)| *voxel == Voxel::Sand));
+
+        // stone spans further and holds the dirt on top of it
+        tree.fill_aabb(VoxelAabb::new(IVec3::new(5, 7, 4), IVec3::new(12, 7, 4)), Voxel::Stone);
+        assert!(overstressed(&tree, IVec3::ZERO, 16).is_empty());
+    }
+
+    #[test]
+    pub fn islands_are_left_alone() {
+        let mut tree = VoxelTree::new();
+        tree.grow_n_layers(1);
+        tree.fill_aabb(VoxelAabb::new(IVec3::ZERO, IVec3::new(31, 0, 31)), Voxel::Base);
+        tree.fill_aabb(VoxelAabb::new(IVec3::new(4, 10, 4), IVec3::new(12, 10, 4)), Voxel::Dirt);
+
+        assert!(overstressed(&tree, IVec3::ZERO, 16).is_empty());
+    }
+}
@@ -0,0 +1,231 @@
+//! Column preserving terrain smoothing.
+//!
+//! The surface height of a column is moved toward a weighted average of the
+//! 3x3 columns around it. Columns are squished or stretched just below their
+//! top voxel, so layers keep their order (grass above dirt above stone) and
+//! only change in thickness. Unbreakable voxels and anything below them never
+//! move.
+
+use std::ops::RangeInclusive;
+
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+
+use crate::sdf::Sdf;
+use crate::sdf::voxel_rasterize::PointIter;
+use crate::voxel::voxel::SimKind;
+use crate::voxel::{Voxel, VoxelTree};
+
+#[rustfmt::skip]
+const SURROUNDING: [IVec3; 9] = [
+    IVec3::new(-1, 0, 1), IVec3::new(0, 0, 1), IVec3::new(1, 0, 1),
+    IVec3::new(-1, 0, 0), IVec3::new(0, 0, 0), IVec3::new(1, 0, 0),
+    IVec3::new(-1, 0, -1), IVec3::new(0, 0, -1), IVec3::new(1, 0, -1),
+];
+
+#[rustfmt::skip]
+const CONVOLUTION: [i32; 9] = [
+    1, 2, 1,
+    2, 3, 2,
+    1, 2, 1,
+];
+
+/// Voxels that make up the ground, air, liquids and gases sit on top of it.
+pub fn is_ground(voxel: Voxel) -> bool {
+    matches!(voxel.definition().simulation_kind, SimKind::Solid | SimKind::SemiSolid)
+}
+
+/// Height of the ground surface in the column at `pos`, relative to `pos.y`.
+///
+/// Searches up from `pos` if it is in the ground, otherwise down, clamped to
+/// `height_range`.
+pub fn column_height(tree: &VoxelTree, pos: IVec3, height_range: RangeInclusive<i32>) -> i32 {
+    let ground = |height: i32| is_ground(tree.get_voxel(pos + IVec3::Y * height));
+
+    let mut height = 0;
+    if ground(0) {
+        while height < *height_range.end() && ground(height) {
+            height += 1;
+        }
+    } else {
+        while height > *height_range.start() && !ground(height - 1) {
+            height -= 1;
+        }
+    }
+    height
+}
+
+/// Weighted average of the column heights around `pos`.
+pub fn smoothed_height(tree: &VoxelTree, pos: IVec3, height_range: RangeInclusive<i32>) -> i32 {
+    let mut sum = 0;
+    for (offset, weight) in SURROUNDING.iter().zip(CONVOLUTION) {
+        sum += column_height(tree, pos + offset, height_range.clone()) * weight;
+    }
+
+    let total = CONVOLUTION.iter().sum::<i32>();
+    (sum + total / 2).div_euclid(total)
+}
+
+/// Move the surface of the column at `pos` toward the [`smoothed_height`] of
+/// `source`, writing the result into `tree`.
+///
+/// `source` is usually a [snapshot](VoxelTree::snapshot) of `tree` so columns
+/// smoothed earlier don't affect the ones after them.
+pub fn smooth_column(
+    source: &VoxelTree,
+    tree: &mut VoxelTree,
+    pos: IVec3,
+    height_range: RangeInclusive<i32>,
+) {
+    let voxel_at = |height: i32| source.get_voxel(pos + IVec3::Y * height);
+
+    let current = column_height(source, pos, height_range.clone());
+    let target = smoothed_height(source, pos, height_range.clone());
+    if target == current {
+        return;
+    }
+
+    // only the part of the column above anything unbreakable can move
+    let bottom = (*height_range.start()..current)
+        .rev()
+        .find(|height| !voxel_at(*height).breakable())
+        .map_or(*height_range.start(), |height| height + 1);
+    if bottom >= current {
+        return;
+    }
+
+    let mut column = (bottom..current).map(voxel_at).collect::<Vec<_>>();
+    let top = column.pop().unwrap();
+    let target = target.max(bottom + 1);
+
+    // squish/stretch the layer under the top voxel
+    let under_top = (target - bottom - 1) as usize;
+    let fill = column.last().copied().unwrap_or(top);
+    column.resize(under_top, fill);
+    column.push(top);
+
+    for (height, voxel) in (bottom..).zip(column) {
+        if voxel_at(height) != voxel {
+            tree.set_voxel(pos + IVec3::Y * height, voxel);
+        }
+    }
+
+    for height in target..current {
+        tree.set_voxel(pos + IVec3::Y * height, Voxel::Air);
+    }
+}
+
+/// Smooth every column inside of `sdf`, surfaces are searched for within the
+/// vertical extent of the sdf in each column.
+pub fn smooth_sdf(tree: &mut VoxelTree, sdf: impl Sdf) {
+    // vertical extent of each column
+    let mut columns = HashMap::<IVec2, (i32, i32)>::new();
+    for point in PointIter::from_sdf(&sdf) {
+        if sdf.sdf(point.as_vec3()) >= 0.0 {
+            continue;
+        }
+
+        let (min_y, max_y) = columns.entry(point.xz()).or_insert((point.y, point.y));
+        *min_y = (*min_y).min(point.y);
+        *max_y = (*max_y).max(point.y);
+    }
+
+    let source = tree.snapshot();
+    for (column, (min_y, max_y)) in columns {
+        let pos = IVec3::new(column.x, min_y, column.y);
+        smooth_column(&source, tree, pos, 0..=(max_y - min_y));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::voxel::VoxelAabb;
+
+    /// Grass on dirt on stone on base, with the top at `height`.
+    fn layered_column(tree: &mut VoxelTree, x: i32, z: i32, height: i32) {
+        tree.set_voxel(IVec3::new(x, 0, z), Voxel::Base);
+        for y in 1..height {
+            let voxel = match y {
+                y if y == height - 1 => Voxel::Grass,
+                y if y >= height - 3 => Voxel::Dirt,
+                _ => Voxel::Stone,
+            };
+            tree.set_voxel(IVec3::new(x, y, z), voxel);
+        }
+    }
+
+    fn column(tree: &VoxelTree, x: i32, z: i32) -> Vec<Voxel> {
+        let mut voxels = (0..32).map(|y| tree.get_voxel(IVec3::new(x, y, z))).collect::<Vec<_>>();
+        while voxels.last() == Some(&Voxel::Air) {
+            voxels.pop();
+        }
+        voxels
+    }
+
+    #[test]
+    pub fn column_height() {
+        let mut tree = VoxelTree::new();
+        layered_column(&mut tree, 0, 0, 8);
+
+        assert_eq!(super::column_height(&tree, IVec3::new(0, 0, 0), 0..=16), 8);
+        assert_eq!(super::column_height(&tree, IVec3::new(0, 12, 0), -12..=4), -4);
+        // clamped to the range
+        assert_eq!(super::column_height(&tree, IVec3::new(0, 0, 0), 0..=4), 4);
+        assert_eq!(super::column_height(&tree, IVec3::new(0, 12, 0), -2..=4), -2);
+    }
+
+    #[test]
+    pub fn squish_and_stretch() {
+        let mut tree = VoxelTree::new();
+        for x in -1..=1 {
+            for z in -1..=1 {
+                layered_column(&mut tree, x, z, 8);
+            }
+        }
+
+        // spike
+        layered_column(&mut tree, 0, 0, 16);
+        let source = tree.snapshot();
+        smooth_column(&source, &mut tree, IVec3::ZERO, 0..=20);
+
+        let smoothed = column(&tree, 0, 0);
+        assert_eq!(smoothed.len() as i32, smoothed_height(&source, IVec3::ZERO, 0..=20));
+        assert!(smoothed.len() < 16);
+        assert_eq!(smoothed[0], Voxel::Base);
+        assert_eq!(smoothed.last(), Some(&Voxel::Grass));
+        assert!(smoothed.is_sorted_by_key(|voxel| match voxel {
+            Voxel::Base => 0,
+            Voxel::Stone => 1,
+            Voxel::Dirt => 2,
+            _ => 3,
+        }));
+
+        // pit
+        layered_column(&mut tree, 0, 0, 3);
+        tree.fill_aabb(VoxelAabb::new(IVec3::new(0, 3, 0), IVec3::new(0, 31, 0)), Voxel::Air);
+        let source = tree.snapshot();
+        smooth_column(&source, &mut tree, IVec3::ZERO, 0..=20);
+
+        let smoothed = column(&tree, 0, 0);
+        assert_eq!(smoothed.len() as i32, smoothed_height(&source, IVec3::ZERO, 0..=20));
+        assert!(smoothed.len() > 3);
+        assert_eq!(smoothed[0], Voxel::Base);
+        assert_eq!(smoothed[1..smoothed.len() - 1], vec![Voxel::Dirt; smoothed.len() - 2]);
+        assert_eq!(smoothed.last(), Some(&Voxel::Grass));
+    }
+
+    #[test]
+    pub fn flat_is_unchanged() {
+        let mut tree = VoxelTree::new();
+        tree.fill_aabb(VoxelAabb::new(IVec3::new(0, 0, 0), IVec3::new(31, 0, 31)), Voxel::Base);
+        tree.fill_aabb(VoxelAabb::new(IVec3::new(0, 1, 0), IVec3::new(31, 5, 31)), Voxel::Dirt);
+
+        let before = tree.snapshot();
+        smooth_sdf(
+            &mut tree,
+            crate::sdf::Sphere { radius: 4.0 }.translate(Vec3::new(16.0, 5.0, 16.0)),
+        );
+        assert!(crate::voxel::diff::diff(&before, &tree).is_empty());
+    }
+}
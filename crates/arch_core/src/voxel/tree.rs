@@ -1,9 +1,10 @@
 use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
 
-use bevy::platform::collections::HashSet;
+use bevy::platform::collections::{HashMap, HashSet};
 use bevy::prelude::*;
 
-use crate::voxel::{Voxel, Voxels};
+use crate::voxel::{Voxel, VoxelAabb, Voxels};
 
 pub const TREE_ARY: usize = 4;
 pub const TREE_LENGTH: usize = TREE_ARY * TREE_ARY * TREE_ARY;
@@ -33,13 +34,13 @@ pub fn draw_tree(grids: Query<(&Voxels,)>, mut gizmos: Gizmos, debug_tree: Res<D
     }
 
     for (voxels,) in &grids {
-        voxels.tree.root.draw_gizmo(IVec3::ZERO, &mut gizmos);
+        voxels.tree.root.draw_gizmo(voxels.tree.voxel_origin(), &mut gizmos);
     }
 }
 
 pub fn compress_tree(mut grids: Query<(&mut Voxels,)>) {
     for (mut voxels,) in &mut grids {
-        voxels.tree.root.compress();
+        voxels.tree.compress_touched();
     }
 }
 
@@ -84,6 +85,16 @@ pub fn to_leaf_index(relative_leaf_point: IVec3) -> usize {
     z as usize + x as usize * CHUNK_WIDTH + y as usize * CHUNK_WIDTH * CHUNK_WIDTH
 }
 
+#[inline]
+pub fn from_leaf_index(leaf_index: usize) -> IVec3 {
+    assert!(leaf_index < CHUNK_LENGTH);
+
+    let z = leaf_index % CHUNK_WIDTH;
+    let x = (leaf_index / CHUNK_WIDTH) % CHUNK_WIDTH;
+    let y = leaf_index / (CHUNK_WIDTH * CHUNK_WIDTH);
+    IVec3::new(x as i32, y as i32, z as i32)
+}
+
 /// Get the index to the [`VoxelNode::Children`]'s subdivided region
 ///
 /// Valid values are (0..[`TREE_ARY`], 0..[`TREE_ARY`], 0..[`TREE_ARY`])
@@ -130,13 +141,17 @@ pub fn get_sublayer_index_from_chunk(layer: usize, chunk_point: IVec3) -> usize
     sublayer_index
 }
 
-#[derive(Debug, Clone, Reflect)]
+#[derive(Debug, Clone, PartialEq, Eq, Reflect)]
 pub struct SharedData {
     pub layer: usize,
     pub authority: bool,
 }
 
-#[derive(Clone)]
+/// Node of the voxel tree.
+///
+/// Children and leaves are shared with [`Arc`] and copied on write, so cloning
+/// a node is cheap, see [`VoxelTree::snapshot`].
+#[derive(Clone, PartialEq)]
 pub enum VoxelNode {
     /// Entire region is filled with a single voxel type.
     Solid {
@@ -144,12 +159,12 @@ pub enum VoxelNode {
         voxel: Voxel, // Solid(Voxel::Air) is the same as "Empty".
     },
     /// Subdivided region, but not the bottom of the graph.
-    Children { shared: SharedData, children: Box<[VoxelNode; TREE_LENGTH]> },
+    Children { shared: SharedData, children: Arc<[VoxelNode; TREE_LENGTH]> },
     /// Leaf node/bottom of the graph, holds fine-grain voxel data.
     Leaf {
         // layer should be 0
         shared: SharedData,
-        leaf: Box<[Voxel; CHUNK_LENGTH]>,
+        leaf: Arc<[Voxel; CHUNK_LENGTH]>,
     },
 }
 
@@ -228,40 +243,6 @@ impl VoxelNode {
         }
     }
 
-    pub fn renderable_chunks(&self, origin: IVec3, buffer: &mut Vec<IVec3>) {
-        match self {
-            Self::Solid { shared, voxel, .. } => {
-                if !voxel.rendered() {
-                    return;
-                }
-
-                // TODO: Only add the chunks on the surface of this solid region to the list.
-
-                // add every chunk point from this layer
-                let width = layer_width_chunk(shared.layer) as i32;
-                for x in 0..width {
-                    for y in 0..width {
-                        for z in 0..width {
-                            buffer.push(origin + IVec3::new(x, y, z));
-                        }
-                    }
-                }
-            },
-            Self::Leaf { .. } => {
-                // assume there is a renderable voxel in this leaf
-                buffer.push(origin);
-            },
-            Self::Children { shared, children } => {
-                for (child_index, child) in children.iter().enumerate() {
-                    let child_position = from_child_index(child_index);
-                    let chunk_space_offset =
-                        child_position * layer_width_chunk(shared.layer) as i32;
-                    child.renderable_chunks(origin + chunk_space_offset, buffer);
-                }
-            },
-        }
-    }
-
     /// Subdivides this voxel node if it is [`VoxelNode::Solid`], otherwise does
     /// nothing since [`VoxelNode::Leaf`] and [`VoxelNode::Children`] are
     /// already subdivided.
@@ -273,12 +254,12 @@ impl VoxelNode {
                 if shared.layer == 0 {
                     *self = Self::Leaf {
                         shared: shared.clone(),
-                        leaf: Box::new([*voxel; CHUNK_LENGTH]),
+                        leaf: Arc::new([*voxel; CHUNK_LENGTH]),
                     };
                 } else {
                     *self = Self::Children {
                         shared: shared.clone(),
-                        children: Box::new(std::array::from_fn(|_| {
+                        children: Arc::new(std::array::from_fn(|_| {
                             let shared = shared.clone();
                             VoxelNode::Solid {
                                 shared: SharedData { layer: shared.layer - 1, ..shared },
@@ -294,12 +275,87 @@ impl VoxelNode {
         }
     }
 
+    /// Set every voxel of this node inside `aabb`, `origin` is the voxel point
+    /// of this node's minimum corner.
+    ///
+    /// Fully covered nodes are replaced with a [`VoxelNode::Solid`], only
+    /// partially covered ones are subdivided. `changed` is called with each
+    /// region of voxels that actually changed.
+    pub fn fill_aabb(
+        &mut self,
+        origin: IVec3,
+        aabb: VoxelAabb,
+        voxel: Voxel,
+        changed: &mut impl FnMut(VoxelAabb),
+    ) {
+        let node_aabb = VoxelAabb::from_size(origin, IVec3::splat(self.voxel_width() as i32));
+        let Some(overlap) = node_aabb.intersection(&aabb) else {
+            return;
+        };
+
+        if matches!(self, Self::Solid { voxel: solid_voxel, .. } if *solid_voxel == voxel) {
+            return;
+        }
+
+        if overlap == node_aabb {
+            let shared = SharedData { layer: self.layer(), authority: self.authority() };
+            *self = Self::Solid { shared, voxel };
+            changed(overlap);
+            return;
+        }
+
+        self.subdivide();
+        match self {
+            Self::Children { shared, children } => {
+                let child_width = layer_width_voxel(shared.layer - 1) as i32;
+                for (child_index, child) in Arc::make_mut(children).iter_mut().enumerate() {
+                    let child_origin = origin + from_child_index(child_index) * child_width;
+                    child.fill_aabb(child_origin, aabb, voxel, changed);
+                }
+            },
+            Self::Leaf { leaf, .. } => {
+                let mut any_changed = false;
+                for y in overlap.min.y..=overlap.max.y {
+                    for x in overlap.min.x..=overlap.max.x {
+                        for z in overlap.min.z..=overlap.max.z {
+                            let leaf_index = to_leaf_index(IVec3::new(x, y, z) - origin);
+                            if leaf[leaf_index] != voxel {
+                                Arc::make_mut(leaf)[leaf_index] = voxel;
+                                any_changed = true;
+                            }
+                        }
+                    }
+                }
+
+                if any_changed {
+                    changed(overlap);
+                }
+            },
+            Self::Solid { .. } => unreachable!("solid nodes are subdivided above"),
+        }
+    }
+
     /// Compress into a [`VoxelNode::Solid`] node if all of the voxels are the
     /// same.
     pub fn compress(&mut self) {
+        self.compress_with(IVec3::ZERO, &mut |_| {});
+    }
+
+    /// [`VoxelNode::compress`], calling `compressed` with the chunk point of
+    /// every [`VoxelNode::Leaf`] that turned [`VoxelNode::Solid`].
+    pub fn compress_with(&mut self, origin: IVec3, compressed: &mut impl FnMut(IVec3)) {
         match self {
             Self::Children { shared, children } => {
-                children[0].compress();
+                let child_width = layer_width_chunk(shared.layer - 1) as i32;
+                for child_index in 0..TREE_LENGTH {
+                    // avoid copying shared children that won't change
+                    if !children[child_index].compressible() {
+                        continue;
+                    }
+
+                    let child_origin = origin + from_child_index(child_index) * child_width;
+                    Arc::make_mut(children)[child_index].compress_with(child_origin, compressed);
+                }
 
                 let mut all_solid = true;
                 let solid_voxel = match children[0] {
@@ -310,8 +366,7 @@ impl VoxelNode {
                     },
                 };
 
-                for child in children.iter_mut().skip(1) {
-                    child.compress();
+                for child in children.iter().skip(1) {
                     match child {
                         Self::Solid { voxel, .. } => {
                             if *voxel != solid_voxel {
@@ -338,7 +393,77 @@ impl VoxelNode {
 
                 // we are all the same voxel, compress to solid
                 *self = Self::Solid { shared: shared.clone(), voxel: first_voxel };
+                compressed(origin);
+            },
+            Self::Solid { .. } => {}, // already compressed
+        }
+    }
+
+    /// Would [`VoxelNode::compress`] change anything in this node?
+    pub fn compressible(&self) -> bool {
+        match self {
+            Self::Children { children, .. } => {
+                let Self::Solid { voxel: solid_voxel, .. } = children[0] else {
+                    return children.iter().any(|child| child.compressible());
+                };
+
+                children.iter().all(|child| match child {
+                    Self::Solid { voxel, .. } => *voxel == solid_voxel,
+                    _ => false,
+                }) || children.iter().any(|child| child.compressible())
+            },
+            Self::Leaf { leaf, .. } => leaf.iter().all(|voxel| *voxel == leaf[0]),
+            Self::Solid { .. } => false,
+        }
+    }
+
+    /// [`VoxelNode::compress_with`], but only descends into the subtrees
+    /// containing `chunk_points` and the ancestors of those.
+    ///
+    /// `chunk_points` is reordered in the process.
+    pub fn compress_chunks(
+        &mut self,
+        origin: IVec3,
+        chunk_points: &mut [IVec3],
+        compressed: &mut impl FnMut(IVec3),
+    ) {
+        if chunk_points.is_empty() {
+            return;
+        }
+
+        match self {
+            Self::Children { shared, children } => {
+                let child_width = layer_width_chunk(shared.layer - 1) as i32;
+                let child_index =
+                    |chunk_point: &IVec3| to_child_index((*chunk_point - origin) / child_width);
+
+                chunk_points.sort_unstable_by_key(child_index);
+                for child_points in
+                    chunk_points.chunk_by_mut(|a, b| child_index(a) == child_index(b))
+                {
+                    let index = child_index(&child_points[0]);
+                    let child_origin = origin + from_child_index(index) * child_width;
+                    Arc::make_mut(children)[index].compress_chunks(
+                        child_origin,
+                        child_points,
+                        compressed,
+                    );
+                }
+
+                let Self::Solid { voxel: solid_voxel, .. } = children[0] else {
+                    return;
+                };
+
+                let all_solid = children.iter().all(|child| match child {
+                    Self::Solid { voxel, .. } => *voxel == solid_voxel,
+                    _ => false,
+                });
+
+                if all_solid {
+                    *self = Self::Solid { shared: shared.clone(), voxel: solid_voxel };
+                }
             },
+            Self::Leaf { .. } => self.compress_with(origin, compressed),
             Self::Solid { .. } => {}, // already compressed
         }
     }
@@ -372,7 +497,7 @@ impl VoxelNode {
             // traverse downwards
             VoxelNode::Children { shared, children } => {
                 let sublayer_index = get_sublayer_index_from_voxel(shared.layer, voxel_point);
-                let next_node = &mut children[sublayer_index];
+                let next_node = &mut Arc::make_mut(children)[sublayer_index];
                 next_node.set_voxel(voxel_point, voxel)
             },
             // fracture into child or leaf
@@ -398,7 +523,7 @@ impl VoxelNode {
                     warn!("voxel index out of bounds");
                     false
                 } else {
-                    leaf[voxel_index] = voxel;
+                    Arc::make_mut(leaf)[voxel_index] = voxel;
                     true
                 }
             },
@@ -437,7 +562,7 @@ impl VoxelNode {
 
                 let sublayer_index = get_sublayer_index_from_chunk(shared.layer, chunk_point);
                 // println!("sublayer_index: {:?}", sublayer_index);
-                let next_node = &mut children[sublayer_index];
+                let next_node = &mut Arc::make_mut(children)[sublayer_index];
                 next_node.get_chunk_mut(chunk_point)
             },
             leaf @ VoxelNode::Leaf { .. } => leaf,
@@ -455,7 +580,7 @@ impl VoxelNode {
             // traverse downwards
             VoxelNode::Children { shared, children } => {
                 let sublayer_index = get_sublayer_index_from_chunk(shared.layer, chunk_point);
-                let next_node = &mut children[sublayer_index];
+                let next_node = &mut Arc::make_mut(children)[sublayer_index];
                 next_node.get_leaf_mut(chunk_point)
             },
             // fracture into child or leaf
@@ -477,7 +602,7 @@ impl VoxelNode {
             panic!("subdivided recursively should end in a leaf node");
         };
 
-        **leaf = chunk_data;
+        *leaf = Arc::new(chunk_data);
     }
 
     pub fn is_subdivided(&self) -> bool {
@@ -522,10 +647,36 @@ impl VoxelNode {
     }
 }
 
+/// Sparse health of damaged voxels in a chunk, keyed by the leaf voxel index.
+///
+/// Voxels without an entry are at their [`Voxel::starting_health`].
+pub type ChunkDamage = HashMap<u16, i16>;
+
+/// Highest layer the root can grow to, keeps voxel points within `i32`.
+pub const MAX_ROOT_LAYER: usize = 12;
+
 #[derive(Clone, Debug)]
 pub struct VoxelTree {
     pub root: VoxelNode,
+    /// Chunk point of the root's minimum corner, moves when the tree grows
+    /// towards negative coordinates.
+    ///
+    /// Nodes are addressed relative to this, everything else on the tree
+    /// (changed chunks, damage, ...) uses absolute points.
+    pub origin: IVec3,
     pub changed_chunks: HashSet<IVec3>,
+    /// Like [`VoxelTree::changed_chunks`] but without the simulation writing
+    /// back its own results, drained by
+    /// [island](crate::voxel::simulation::islands) detection.
+    pub edited_chunks: HashSet<IVec3>,
+    /// Chunks modified since the last compression, see
+    /// [`VoxelTree::compress_touched`].
+    pub touched_chunks: HashSet<IVec3>,
+    /// Damage layer alongside the leaves, only chunks with damaged voxels have
+    /// an entry.
+    ///
+    /// Shared with snapshots like the nodes.
+    pub damage: Arc<HashMap<IVec3, ChunkDamage>>,
 }
 
 impl VoxelTree {
@@ -536,7 +687,27 @@ impl VoxelTree {
                 shared: SharedData { layer: 0, authority: true },
                 voxel: Voxel::Air,
             },
+            origin: IVec3::ZERO,
             changed_chunks: default(),
+            edited_chunks: default(),
+            touched_chunks: default(),
+            damage: default(),
+        }
+    }
+
+    /// Copy of the tree that shares all of its nodes, O(1) since nodes are only
+    /// copied once either tree modifies them.
+    ///
+    /// Change tracking isn't carried over, snapshots are meant to be handed to
+    /// other threads for meshing, saving, etc.
+    pub fn snapshot(&self) -> VoxelTree {
+        Self {
+            root: self.root.clone(),
+            origin: self.origin,
+            changed_chunks: default(),
+            edited_chunks: default(),
+            touched_chunks: default(),
+            damage: self.damage.clone(),
         }
     }
 
@@ -544,37 +715,66 @@ impl VoxelTree {
         self.root.layer()
     }
 
+    /// Minimum voxel point covered by the root.
+    pub fn voxel_origin(&self) -> IVec3 {
+        self.origin * IVec3::splat(CHUNK_WIDTH as i32)
+    }
+
+    /// Voxel bounds of the root, max exclusive.
+    pub fn voxel_bounds(&self) -> (IVec3, IVec3) {
+        let min = self.voxel_origin();
+        (min, min + IVec3::splat(self.root.voxel_width() as i32))
+    }
+
+    /// Grow towards +X/+Y/+Z, see [`VoxelTree::grow_layer_towards`].
     pub fn grow_layer(&mut self) {
-        // take the root with a temporary value
-        // let root = std::mem::swap(self.root,
-
-        // leaf -> children [leaf, ..]
-        // children -> children [children, ..]
-        // solid -> solid
-        let new_root = match &self.root {
-            VoxelNode::Leaf { shared, .. } | VoxelNode::Children { shared, .. } => {
-                let shared = shared.clone();
-                let mut children: [VoxelNode; TREE_LENGTH] = std::array::from_fn(|_| {
-                    let shared = shared.clone();
-                    VoxelNode::Solid { shared, voxel: Voxel::Air }
-                });
-                std::mem::swap(&mut children[0], &mut self.root);
+        self.grow_layer_towards(BVec3::FALSE);
+    }
 
-                VoxelNode::Children {
-                    shared: SharedData { layer: shared.layer + 1, ..shared },
-                    children: Box::new(children),
-                }
-            },
-            VoxelNode::Solid { shared, voxel } => {
-                let shared = shared.clone();
-                VoxelNode::Solid {
-                    shared: SharedData { layer: shared.layer + 1, ..shared },
-                    voxel: *voxel,
-                }
-            },
-        };
+    /// Add a layer above the root, wrapping the old root so the tree extends
+    /// towards negative coordinates on the `negative` axes and positive
+    /// coordinates on the rest.
+    pub fn grow_layer_towards(&mut self, negative: BVec3) {
+        // the old root sits in the last octant along negative axes so the new
+        // space is in front of it
+        let root_position = IVec3::select(negative, IVec3::splat(TREE_ARY as i32 - 1), IVec3::ZERO);
+        let old_chunk_width = self.root.chunk_width() as i32;
+
+        // air -> air
+        // leaf/children/solid -> children [.., root, ..]
+        let shared = SharedData { layer: self.root.layer() + 1, authority: self.root.authority() };
+        if let VoxelNode::Solid { voxel: Voxel::Air, .. } = self.root {
+            self.root = VoxelNode::Solid { shared, voxel: Voxel::Air };
+        } else {
+            let child_shared = SharedData { layer: self.root.layer(), ..shared.clone() };
+            let mut children: [VoxelNode; TREE_LENGTH] = std::array::from_fn(|_| {
+                VoxelNode::Solid { shared: child_shared.clone(), voxel: Voxel::Air }
+            });
+            std::mem::swap(&mut children[to_child_index(root_position)], &mut self.root);
+            self.root = VoxelNode::Children { shared, children: Arc::new(children) };
+        }
+
+        self.origin -= root_position * old_chunk_width;
+    }
 
-        self.root = new_root;
+    /// Grow the tree until `voxel_point` is in bounds.
+    ///
+    /// Returns false if that would grow the root past [`MAX_ROOT_LAYER`].
+    pub fn grow_to_contain(&mut self, voxel_point: IVec3) -> bool {
+        self.grow_to_contain_chunk(voxel_point.div_euclid(IVec3::splat(CHUNK_WIDTH as i32)))
+    }
+
+    /// [`VoxelTree::grow_to_contain`] for a chunk point.
+    pub fn grow_to_contain_chunk(&mut self, chunk_point: IVec3) -> bool {
+        while !self.chunk_point_in_bounds(chunk_point) {
+            if self.root_layer() >= MAX_ROOT_LAYER {
+                return false;
+            }
+
+            self.grow_layer_towards(chunk_point.cmplt(self.origin));
+        }
+
+        true
     }
 
     pub fn grow_n_layers(&mut self, layers: usize) {
@@ -585,56 +785,479 @@ impl VoxelTree {
 
     pub fn get_voxel(&self, voxel_point: IVec3) -> Voxel {
         if !self.voxel_point_in_bounds(voxel_point) {
-            return Voxel::Barrier;
+            // everything outside of the tree is unexplored space
+            return Voxel::Air;
         }
 
-        self.root.get_voxel(voxel_point)
+        self.root.get_voxel(voxel_point - self.voxel_origin())
     }
 
+    /// Set a voxel, growing the tree if the point is out of bounds.
     pub fn set_voxel(&mut self, voxel_point: IVec3, voxel: Voxel) {
-        if !self.voxel_point_in_bounds(voxel_point) {
+        if !self.grow_to_contain(voxel_point) {
             warn!("voxel point set out-of-bounds: {:?} {:?}", voxel_point, voxel);
             return;
         }
 
-        if self.root.set_voxel(voxel_point, voxel) {
+        if self.root.set_voxel(voxel_point - self.voxel_origin(), voxel) {
+            let chunk_width = IVec3::splat(CHUNK_WIDTH as i32);
+            self.clear_damage(voxel_point);
+            self.touched_chunks.insert(voxel_point.div_euclid(chunk_width));
+
             let min = voxel_point - IVec3::ONE;
             let max = voxel_point + IVec3::ONE;
-            let min_chunk = min / IVec3::splat(CHUNK_WIDTH as i32);
-            let max_chunk = max / IVec3::splat(CHUNK_WIDTH as i32);
+            let min_chunk = min.div_euclid(chunk_width);
+            let max_chunk = max.div_euclid(chunk_width);
+
+            for x in min_chunk.x..=max_chunk.x {
+                for y in min_chunk.y..=max_chunk.y {
+                    for z in min_chunk.z..=max_chunk.z {
+                        self.mark_changed(IVec3::new(x, y, z));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Set every voxel inside `aabb`, growing the tree to fit it.
+    ///
+    /// Much faster than [`VoxelTree::set_voxel`] for large regions since
+    /// covered nodes are replaced whole.
+    pub fn fill_aabb(&mut self, aabb: VoxelAabb, voxel: Voxel) {
+        if aabb.min.cmpgt(aabb.max).any() {
+            return;
+        }
+
+        if !self.grow_to_contain(aabb.min) || !self.grow_to_contain(aabb.max) {
+            warn!("voxel aabb filled out-of-bounds: {:?} {:?}", aabb, voxel);
+            return;
+        }
+
+        let voxel_origin = self.voxel_origin();
+        let relative = VoxelAabb::new(aabb.min - voxel_origin, aabb.max - voxel_origin);
+        let mut regions = Vec::new();
+        self.root.fill_aabb(IVec3::ZERO, relative, voxel, &mut |region| regions.push(region));
+
+        let chunk_width = IVec3::splat(CHUNK_WIDTH as i32);
+        for region in regions {
+            let region = VoxelAabb::new(region.min + voxel_origin, region.max + voxel_origin);
+            self.clear_damage_aabb(region);
+
+            let min_chunk = region.min.div_euclid(chunk_width);
+            let max_chunk = region.max.div_euclid(chunk_width);
+            for x in min_chunk.x..=max_chunk.x {
+                for y in min_chunk.y..=max_chunk.y {
+                    for z in min_chunk.z..=max_chunk.z {
+                        self.touched_chunks.insert(IVec3::new(x, y, z));
+                    }
+                }
+            }
 
+            // neighbors share a border with the region for meshing
+            let min_chunk = (region.min - IVec3::ONE).div_euclid(chunk_width);
+            let max_chunk = (region.max + IVec3::ONE).div_euclid(chunk_width);
             for x in min_chunk.x..=max_chunk.x {
                 for y in min_chunk.y..=max_chunk.y {
                     for z in min_chunk.z..=max_chunk.z {
-                        self.changed_chunks.insert(IVec3::new(x, y, z));
+                        self.mark_changed(IVec3::new(x, y, z));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Compress the tree, resetting the damage of any chunks that collapsed
+    /// into a [`VoxelNode::Solid`].
+    pub fn compress(&mut self) {
+        self.touched_chunks.clear();
+
+        let origin = self.origin;
+        let damage = &mut self.damage;
+        self.root.compress_with(IVec3::ZERO, &mut |chunk_point| {
+            remove_chunk_damage(damage, origin + chunk_point);
+        });
+    }
+
+    /// [`VoxelTree::compress`], only checking the chunks touched since the
+    /// last compression and their ancestors.
+    pub fn compress_touched(&mut self) {
+        if self.touched_chunks.is_empty() {
+            return;
+        }
+
+        let origin = self.origin;
+        let chunk_width = self.root.chunk_width() as i32;
+        let mut chunk_points = self
+            .touched_chunks
+            .drain()
+            .map(|chunk_point| chunk_point - origin)
+            .filter(|chunk_point| {
+                chunk_point.min_element() >= 0 && chunk_point.max_element() < chunk_width
+            })
+            .collect::<Vec<_>>();
+
+        let damage = &mut self.damage;
+        self.root.compress_chunks(IVec3::ZERO, &mut chunk_points, &mut |chunk_point| {
+            remove_chunk_damage(damage, origin + chunk_point);
+        });
+    }
+
+    /// Current health of a voxel, taking the damage layer into account.
+    pub fn voxel_health(&self, voxel_point: IVec3) -> i16 {
+        let (chunk_point, voxel_index) = Self::damage_indices(voxel_point);
+        self.damage
+            .get(&chunk_point)
+            .and_then(|chunk_damage| chunk_damage.get(&voxel_index))
+            .copied()
+            .unwrap_or_else(|| self.get_voxel(voxel_point).starting_health())
+    }
+
+    /// Subtract `amount` of health from a voxel, breaking it into
+    /// [`Voxel::Air`] once it reaches zero.
+    ///
+    /// Returns the voxel that was broken, unbreakable voxels are never
+    /// damaged.
+    pub fn damage_voxel(&mut self, voxel_point: IVec3, amount: i16) -> Option<Voxel> {
+        let voxel = self.get_voxel(voxel_point);
+        if !voxel.breakable() || amount <= 0 {
+            return None;
+        }
+
+        let (chunk_point, voxel_index) = Self::damage_indices(voxel_point);
+        let chunk_damage = Arc::make_mut(&mut self.damage).entry(chunk_point).or_default();
+        let health = chunk_damage.entry(voxel_index).or_insert(voxel.starting_health());
+        *health = health.saturating_sub(amount);
+        if *health > 0 {
+            return None;
+        }
+
+        // also clears the damage
+        self.set_voxel(voxel_point, Voxel::Air);
+        Some(voxel)
+    }
+
+    /// Forget any damage done to this voxel.
+    pub fn clear_damage(&mut self, voxel_point: IVec3) {
+        if self.damage.is_empty() {
+            return;
+        }
+
+        let (chunk_point, voxel_index) = Self::damage_indices(voxel_point);
+        let damaged = self
+            .damage
+            .get(&chunk_point)
+            .is_some_and(|chunk_damage| chunk_damage.contains_key(&voxel_index));
+        if !damaged {
+            return;
+        }
+
+        let damage = Arc::make_mut(&mut self.damage);
+        if let Some(chunk_damage) = damage.get_mut(&chunk_point) {
+            chunk_damage.remove(&voxel_index);
+            if chunk_damage.is_empty() {
+                damage.remove(&chunk_point);
+            }
+        }
+    }
+
+    /// Forget any damage done to the voxels at `voxel_indices` in a chunk, for
+    /// writes that go straight into a leaf instead of through
+    /// [`VoxelTree::set_voxel`].
+    pub fn clear_chunk_damage(
+        &mut self,
+        chunk_point: IVec3,
+        voxel_indices: impl IntoIterator<Item = usize>,
+    ) {
+        if !self.damage.contains_key(&chunk_point) {
+            return;
+        }
+
+        let damage = Arc::make_mut(&mut self.damage);
+        if let Some(chunk_damage) = damage.get_mut(&chunk_point) {
+            for voxel_index in voxel_indices {
+                chunk_damage.remove(&(voxel_index as u16));
+            }
+            if chunk_damage.is_empty() {
+                damage.remove(&chunk_point);
+            }
+        }
+    }
+
+    /// Lowest nodes ([`VoxelNode::Solid`] or [`VoxelNode::Leaf`]) overlapping
+    /// `aabb`, along with the voxel bounds of each node.
+    pub fn iter_nodes_in(&self, aabb: VoxelAabb) -> NodesIn<'_> {
+        NodesIn { aabb, stack: vec![(self.voxel_origin(), &self.root)] }
+    }
+
+    /// Leaves overlapping `aabb` by chunk point, the leaf may extend outside of
+    /// `aabb`.
+    pub fn iter_leaves_in(
+        &self,
+        aabb: VoxelAabb,
+    ) -> impl Iterator<Item = (IVec3, &[Voxel; CHUNK_LENGTH])> {
+        self.iter_nodes_in(aabb).filter_map(|(node_aabb, node)| match node {
+            VoxelNode::Leaf { leaf, .. } => {
+                Some((node_aabb.min.div_euclid(IVec3::splat(CHUNK_WIDTH as i32)), &**leaf))
+            },
+            _ => None,
+        })
+    }
+
+    /// [`VoxelNode::Solid`] regions overlapping `aabb`, clipped to `aabb`.
+    ///
+    /// Space outside of the tree isn't included.
+    pub fn iter_solid_regions_in(
+        &self,
+        aabb: VoxelAabb,
+    ) -> impl Iterator<Item = (VoxelAabb, Voxel)> {
+        self.iter_nodes_in(aabb).filter_map(move |(node_aabb, node)| match node {
+            VoxelNode::Solid { voxel, .. } => Some((node_aabb.intersection(&aabb)?, *voxel)),
+            _ => None,
+        })
+    }
+
+    /// How many of each voxel are inside `aabb`, everything outside of the
+    /// tree counts as [`Voxel::Air`].
+    ///
+    /// Voxels are keyed with their liquid/gas state, so e.g. water at
+    /// different levels is counted separately.
+    pub fn count_by_type(&self, aabb: VoxelAabb) -> HashMap<Voxel, u64> {
+        let mut counts = HashMap::new();
+        if aabb.min.cmpgt(aabb.max).any() {
+            return counts;
+        }
+
+        let mut counted = 0;
+        for (node_aabb, node) in self.iter_nodes_in(aabb) {
+            let Some(overlap) = node_aabb.intersection(&aabb) else {
+                continue;
+            };
+
+            match node {
+                VoxelNode::Solid { voxel, .. } => {
+                    *counts.entry(*voxel).or_default() += aabb_volume(overlap);
+                },
+                VoxelNode::Leaf { leaf, .. } if overlap == node_aabb => {
+                    // leaves are mostly long runs of the same voxel
+                    for run in leaf.chunk_by(|a, b| a == b) {
+                        *counts.entry(run[0]).or_default() += run.len() as u64;
+                    }
+                },
+                VoxelNode::Leaf { leaf, .. } => {
+                    for y in overlap.min.y..=overlap.max.y {
+                        for x in overlap.min.x..=overlap.max.x {
+                            for z in overlap.min.z..=overlap.max.z {
+                                let point = IVec3::new(x, y, z) - node_aabb.min;
+                                *counts.entry(leaf[to_leaf_index(point)]).or_default() += 1;
+                            }
+                        }
+                    }
+                },
+                VoxelNode::Children { .. } => unreachable!("only the lowest nodes are iterated"),
+            }
+
+            counted += aabb_volume(overlap);
+        }
+
+        let outside = aabb_volume(aabb) - counted;
+        if outside > 0 {
+            *counts.entry(Voxel::Air).or_default() += outside;
+        }
+        counts
+    }
+
+    /// Chunks that may need a mesh, [`VoxelNode::Leaf`]s and the chunks of
+    /// rendered [`VoxelNode::Solid`] regions that aren't
+    /// [interior](Self::is_interior_chunk).
+    ///
+    /// Only the shell of a solid region is checked against its neighbors, so a
+    /// large solid region costs its surface rather than its volume.
+    pub fn renderable_chunks(&self, buffer: &mut Vec<IVec3>) {
+        let (min, max) = self.voxel_bounds();
+        let bounds = VoxelAabb::new(min, max - IVec3::ONE);
+        let chunk_width = IVec3::splat(CHUNK_WIDTH as i32);
+        for (node_aabb, node) in self.iter_nodes_in(bounds) {
+            let origin = node_aabb.min.div_euclid(chunk_width);
+            let voxel = match node {
+                VoxelNode::Leaf { .. } => {
+                    // assume there is a renderable voxel in this leaf
+                    buffer.push(origin);
+                    continue;
+                },
+                VoxelNode::Solid { voxel, .. } if voxel.rendered() => *voxel,
+                _ => continue,
+            };
+
+            // everything inside of the shell is surrounded by the same voxel
+            let width = node.chunk_width() as i32;
+            let edge = |v: i32| v == 0 || v == width - 1;
+            for x in 0..width {
+                for y in 0..width {
+                    let z_step = if edge(x) || edge(y) { 1 } else { (width - 1).max(1) };
+                    for z in (0..width).step_by(z_step as usize) {
+                        let chunk_point = origin + IVec3::new(x, y, z);
+                        if !self.surrounded_by(chunk_point, voxel) {
+                            buffer.push(chunk_point);
+                        }
                     }
                 }
             }
         }
     }
 
+    /// Is the chunk a [`VoxelNode::Solid`] with no non-opaque neighbor, its
+    /// mesh could never be seen so it can be skipped.
+    ///
+    /// Each voxel type is meshed separately, so neighbors of the same type
+    /// hide the chunk too even if they aren't opaque (e.g. inside a lake),
+    /// there is no surface between them.
+    pub fn is_interior_chunk(&self, chunk_point: IVec3) -> bool {
+        match self.get_chunk(chunk_point) {
+            Some(VoxelNode::Solid { voxel, .. }) => self.surrounded_by(chunk_point, *voxel),
+            _ => false,
+        }
+    }
+
+    /// Are all 26 neighboring chunks filled with [opaque](Voxel::opaque)
+    /// voxels or `voxel`'s type.
+    fn surrounded_by(&self, chunk_point: IVec3, voxel: Voxel) -> bool {
+        let id = voxel.id();
+        let hides = |neighbor: Voxel| neighbor.id() == id || neighbor.opaque();
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let offset = IVec3::new(x, y, z);
+                    if offset == IVec3::ZERO {
+                        continue;
+                    }
+
+                    let hidden = match self.get_chunk(chunk_point + offset) {
+                        Some(VoxelNode::Solid { voxel, .. }) => hides(*voxel),
+                        Some(VoxelNode::Leaf { leaf, .. }) => {
+                            leaf.iter().all(|voxel| hides(*voxel))
+                        },
+                        Some(VoxelNode::Children { .. }) => {
+                            unreachable!("chunks are the lowest nodes")
+                        },
+                        // outside of the tree is air
+                        None => hides(Voxel::Air),
+                    };
+
+                    if !hidden {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Forget any damage done to the voxels inside `aabb`.
+    pub fn clear_damage_aabb(&mut self, aabb: VoxelAabb) {
+        if self.damage.is_empty() {
+            return;
+        }
+
+        let chunk_width = IVec3::splat(CHUNK_WIDTH as i32);
+        Arc::make_mut(&mut self.damage).retain(|chunk_point, chunk_damage| {
+            let chunk_min = *chunk_point * chunk_width;
+            let chunk_aabb = VoxelAabb::from_size(chunk_min, chunk_width);
+            match chunk_aabb.intersection(&aabb) {
+                None => true,
+                Some(overlap) if overlap == chunk_aabb => false,
+                Some(overlap) => {
+                    chunk_damage.retain(|voxel_index, _| {
+                        !overlap.contains(chunk_min + from_leaf_index(*voxel_index as usize))
+                    });
+                    !chunk_damage.is_empty()
+                },
+            }
+        });
+    }
+
+    #[inline]
+    fn damage_indices(voxel_point: IVec3) -> (IVec3, u16) {
+        let chunk_width = IVec3::splat(CHUNK_WIDTH as i32);
+        let chunk_point = voxel_point.div_euclid(chunk_width);
+        let voxel_index = to_leaf_index(voxel_point.rem_euclid(chunk_width));
+        (chunk_point, voxel_index as u16)
+    }
+
     pub fn set_chunk_data(&mut self, chunk_point: IVec3, chunk_data: [Voxel; 4096]) {
-        assert!(self.chunk_point_in_bounds(chunk_point));
-        self.root.set_chunk_data(chunk_point, chunk_data);
+        assert!(self.grow_to_contain_chunk(chunk_point));
+        self.root.set_chunk_data(chunk_point - self.origin, chunk_data);
+        self.touched_chunks.insert(chunk_point);
 
         // all neighbors should re-mesh
         self.mark_neighbors_changed(chunk_point);
     }
 
+    /// Write the simulation's results for a chunk back, `modified` are the
+    /// indices to copy from `voxels`.
+    ///
+    /// Only marks [`VoxelTree::changed_chunks`], the sim writing back its own
+    /// results isn't an edit (see [`VoxelTree::edited_chunks`]). Damage of the
+    /// written voxels is cleared.
+    pub fn write_sim_chunk(
+        &mut self,
+        chunk_point: IVec3,
+        voxels: &[Voxel; CHUNK_LENGTH],
+        modified: impl Iterator<Item = usize> + Clone,
+    ) {
+        assert!(self.grow_to_contain_chunk(chunk_point));
+        let relative = chunk_point - self.origin;
+        match self.root.get_chunk_mut(relative) {
+            VoxelNode::Solid { .. } => self.root.set_chunk_data(relative, *voxels),
+            VoxelNode::Leaf { leaf, .. } => {
+                let leaf = Arc::make_mut(leaf);
+                for voxel_index in modified.clone() {
+                    leaf[voxel_index] = voxels[voxel_index];
+                }
+            },
+            VoxelNode::Children { .. } => unreachable!("chunks are the lowest nodes"),
+        }
+        self.clear_chunk_damage(chunk_point, modified);
+        self.touched_chunks.insert(chunk_point);
+
+        // TODO: Be smarter about which chunks need to be updated here
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    self.changed_chunks.insert(chunk_point + IVec3::new(x, y, z));
+                }
+            }
+        }
+    }
+
+    /// Get the leaf at `chunk_point`, growing the tree and splitting solid
+    /// nodes as needed.
     pub fn get_leaf_mut(&mut self, chunk_point: IVec3) -> &mut VoxelNode {
-        assert!(self.chunk_point_in_bounds(chunk_point));
+        assert!(self.grow_to_contain_chunk(chunk_point));
         // we could do a better job about this.
         self.mark_neighbors_changed(chunk_point);
+        self.touched_chunks.insert(chunk_point);
+
+        self.root.get_leaf_mut(chunk_point - self.origin)
+    }
 
-        self.root.get_leaf_mut(chunk_point)
+    /// Get the [`VoxelNode::Solid`] or [`VoxelNode::Leaf`] at `chunk_point`,
+    /// `None` if it is out of bounds.
+    pub fn get_chunk(&self, chunk_point: IVec3) -> Option<&VoxelNode> {
+        if !self.chunk_point_in_bounds(chunk_point) {
+            return None;
+        }
+
+        Some(self.root.get_chunk(chunk_point - self.origin))
     }
 
     pub fn get_chunk_mut(&mut self, chunk_point: IVec3) -> &mut VoxelNode {
-        assert!(self.chunk_point_in_bounds(chunk_point));
+        assert!(self.grow_to_contain_chunk(chunk_point));
         // we could do a better job about this.
         self.mark_neighbors_changed(chunk_point);
+        self.touched_chunks.insert(chunk_point);
 
-        self.root.get_chunk_mut(chunk_point)
+        self.root.get_chunk_mut(chunk_point - self.origin)
     }
 
     pub fn mark_neighbors_changed(&mut self, chunk_point: IVec3) {
@@ -642,26 +1265,82 @@ impl VoxelTree {
             for y in -1..=1 {
                 for z in -1..=1 {
                     let offset = IVec3::new(x, y, z);
-                    self.changed_chunks.insert(chunk_point + offset);
+                    self.mark_changed(chunk_point + offset);
                 }
             }
         }
     }
 
+    fn mark_changed(&mut self, chunk_point: IVec3) {
+        self.changed_chunks.insert(chunk_point);
+        self.edited_chunks.insert(chunk_point);
+    }
+
     pub fn voxel_point_in_bounds(&self, voxel_point: IVec3) -> bool {
-        voxel_point.max_element() < self.root.voxel_width() as i32 && voxel_point.min_element() >= 0
+        let relative = voxel_point - self.voxel_origin();
+        relative.max_element() < self.root.voxel_width() as i32 && relative.min_element() >= 0
     }
 
     pub fn chunk_point_in_bounds(&self, chunk_point: IVec3) -> bool {
-        chunk_point.max_element() < self.root.chunk_width() as i32 && chunk_point.min_element() >= 0
+        let relative = chunk_point - self.origin;
+        relative.max_element() < self.root.chunk_width() as i32 && relative.min_element() >= 0
+    }
+}
+
+/// Iterator over the lowest nodes of a [`VoxelTree`] overlapping an aabb, see
+/// [`VoxelTree::iter_nodes_in`].
+pub struct NodesIn<'a> {
+    aabb: VoxelAabb,
+    /// Voxel point of each node's minimum corner.
+    stack: Vec<(IVec3, &'a VoxelNode)>,
+}
+
+impl<'a> Iterator for NodesIn<'a> {
+    type Item = (VoxelAabb, &'a VoxelNode);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((origin, node)) = self.stack.pop() {
+            let node_aabb = VoxelAabb::from_size(origin, IVec3::splat(node.voxel_width() as i32));
+            if !node_aabb.overlaps(&self.aabb) {
+                continue;
+            }
+
+            match node {
+                VoxelNode::Children { shared, children } => {
+                    let child_width = layer_width_voxel(shared.layer - 1) as i32;
+                    for (child_index, child) in children.iter().enumerate().rev() {
+                        let child_origin = origin + from_child_index(child_index) * child_width;
+                        self.stack.push((child_origin, child));
+                    }
+                },
+                VoxelNode::Solid { .. } | VoxelNode::Leaf { .. } => {
+                    return Some((node_aabb, node));
+                },
+            }
+        }
+
+        None
     }
 }
 
+/// Remove a chunk's damage, only copying shared damage if there is any.
+fn remove_chunk_damage(damage: &mut Arc<HashMap<IVec3, ChunkDamage>>, chunk_point: IVec3) {
+    if damage.contains_key(&chunk_point) {
+        Arc::make_mut(damage).remove(&chunk_point);
+    }
+}
+
+fn aabb_volume(aabb: VoxelAabb) -> u64 {
+    let size = aabb.size();
+    size.x as u64 * size.y as u64 * size.z as u64
+}
+
 #[cfg(test)]
 mod test {
     use bevy::prelude::*;
 
     use super::*;
+    use crate::voxel::simulation::kinds::liquid::LiquidState;
 
     #[test]
     pub fn get_set_sanity() {
@@ -717,6 +1396,377 @@ mod test {
         }
     }
 
+    #[test]
+    pub fn damage() {
+        let mut tree = VoxelTree::new();
+        tree.grow_n_layers(2);
+
+        let point = IVec3::new(3, 4, 5);
+        tree.set_voxel(point, Voxel::Dirt);
+        let health = Voxel::Dirt.starting_health();
+
+        assert_eq!(tree.damage_voxel(point, health - 1), None);
+        assert_eq!(tree.voxel_health(point), 1);
+        assert_eq!(tree.get_voxel(point), Voxel::Dirt);
+
+        assert_eq!(tree.damage_voxel(point, 1), Some(Voxel::Dirt));
+        assert_eq!(tree.get_voxel(point), Voxel::Air);
+        assert!(tree.damage.is_empty());
+
+        // unbreakable voxels are immune
+        for voxel in [Voxel::Base, Voxel::Barrier] {
+            tree.set_voxel(point, voxel);
+            assert_eq!(tree.damage_voxel(point, i16::MAX), None);
+            assert_eq!(tree.get_voxel(point), voxel);
+        }
+        assert!(tree.damage.is_empty());
+    }
+
+    #[test]
+    pub fn damage_resets_on_compress() {
+        let mut tree = VoxelTree::new();
+        tree.grow_n_layers(2);
+
+        // a chunk that is already solid keeps its damage
+        let VoxelNode::Leaf { leaf, .. } = tree.get_leaf_mut(IVec3::ZERO) else {
+            panic!("expected a leaf");
+        };
+        Arc::make_mut(leaf).fill(Voxel::Stone);
+        tree.compress();
+        assert!(tree.root.get_chunk(IVec3::ZERO).is_solid());
+
+        tree.damage_voxel(IVec3::ZERO, 1);
+        tree.compress();
+        assert_eq!(tree.voxel_health(IVec3::ZERO), Voxel::Stone.starting_health() - 1);
+
+        // a leaf collapsing into a solid resets its damage
+        let chunk_point = IVec3::new(1, 0, 0);
+        let VoxelNode::Leaf { leaf, .. } = tree.get_leaf_mut(chunk_point) else {
+            panic!("expected a leaf");
+        };
+        let leaf = Arc::make_mut(leaf);
+        leaf.fill(Voxel::Stone);
+        leaf[0] = Voxel::Dirt;
+
+        let damaged = chunk_point * CHUNK_WIDTH as i32 + IVec3::ONE;
+        tree.damage_voxel(damaged, 1);
+        tree.compress();
+        assert_eq!(tree.voxel_health(damaged), Voxel::Stone.starting_health() - 1);
+
+        // sim writes straight into the leaf, bypassing `set_voxel`
+        let VoxelNode::Leaf { leaf, .. } = tree.get_leaf_mut(chunk_point) else {
+            panic!("expected a leaf");
+        };
+        Arc::make_mut(leaf)[0] = Voxel::Stone;
+        tree.compress();
+        assert!(tree.root.get_chunk(chunk_point).is_solid());
+        assert_eq!(tree.voxel_health(damaged), Voxel::Stone.starting_health());
+    }
+
+    #[test]
+    pub fn compress_touched() {
+        let mut tree = VoxelTree::new();
+        tree.grow_n_layers(2);
+
+        // fill a chunk one voxel at a time, then dig a hole in the next one
+        for x in 0..16 {
+            for y in 0..16 {
+                for z in 0..16 {
+                    tree.set_voxel(IVec3::new(x, y, z), Voxel::Stone);
+                    tree.set_voxel(IVec3::new(x, y, z) + IVec3::X * 16, Voxel::Stone);
+                }
+            }
+        }
+        tree.set_voxel(IVec3::new(20, 3, 3), Voxel::Air);
+        assert_eq!(tree.touched_chunks.len(), 2);
+
+        let mut full = tree.clone();
+        full.compress();
+        tree.compress_touched();
+
+        assert!(tree.touched_chunks.is_empty());
+        assert!(tree.root == full.root);
+        assert!(tree.root.get_chunk(IVec3::ZERO).is_solid());
+        assert!(!tree.root.get_chunk(IVec3::X).is_solid());
+
+        // filling the rest of the tree collapses the ancestors too
+        tree.set_voxel(IVec3::new(20, 3, 3), Voxel::Stone);
+        let width = tree.root.chunk_width() as i32;
+        for x in 0..width {
+            for y in 0..width {
+                for z in 0..width {
+                    let chunk_point = IVec3::new(x, y, z);
+                    if chunk_point.cmple(IVec3::X).all() {
+                        continue;
+                    }
+
+                    let VoxelNode::Leaf { leaf, .. } = tree.get_leaf_mut(chunk_point) else {
+                        panic!("expected a leaf");
+                    };
+                    Arc::make_mut(leaf).fill(Voxel::Stone);
+                }
+            }
+        }
+
+        tree.compress_touched();
+        assert!(matches!(tree.root, VoxelNode::Solid { voxel: Voxel::Stone, .. }));
+    }
+
+    #[test]
+    pub fn fill_aabb() {
+        let mut tree = VoxelTree::new();
+        tree.grow_n_layers(2);
+
+        // covers the whole first layer 1 node and part of its neighbors
+        let aabb = VoxelAabb::new(IVec3::ZERO, IVec3::new(69, 63, 63));
+        let damaged = IVec3::new(66, 1, 1);
+        tree.set_voxel(damaged, Voxel::Dirt);
+        tree.damage_voxel(damaged, 1);
+        tree.changed_chunks.clear();
+
+        tree.fill_aabb(aabb, Voxel::Stone);
+        let VoxelNode::Children { children, .. } = &tree.root else {
+            panic!("expected children");
+        };
+        assert!(matches!(children[0], VoxelNode::Solid { voxel: Voxel::Stone, .. }));
+        assert!(tree.damage.is_empty());
+
+        let mut expected = VoxelTree::new();
+        expected.grow_n_layers(2);
+        for x in aabb.min.x..=aabb.max.x {
+            for y in aabb.min.y..=aabb.max.y {
+                for z in aabb.min.z..=aabb.max.z {
+                    expected.set_voxel(IVec3::new(x, y, z), Voxel::Stone);
+                }
+            }
+        }
+
+        assert_eq!(tree.changed_chunks, expected.changed_chunks);
+        let width = tree.root.voxel_width() as i32;
+        for x in 0..width {
+            for y in 0..aabb.max.y + 2 {
+                for z in 0..aabb.max.z + 2 {
+                    let point = IVec3::new(x, y, z);
+                    assert_eq!(tree.get_voxel(point), expected.get_voxel(point), "{point}");
+                }
+            }
+        }
+
+        // filling with what is already there changes nothing
+        tree.changed_chunks.clear();
+        tree.fill_aabb(aabb, Voxel::Stone);
+        assert!(tree.changed_chunks.is_empty());
+    }
+
+    #[test]
+    pub fn count_by_type() {
+        let mut tree = VoxelTree::new();
+        tree.grow_n_layers(2);
+        tree.fill_aabb(VoxelAabb::new(IVec3::ZERO, IVec3::new(255, 31, 255)), Voxel::Dirt);
+        tree.fill_aabb(VoxelAabb::new(IVec3::new(0, 32, 0), IVec3::new(63, 63, 63)), Voxel::Stone);
+        tree.set_voxel(IVec3::new(3, 4, 5), Voxel::Water(default()));
+        tree.set_voxel(IVec3::new(4, 4, 5), Voxel::Water(LiquidState::from_bits(0b1011_0101)));
+        tree.set_voxel(IVec3::new(40, 40, 20), Voxel::Air);
+
+        // crosses solid nodes, leaves and the edge of the tree
+        let aabb = VoxelAabb::new(IVec3::new(-10, 2, 1), IVec3::new(70, 50, 30));
+        let mut expected = HashMap::new();
+        for x in aabb.min.x..=aabb.max.x {
+            for y in aabb.min.y..=aabb.max.y {
+                for z in aabb.min.z..=aabb.max.z {
+                    let voxel = tree.get_voxel(IVec3::new(x, y, z));
+                    *expected.entry(voxel).or_insert(0u64) += 1;
+                }
+            }
+        }
+
+        let counts = tree.count_by_type(aabb);
+        assert_eq!(counts, expected);
+        assert_eq!(counts[&Voxel::Water(default())], 1);
+        assert_eq!(counts[&Voxel::Water(LiquidState::from_bits(0b1011_0101))], 1);
+        assert_eq!(counts[&Voxel::Stone], 64 * 19 * 30 - 1);
+        assert_eq!(counts.values().sum::<u64>(), aabb.volume() as u64);
+
+        let leaves = tree.iter_leaves_in(aabb).map(|(chunk_point, _)| chunk_point);
+        assert_eq!(leaves.collect::<Vec<_>>(), vec![IVec3::ZERO, IVec3::new(2, 2, 1)]);
+
+        // solid regions and leaves cover the part of the aabb inside the tree
+        let leaf_volume = tree
+            .iter_leaves_in(aabb)
+            .map(|(chunk_point, _)| {
+                let chunk_aabb = VoxelAabb::from_size(chunk_point * 16, IVec3::splat(16));
+                chunk_aabb.intersection(&aabb).unwrap().volume()
+            })
+            .sum::<i32>();
+        let solid_volume = tree
+            .iter_solid_regions_in(aabb)
+            .map(|(region, _)| {
+                assert!(region.fits_inside(&aabb));
+                region.volume()
+            })
+            .sum::<i32>();
+        let tree_aabb = VoxelAabb::from_size(IVec3::ZERO, IVec3::splat(256));
+        assert_eq!(solid_volume + leaf_volume, aabb.intersection(&tree_aabb).unwrap().volume());
+    }
+
+    #[test]
+    pub fn snapshot() {
+        let mut tree = VoxelTree::new();
+        tree.grow_n_layers(2);
+        tree.fill_aabb(VoxelAabb::new(IVec3::ZERO, IVec3::new(255, 31, 255)), Voxel::Dirt);
+        tree.set_voxel(IVec3::new(3, 4, 5), Voxel::Stone);
+        tree.set_voxel(IVec3::new(100, 4, 5), Voxel::Stone);
+        tree.damage_voxel(IVec3::new(1, 1, 1), 1);
+
+        let snapshot = tree.snapshot();
+        let children = |tree: &VoxelTree| match &tree.root {
+            VoxelNode::Children { children, .. } => children.clone(),
+            _ => panic!("expected children"),
+        };
+        assert!(Arc::ptr_eq(&children(&tree), &children(&snapshot)));
+        assert!(Arc::ptr_eq(&tree.damage, &snapshot.damage));
+
+        // only the modified path is copied
+        tree.set_voxel(IVec3::new(3, 4, 5), Voxel::Air);
+        tree.damage_voxel(IVec3::new(1, 1, 1), 1);
+        let (tree_children, snapshot_children) = (children(&tree), children(&snapshot));
+        assert!(!Arc::ptr_eq(&tree_children, &snapshot_children));
+        assert!(tree_children[0] != snapshot_children[0]);
+        assert!(tree_children[1..] == snapshot_children[1..]);
+        match (&tree_children[1], &snapshot_children[1]) {
+            (VoxelNode::Children { children: a, .. }, VoxelNode::Children { children: b, .. }) => {
+                assert!(Arc::ptr_eq(a, b));
+            },
+            _ => panic!("expected children"),
+        }
+
+        let snapshot = std::thread::spawn(move || {
+            assert_eq!(snapshot.get_voxel(IVec3::new(3, 4, 5)), Voxel::Stone);
+            assert_eq!(
+                snapshot.voxel_health(IVec3::new(1, 1, 1)),
+                Voxel::Dirt.starting_health() - 1
+            );
+            snapshot
+        })
+        .join()
+        .unwrap();
+        assert_eq!(tree.get_voxel(IVec3::new(3, 4, 5)), Voxel::Air);
+        assert_eq!(tree.voxel_health(IVec3::new(1, 1, 1)), Voxel::Dirt.starting_health() - 2);
+
+        // compressing doesn't copy shared nodes that stay the same
+        tree.compress();
+        assert!(Arc::ptr_eq(
+            match &children(&tree)[1] {
+                VoxelNode::Children { children, .. } => children,
+                _ => panic!("expected children"),
+            },
+            match &children(&snapshot)[1] {
+                VoxelNode::Children { children, .. } => children,
+                _ => panic!("expected children"),
+            },
+        ));
+    }
+
+    #[test]
+    pub fn grow_towards_negative() {
+        let mut tree = VoxelTree::new();
+        tree.grow_n_layers(1);
+
+        let point = IVec3::new(3, 4, 5);
+        tree.set_voxel(point, Voxel::Dirt);
+        assert_eq!(tree.get_voxel(IVec3::new(-1, 0, 0)), Voxel::Air);
+
+        // growing keeps the existing voxels where they were
+        let negative = IVec3::new(-5, 20, -70);
+        tree.set_voxel(negative, Voxel::Stone);
+        assert!(tree.voxel_point_in_bounds(negative));
+        assert!(tree.origin.x < 0 && tree.origin.y == 0 && tree.origin.z < 0);
+        assert_eq!(tree.get_voxel(point), Voxel::Dirt);
+        assert_eq!(tree.get_voxel(negative), Voxel::Stone);
+        assert_eq!(tree.get_voxel(negative + IVec3::X), Voxel::Air);
+
+        // damage and change tracking use absolute points
+        tree.damage_voxel(negative, 1);
+        assert_eq!(tree.voxel_health(negative), Voxel::Stone.starting_health() - 1);
+        assert!(tree.changed_chunks.contains(&IVec3::new(-1, 1, -5)));
+
+        tree.set_voxel(negative, Voxel::Air);
+        tree.set_voxel(point, Voxel::Air);
+        tree.compress_touched();
+        assert!(matches!(tree.root, VoxelNode::Solid { voxel: Voxel::Air, .. }));
+    }
+
+    #[test]
+    pub fn grow_solid_root() {
+        let mut tree = VoxelTree::new();
+        tree.root = VoxelNode::Solid {
+            shared: SharedData { layer: 0, authority: true },
+            voxel: Voxel::Stone,
+        };
+
+        tree.grow_layer_towards(BVec3::new(true, false, true));
+        assert_eq!(tree.origin, IVec3::new(-3, 0, -3));
+        assert_eq!(tree.get_voxel(IVec3::new(0, 0, 0)), Voxel::Stone);
+        assert_eq!(tree.get_voxel(IVec3::new(15, 15, 15)), Voxel::Stone);
+        assert_eq!(tree.get_voxel(IVec3::new(-1, 0, 0)), Voxel::Air);
+        assert_eq!(tree.get_voxel(IVec3::new(16, 0, 0)), Voxel::Air);
+    }
+
+    #[test]
+    pub fn renderable_shell() {
+        fn renderable(tree: &VoxelTree) -> HashSet<IVec3> {
+            let mut buffer = Vec::new();
+            tree.renderable_chunks(&mut buffer);
+            let chunks = buffer.iter().copied().collect::<HashSet<_>>();
+            assert_eq!(chunks.len(), buffer.len(), "duplicate chunks");
+            chunks
+        }
+
+        let mut tree = VoxelTree::new();
+        tree.grow_n_layers(3);
+        let (min, max) = tree.voxel_bounds();
+        tree.fill_aabb(VoxelAabb::new(min, max - IVec3::ONE), Voxel::Stone);
+        tree.compress();
+        assert!(matches!(tree.root, VoxelNode::Solid { shared: SharedData { layer: 3, .. }, .. }));
+
+        let width = tree.root.chunk_width() as i32;
+        let on_shell = |chunk_point: IVec3| {
+            chunk_point.min_element() == 0 || chunk_point.max_element() == width - 1
+        };
+        let shell = renderable(&tree);
+        assert_eq!(shell.len(), (64 * 64 * 64) - (62 * 62 * 62));
+        assert!(shell.iter().all(|chunk_point| on_shell(*chunk_point)));
+
+        assert!(tree.is_interior_chunk(IVec3::splat(32)));
+        assert!(tree.is_interior_chunk(IVec3::splat(1)));
+        assert!(!tree.is_interior_chunk(IVec3::new(0, 5, 5)));
+        assert!(!tree.is_interior_chunk(IVec3::splat(-1)));
+
+        // a hole exposes its chunk and the neighbors
+        tree.set_voxel(IVec3::splat(10 * 16 + 3), Voxel::Air);
+        let with_hole = renderable(&tree);
+        assert_eq!(with_hole.len(), shell.len() + 27);
+        assert!(with_hole.contains(&IVec3::splat(9)));
+        assert!(!tree.is_interior_chunk(IVec3::splat(11)));
+        assert!(tree.is_interior_chunk(IVec3::splat(12)));
+
+        // opaque voxels of another type hide the stone, and the stone hides them
+        let block = VoxelAabb::from_size(IVec3::splat(40 * 16), IVec3::splat(32));
+        tree.fill_aabb(block, Voxel::Dirt);
+        tree.compress();
+        assert_eq!(renderable(&tree), with_hole);
+        assert!(tree.is_interior_chunk(IVec3::splat(39)));
+        assert!(tree.is_interior_chunk(IVec3::splat(40)));
+
+        // see-through voxels expose the stone around them, but not each other
+        tree.fill_aabb(block, Voxel::Glass);
+        tree.compress();
+        let with_glass = renderable(&tree);
+        assert_eq!(with_glass.len(), with_hole.len() + (4 * 4 * 4) - (2 * 2 * 2));
+        assert!(!tree.is_interior_chunk(IVec3::splat(39)));
+        assert!(tree.is_interior_chunk(IVec3::splat(40)));
+    }
+
     // #[test]
     // pub fn compress() {
     //     let mut tree = VoxelTree::new();
@@ -1,12 +1,14 @@
+use std::borrow::Cow;
+
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
+use crate::voxel::registry;
+use crate::voxel::simulation::kinds::gas::{DEFAULT_GAS_STATE, GasState};
 use crate::voxel::simulation::kinds::liquid::{DEFAULT_LIQUID_STATE, LiquidState};
 
 pub fn plugin(app: &mut App) {
     app.register_type::<Voxel>();
-    app.register_type::<VoxelMaterials>();
-    app.add_systems(Startup, VoxelMaterials::setup);
 }
 
 pub type VoxelId = u8;
@@ -14,6 +16,11 @@ pub type VoxelData = u8;
 pub type VoxelBits = u16;
 pub const VOXEL_ID_BITCOUNT: usize = std::mem::size_of::<VoxelId>() * 8;
 pub const VOXEL_DATA_BITCOUNT: usize = std::mem::size_of::<VoxelData>() * 8;
+/// Total number of ids addressable by the packed voxel format.
+pub const VOXEL_ID_COUNT: usize = 1 << VOXEL_ID_BITCOUNT;
+/// First id available to custom voxels. Ids between the builtins and this are
+/// reserved so new builtins don't move custom voxels, saves store voxels by id.
+pub const CUSTOM_VOXEL_ID_START: usize = 32;
 
 #[derive(Reflect, Hash, PartialEq, Eq, Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum Voxel {
@@ -27,6 +34,8 @@ pub enum Voxel {
     Dirt,
     Grass,
     Stone,
+    Ice,
+    Glass,
 
     // semi-solids (falling sand)
     Sand,
@@ -34,9 +43,22 @@ pub enum Voxel {
     // liquids
     Water(LiquidState), // TODO: add lateral velocity to remove oscillation?
     Oil(LiquidState),
+    Lava(LiquidState),
+
+    // gases
+    Steam(GasState),
+    Smoke(GasState),
+    Methane(GasState),
 
     // Special
-    Fire { voxel_id: VoxelData },
+    Fire {
+        voxel_id: VoxelData,
+    },
+
+    /// Voxel type defined only by the
+    /// [`VoxelRegistry`](registry::VoxelRegistry), ids are always past the
+    /// builtin voxels.
+    Custom(VoxelId),
 }
 
 // pub struct VoxelData(u16);
@@ -44,8 +66,9 @@ pub enum Voxel {
 #[inline]
 pub fn pack_voxel(voxel: Voxel) -> VoxelBits {
     let extra_data: VoxelData = match voxel {
-        Voxel::Water(state) | Voxel::Oil(state) => state.bits(),
+        Voxel::Water(state) | Voxel::Oil(state) | Voxel::Lava(state) => state.bits(),
         Voxel::Fire { voxel_id } => voxel_id,
+        Voxel::Steam(state) | Voxel::Smoke(state) | Voxel::Methane(state) => state.bits(),
         _ => 0,
     };
 
@@ -68,18 +91,67 @@ pub fn unpack_voxel(data: VoxelBits) -> Voxel {
         7 => Voxel::Water(LiquidState::from_bits(extra_data)),
         8 => Voxel::Oil(LiquidState::from_bits(extra_data)),
         9 => Voxel::Fire { voxel_id: extra_data },
-        _ => panic!("Invalid voxel id: {}", id),
+        10 => Voxel::Steam(GasState::from_bits(extra_data)),
+        11 => Voxel::Smoke(GasState::from_bits(extra_data)),
+        12 => Voxel::Methane(GasState::from_bits(extra_data)),
+        13 => Voxel::Lava(LiquidState::from_bits(extra_data)),
+        14 => Voxel::Ice,
+        15 => Voxel::Glass,
+        _ => Voxel::Custom(id as VoxelId),
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Interactions {
     /// What voxel this turns into after being burnt.
     /// None means it isn't flammable.
     pub burnt: Option<Voxel>,
+    /// Reactions with neighbouring voxels, evaluated by the falling sands sim.
+    pub reactions: &'static [Reaction],
+    /// Temperature this voxel pulls the heat field towards.
+    /// None means it doesn't affect the heat field.
+    pub temperature: Option<i16>,
+    /// What this voxel turns into when the heat field gets hot enough.
+    pub heated: Option<PhaseChange>,
+    /// What this voxel turns into when the heat field gets cold enough.
+    pub cooled: Option<PhaseChange>,
+    /// What this voxel turns into when it can't carry its load.
+    /// None means it never crumbles.
+    pub crumbled: Option<Voxel>,
 }
 
-pub const DEFAULT_INTERACTIONS: Interactions = Interactions { burnt: None };
+pub const DEFAULT_INTERACTIONS: Interactions = Interactions {
+    burnt: None,
+    reactions: &[],
+    temperature: None,
+    heated: None,
+    cooled: None,
+    crumbled: None,
+};
+
+/// Phase change driven by the heat field, e.g. water -> steam above 100.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PhaseChange {
+    /// Temperature the heat field has to pass for this to happen.
+    pub temperature: i16,
+    pub becomes: Voxel,
+}
+
+/// This voxel reacting with a neighbouring voxel, e.g. water + lava -> steam +
+/// stone.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Reaction {
+    /// Neighbouring voxel type that triggers the reaction, only the id is
+    /// compared.
+    pub with: Voxel,
+    /// What this voxel turns into, `None` leaves it as is. Turning into fire
+    /// sets flammable voxels alight instead of replacing them with a flame.
+    pub becomes: Option<Voxel>,
+    /// What the neighbouring voxel turns into, `None` leaves it as is.
+    pub other_becomes: Option<Voxel>,
+    /// Chance of reacting per simulation tick, `0.0..=1.0`.
+    pub chance: f32,
+}
 
 impl Default for Interactions {
     fn default() -> Self {
@@ -87,10 +159,58 @@ impl Default for Interactions {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VoxelPbr {
+    pub base_color: Color,
+    pub perceptual_roughness: f32,
+    pub reflectance: f32,
+    pub metallic: f32,
+    /// Alpha blend the material, otherwise it is opaque.
+    pub alpha_blend: bool,
+    /// Layer of the voxel texture pack to triplanar map onto the surface, flat
+    /// `base_color` if `None`.
+    pub texture_layer: Option<u32>,
+    /// Number of animation frames, stored in consecutive layers after
+    /// `texture_layer`.
+    pub texture_frames: u32,
+}
+
+pub const DEFAULT_PBR: VoxelPbr = VoxelPbr {
+    base_color: Color::WHITE,
+    perceptual_roughness: 1.0,
+    reflectance: 0.1,
+    metallic: 0.0,
+    alpha_blend: false,
+    texture_layer: None,
+    texture_frames: 1,
+};
+
+impl VoxelPbr {
+    /// Untextured material, see
+    /// [`VoxelMaterials`](crate::voxel::material::VoxelMaterials) for the
+    /// textured chunk material.
+    pub fn standard_material(&self) -> StandardMaterial {
+        StandardMaterial {
+            base_color: self.base_color,
+            perceptual_roughness: self.perceptual_roughness,
+            reflectance: self.reflectance,
+            metallic: self.metallic,
+            alpha_mode: if self.alpha_blend { AlphaMode::Blend } else { AlphaMode::Opaque },
+            ..default()
+        }
+    }
+}
+
+impl Default for VoxelPbr {
+    fn default() -> Self {
+        DEFAULT_PBR
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct VoxelDefinition {
     pub voxel: Voxel,
-    pub name: &'static str,
+    pub name: Cow<'static, str>,
     /// How does this interaction with falling sands sim (cellular automaton)?
     pub simulation_kind: SimKind,
     /// Should we simulate this voxel?
@@ -107,8 +227,13 @@ pub struct VoxelDefinition {
     pub breakable: bool,
     /// Initial health of the voxel.
     pub initial_health: i16,
-    /// "Density" of voxel, only really important for liquids/gases
+    /// "Density" of voxel, moving voxels sink through lighter liquids and
+    /// gases, see [`Voxel::sinks_through`].
     pub density: i8,
+    /// How far this voxel can carry load sideways from its support, see
+    /// [`structure`](crate::voxel::simulation::structure). 0 only holds up
+    /// what sits directly on top of it.
+    pub strength: u8,
 
     /// Should this voxel cast shadows?
     pub shadow_caster: bool,
@@ -116,6 +241,9 @@ pub struct VoxelDefinition {
     pub shadow_receiver: bool,
 
     pub interactions: Interactions,
+
+    /// Parameters for the render material of this voxel.
+    pub material: VoxelPbr,
 }
 
 #[derive(
@@ -132,7 +260,7 @@ pub enum SimKind {
 pub const VOXEL_DEFINITIONS: &[&'static VoxelDefinition] = &[
     &VoxelDefinition {
         voxel: Voxel::Air,
-        name: "air",
+        name: Cow::Borrowed("air"),
         simulation_kind: SimKind::Gas,
         simulated: false,
         collidable: false,
@@ -142,14 +270,16 @@ pub const VOXEL_DEFINITIONS: &[&'static VoxelDefinition] = &[
         breakable: false,
         initial_health: 0,
         density: 0,
+        strength: 0,
         shadow_caster: false,
         shadow_receiver: false,
 
         interactions: DEFAULT_INTERACTIONS,
+        material: DEFAULT_PBR,
     },
     &VoxelDefinition {
         voxel: Voxel::Base,
-        name: "base",
+        name: Cow::Borrowed("base"),
         simulation_kind: SimKind::Solid,
         simulated: false,
         collidable: true,
@@ -159,14 +289,16 @@ pub const VOXEL_DEFINITIONS: &[&'static VoxelDefinition] = &[
         breakable: false,
         initial_health: 0,
         density: 0,
+        strength: 0,
         shadow_caster: true,
         shadow_receiver: true,
 
         interactions: DEFAULT_INTERACTIONS,
+        material: VoxelPbr { base_color: Color::srgb(0.6, 0.6, 0.6), ..DEFAULT_PBR },
     },
     &VoxelDefinition {
         voxel: Voxel::Barrier, // base but transparent
-        name: "barrier",
+        name: Cow::Borrowed("barrier"),
         simulation_kind: SimKind::Solid,
         simulated: false,
         collidable: true,
@@ -176,14 +308,16 @@ pub const VOXEL_DEFINITIONS: &[&'static VoxelDefinition] = &[
         breakable: false,
         initial_health: 0,
         density: 0,
+        strength: 0,
         shadow_caster: false,
         shadow_receiver: false,
 
         interactions: DEFAULT_INTERACTIONS,
+        material: DEFAULT_PBR,
     },
     &VoxelDefinition {
         voxel: Voxel::Dirt,
-        name: "dirt",
+        name: Cow::Borrowed("dirt"),
         simulation_kind: SimKind::Solid,
         simulated: true,
         collidable: true,
@@ -193,14 +327,20 @@ pub const VOXEL_DEFINITIONS: &[&'static VoxelDefinition] = &[
         breakable: true,
         initial_health: 10,
         density: 0,
+        strength: 4,
         shadow_caster: true,
         shadow_receiver: true,
 
-        interactions: DEFAULT_INTERACTIONS,
+        interactions: Interactions { crumbled: Some(Voxel::Sand), ..DEFAULT_INTERACTIONS },
+        material: VoxelPbr {
+            base_color: Color::srgb(79.0 / 225.0, 55.0 / 255.0, 39.0 / 255.0),
+            texture_layer: Some(1),
+            ..DEFAULT_PBR
+        },
     },
     &VoxelDefinition {
         voxel: Voxel::Grass,
-        name: "grass",
+        name: Cow::Borrowed("grass"),
         simulation_kind: SimKind::Solid,
         simulated: false,
         collidable: true,
@@ -210,14 +350,24 @@ pub const VOXEL_DEFINITIONS: &[&'static VoxelDefinition] = &[
         breakable: true,
         initial_health: 10,
         density: 0,
+        strength: 4,
         shadow_caster: true,
         shadow_receiver: true,
 
-        interactions: Interactions { burnt: Some(Voxel::Dirt) },
+        interactions: Interactions {
+            burnt: Some(Voxel::Dirt),
+            crumbled: Some(Voxel::Sand),
+            ..DEFAULT_INTERACTIONS
+        },
+        material: VoxelPbr {
+            base_color: Color::srgb(126.0 / 225.0, 200.0 / 255.0, 80.0 / 255.0),
+            texture_layer: Some(2),
+            ..DEFAULT_PBR
+        },
     },
     &VoxelDefinition {
         voxel: Voxel::Stone,
-        name: "stone",
+        name: Cow::Borrowed("stone"),
         simulation_kind: SimKind::Solid,
         simulated: false,
         collidable: true,
@@ -227,14 +377,16 @@ pub const VOXEL_DEFINITIONS: &[&'static VoxelDefinition] = &[
         breakable: true,
         initial_health: 100,
         density: 0,
+        strength: 16,
         shadow_caster: true,
         shadow_receiver: true,
 
         interactions: DEFAULT_INTERACTIONS,
+        material: VoxelPbr { texture_layer: Some(0), ..DEFAULT_PBR },
     },
     &VoxelDefinition {
         voxel: Voxel::Sand,
-        name: "sand",
+        name: Cow::Borrowed("sand"),
         simulation_kind: SimKind::SemiSolid,
         simulated: true,
         collidable: true,
@@ -243,16 +395,30 @@ pub const VOXEL_DEFINITIONS: &[&'static VoxelDefinition] = &[
         pickable: true,
         breakable: true,
         initial_health: 10,
-        density: 0,
+        density: 50,
+        strength: 0,
         shadow_caster: true,
         shadow_receiver: true,
 
-        interactions: DEFAULT_INTERACTIONS,
+        interactions: Interactions {
+            reactions: &[Reaction {
+                with: Voxel::Fire { voxel_id: 0 },
+                becomes: Some(Voxel::Glass),
+                other_becomes: None,
+                chance: 0.05,
+            }],
+            ..DEFAULT_INTERACTIONS
+        },
+        material: VoxelPbr {
+            base_color: Color::srgba(0.396, 0.314, 0.113, 1.0),
+            texture_layer: Some(3),
+            ..DEFAULT_PBR
+        },
     },
     &VoxelDefinition {
         voxel: Voxel::Water(DEFAULT_LIQUID_STATE),
         // voxel: Voxel::Water,
-        name: "water",
+        name: Cow::Borrowed("water"),
         simulation_kind: SimKind::Liquid,
         simulated: true,
         collidable: false,
@@ -262,15 +428,46 @@ pub const VOXEL_DEFINITIONS: &[&'static VoxelDefinition] = &[
         breakable: true,
         initial_health: 10,
         density: 40,
+        strength: 0,
         shadow_caster: false,
         shadow_receiver: true,
 
-        interactions: DEFAULT_INTERACTIONS,
+        interactions: Interactions {
+            reactions: &[
+                Reaction {
+                    with: Voxel::Fire { voxel_id: 0 },
+                    becomes: Some(Voxel::Steam(DEFAULT_GAS_STATE)),
+                    other_becomes: Some(Voxel::Air),
+                    chance: 0.25,
+                },
+                Reaction {
+                    with: Voxel::Lava(DEFAULT_LIQUID_STATE),
+                    becomes: Some(Voxel::Steam(DEFAULT_GAS_STATE)),
+                    other_becomes: Some(Voxel::Stone),
+                    chance: 0.5,
+                },
+            ],
+            temperature: Some(15),
+            heated: Some(PhaseChange {
+                temperature: 100,
+                becomes: Voxel::Steam(DEFAULT_GAS_STATE),
+            }),
+            ..DEFAULT_INTERACTIONS
+        },
+        material: VoxelPbr {
+            base_color: Color::srgba(10.0 / 225.0, 10.0 / 255.0, 150.0 / 255.0, 0.2),
+            perceptual_roughness: 0.5,
+            reflectance: 0.5,
+            alpha_blend: true,
+            texture_layer: Some(8),
+            texture_frames: 2,
+            ..DEFAULT_PBR
+        },
     },
     &VoxelDefinition {
         voxel: Voxel::Oil(DEFAULT_LIQUID_STATE),
         // voxel: Voxel::Oil,
-        name: "oil",
+        name: Cow::Borrowed("oil"),
         simulation_kind: SimKind::Liquid,
         simulated: true,
         collidable: false,
@@ -280,15 +477,32 @@ pub const VOXEL_DEFINITIONS: &[&'static VoxelDefinition] = &[
         breakable: true,
         initial_health: 10,
         density: 10,
+        strength: 0,
         shadow_caster: false,
         shadow_receiver: true,
 
-        interactions: Interactions { burnt: Some(Voxel::Air) },
+        interactions: Interactions {
+            burnt: Some(Voxel::Air),
+            reactions: &[Reaction {
+                with: Voxel::Fire { voxel_id: 0 },
+                becomes: Some(Voxel::Fire { voxel_id: 0 }),
+                other_becomes: None,
+                chance: 0.05,
+            }],
+            ..DEFAULT_INTERACTIONS
+        },
+        material: VoxelPbr {
+            base_color: Color::srgba(79.0 / 225.0, 55.0 / 255.0, 39.0 / 255.0, 0.2),
+            perceptual_roughness: 0.5,
+            reflectance: 0.9,
+            alpha_blend: true,
+            ..DEFAULT_PBR
+        },
     },
     &VoxelDefinition {
         voxel: Voxel::Fire { voxel_id: 0 },
         // voxel: Voxel::Fire,
-        name: "fire",
+        name: Cow::Borrowed("fire"),
         simulation_kind: SimKind::Special,
         simulated: true,
         collidable: false,
@@ -298,24 +512,192 @@ pub const VOXEL_DEFINITIONS: &[&'static VoxelDefinition] = &[
         breakable: true,
         initial_health: 10,
         density: 10,
+        strength: 0,
         shadow_caster: false,
         shadow_receiver: false,
 
+        interactions: Interactions { temperature: Some(600), ..DEFAULT_INTERACTIONS },
+        material: DEFAULT_PBR,
+    },
+    &VoxelDefinition {
+        voxel: Voxel::Steam(DEFAULT_GAS_STATE),
+        name: Cow::Borrowed("steam"),
+        simulation_kind: SimKind::Gas,
+        simulated: true,
+        collidable: false,
+        rendered: true,
+        transparent: true,
+        pickable: false,
+        breakable: false,
+        initial_health: 0,
+        density: -10,
+        strength: 0,
+        shadow_caster: false,
+        shadow_receiver: false,
+
+        interactions: DEFAULT_INTERACTIONS,
+        material: VoxelPbr {
+            base_color: Color::srgba(0.9, 0.9, 0.95, 0.3),
+            alpha_blend: true,
+            ..DEFAULT_PBR
+        },
+    },
+    &VoxelDefinition {
+        voxel: Voxel::Smoke(GasState::new(160)),
+        name: Cow::Borrowed("smoke"),
+        simulation_kind: SimKind::Gas,
+        simulated: true,
+        collidable: false,
+        rendered: true,
+        transparent: true,
+        pickable: false,
+        breakable: false,
+        initial_health: 0,
+        density: -5,
+        strength: 0,
+        shadow_caster: false,
+        shadow_receiver: false,
+
+        interactions: DEFAULT_INTERACTIONS,
+        material: VoxelPbr {
+            base_color: Color::srgba(0.2, 0.2, 0.2, 0.6),
+            alpha_blend: true,
+            ..DEFAULT_PBR
+        },
+    },
+    &VoxelDefinition {
+        voxel: Voxel::Methane(GasState::PERMANENT),
+        name: Cow::Borrowed("methane"),
+        simulation_kind: SimKind::Gas,
+        simulated: true,
+        collidable: false,
+        rendered: true,
+        transparent: true,
+        pickable: false,
+        breakable: false,
+        initial_health: 1,
+        density: -20,
+        strength: 0,
+        shadow_caster: false,
+        shadow_receiver: false,
+
+        interactions: Interactions { burnt: Some(Voxel::Air), ..DEFAULT_INTERACTIONS },
+        material: VoxelPbr {
+            base_color: Color::srgba(0.6, 0.8, 0.4, 0.1),
+            alpha_blend: true,
+            ..DEFAULT_PBR
+        },
+    },
+    &VoxelDefinition {
+        voxel: Voxel::Lava(DEFAULT_LIQUID_STATE),
+        name: Cow::Borrowed("lava"),
+        simulation_kind: SimKind::Liquid,
+        simulated: true,
+        collidable: false,
+        rendered: true,
+        transparent: false,
+        pickable: false,
+        breakable: false,
+        initial_health: 10,
+        density: 60,
+        strength: 0,
+        shadow_caster: false,
+        shadow_receiver: false,
+
+        interactions: Interactions {
+            temperature: Some(1000),
+            cooled: Some(PhaseChange { temperature: 600, becomes: Voxel::Stone }),
+            ..DEFAULT_INTERACTIONS
+        },
+        material: VoxelPbr {
+            base_color: Color::srgb(1.0, 0.35, 0.05),
+            perceptual_roughness: 0.6,
+            texture_layer: Some(10),
+            texture_frames: 2,
+            ..DEFAULT_PBR
+        },
+    },
+    &VoxelDefinition {
+        voxel: Voxel::Ice,
+        name: Cow::Borrowed("ice"),
+        simulation_kind: SimKind::Solid,
+        simulated: false,
+        collidable: true,
+        rendered: true,
+        transparent: true,
+        pickable: true,
+        breakable: true,
+        initial_health: 20,
+        density: 0,
+        strength: 8,
+        shadow_caster: true,
+        shadow_receiver: true,
+
+        interactions: Interactions {
+            temperature: Some(-10),
+            heated: Some(PhaseChange {
+                temperature: 5,
+                becomes: Voxel::Water(DEFAULT_LIQUID_STATE),
+            }),
+            ..DEFAULT_INTERACTIONS
+        },
+        material: VoxelPbr {
+            base_color: Color::srgba(0.7, 0.85, 1.0, 0.7),
+            perceptual_roughness: 0.1,
+            reflectance: 0.5,
+            alpha_blend: true,
+            texture_layer: Some(7),
+            ..DEFAULT_PBR
+        },
+    },
+    &VoxelDefinition {
+        voxel: Voxel::Glass,
+        name: Cow::Borrowed("glass"),
+        simulation_kind: SimKind::Solid,
+        simulated: false,
+        collidable: true,
+        rendered: true,
+        transparent: true,
+        pickable: true,
+        breakable: true,
+        initial_health: 5,
+        density: 0,
+        strength: 2,
+        shadow_caster: false,
+        shadow_receiver: true,
+
         interactions: DEFAULT_INTERACTIONS,
+        material: VoxelPbr {
+            base_color: Color::srgba(0.8, 0.9, 0.95, 0.3),
+            perceptual_roughness: 0.1,
+            reflectance: 0.6,
+            alpha_blend: true,
+            ..DEFAULT_PBR
+        },
     },
 ];
 
+const _: () = assert!(VOXEL_DEFINITIONS.len() <= CUSTOM_VOXEL_ID_START);
+
 impl Voxel {
+    /// Iterate every voxel type currently registered.
     #[inline]
     pub fn iter() -> impl Iterator<Item = Voxel> {
-        VOXEL_DEFINITIONS.iter().map(|def| def.voxel)
+        registry::iter_definitions().map(|def| def.voxel)
     }
 
+    /// How many builtin voxel types there are, [`Voxel::Custom`] ids start
+    /// here.
     #[inline]
-    pub const fn type_count() -> usize {
+    pub const fn builtin_count() -> usize {
         VOXEL_DEFINITIONS.len()
     }
 
+    #[inline]
+    pub fn type_count() -> usize {
+        registry::iter_definitions().count()
+    }
+
     #[inline]
     pub fn data(self) -> u16 {
         pack_voxel(self)
@@ -334,6 +716,13 @@ impl Voxel {
             Voxel::Water { .. } => 7,
             Voxel::Oil { .. } => 8,
             Voxel::Fire { .. } => 9,
+            Voxel::Steam(..) => 10,
+            Voxel::Smoke(..) => 11,
+            Voxel::Methane(..) => 12,
+            Voxel::Lava(..) => 13,
+            Voxel::Ice => 14,
+            Voxel::Glass => 15,
+            Voxel::Custom(id) => id as u16,
         }
     }
 
@@ -349,37 +738,33 @@ impl Voxel {
 
     #[inline]
     pub fn from_id(id: u16) -> Option<Self> {
-        VOXEL_DEFINITIONS.get(id as usize).map(|def| def.voxel)
+        if id as usize >= VOXEL_ID_COUNT {
+            return None;
+        }
+
+        registry::definition(id as VoxelId).map(|def| def.voxel)
     }
 
     #[inline]
     pub fn from_name(name: &str) -> Option<Self> {
-        match name.to_ascii_lowercase().trim() {
-            "air" => Some(Voxel::Air),
-
-            "base" => Some(Voxel::Base),
-            "barrier" => Some(Voxel::Barrier),
-
-            "sand" => Some(Voxel::Sand),
-
-            "dirt" => Some(Voxel::Dirt),
-            "grass" => Some(Voxel::Grass),
-            "stone" => Some(Voxel::Stone),
-
-            "water" => Some(Voxel::Water(default())),
-            "oil" => Some(Voxel::Oil(default())),
-            _ => None,
-        }
+        let name = name.to_ascii_lowercase();
+        let name = name.trim();
+        registry::iter_definitions().find(|def| def.name == name).map(|def| def.voxel)
     }
 
     #[inline]
     pub fn as_name(&self) -> &'static str {
-        self.definition().name
+        &self.definition().name
     }
 
+    /// Definition of this voxel type from the published
+    /// [`VoxelRegistry`](registry::VoxelRegistry).
+    ///
+    /// Unregistered [`Voxel::Custom`] ids fall back to
+    /// [`MISSING_DEFINITION`].
     #[inline]
     pub fn definition(self) -> &'static VoxelDefinition {
-        VOXEL_DEFINITIONS[self.id() as usize]
+        registry::definition(self.id() as VoxelId).unwrap_or(&MISSING_DEFINITION)
     }
 
     #[inline]
@@ -398,6 +783,15 @@ impl Voxel {
         self.definition().transparent
     }
 
+    /// Solid and not see-through, hides anything behind it.
+    #[inline]
+    pub fn opaque(self) -> bool {
+        let definition = self.definition();
+        definition.simulation_kind == SimKind::Solid
+            && definition.rendered
+            && !definition.transparent
+    }
+
     #[inline]
     pub fn pickable(self) -> bool {
         self.definition().pickable
@@ -428,18 +822,32 @@ impl Voxel {
         self.density() > other.density()
     }
 
+    /// Can this voxel swap places with `other` when `other` is below it.
+    ///
+    /// Liquids and semi-solids fall into any gas, otherwise only voxels denser
+    /// than the liquid or gas under them sink.
+    #[inline]
+    pub fn sinks_through(self, other: Self) -> bool {
+        match other.definition().simulation_kind {
+            SimKind::Gas => !self.is_gas() || self.denser(other),
+            SimKind::Liquid => self.denser(other),
+            _ => false,
+        }
+    }
+
     #[inline]
     pub fn is_gas(self) -> bool {
         self.definition().simulation_kind == SimKind::Gas
     }
 
+    #[inline]
+    pub fn is_solid(self) -> bool {
+        self.definition().simulation_kind == SimKind::Solid
+    }
+
     #[inline]
     pub fn is_simulated(self) -> bool {
-        use Voxel::*;
-        match self {
-            Sand | Dirt | Water { .. } | Oil { .. } => true,
-            _ => false,
-        }
+        self.definition().simulated
     }
 
     #[inline]
@@ -454,103 +862,42 @@ impl Voxel {
 
     #[inline]
     pub fn material(self) -> StandardMaterial {
-        let default_material = StandardMaterial {
-            perceptual_roughness: 1.0,
-            reflectance: 0.1,
-            base_color: Color::WHITE,
-            ..default()
-        };
-        match self {
-            Voxel::Dirt => StandardMaterial {
-                perceptual_roughness: 1.0,
-                base_color: Color::srgb(79.0 / 225.0, 55.0 / 255.0, 39.0 / 255.0),
-                ..default_material
-            },
-            Voxel::Grass => StandardMaterial {
-                perceptual_roughness: 1.0,
-                // base_color: Color::srgb(124.0 / 225.0, 212.0 / 255.0, 0.0 / 255.0),
-                // base_color: Color::srgb(6.0 / 225.0, 64.0 / 255.0, 43.0 / 255.0),
-                base_color: Color::srgb(126.0 / 225.0, 200.0 / 255.0, 80.0 / 255.0),
-                ..default_material
-            },
-            Voxel::Base => StandardMaterial {
-                perceptual_roughness: 1.0,
-                // base_color: Color::BLACK,
-                base_color: Color::srgb(0.6, 0.6, 0.6),
-                ..default_material
-            },
-            Voxel::Water { .. } => StandardMaterial {
-                perceptual_roughness: 0.5,
-                base_color: Color::srgba(10.0 / 225.0, 10.0 / 255.0, 150.0 / 255.0, 0.2),
-                reflectance: 0.5,
-                alpha_mode: AlphaMode::Blend,
-                ..default_material
-            },
-            Voxel::Oil { .. } => StandardMaterial {
-                perceptual_roughness: 0.5,
-                reflectance: 0.9,
-                base_color: Color::srgba(79.0 / 225.0, 55.0 / 255.0, 39.0 / 255.0, 0.2),
-                alpha_mode: AlphaMode::Blend,
-                ..default_material
-            },
-            Voxel::Sand => StandardMaterial {
-                perceptual_roughness: 1.0,
-                base_color: Color::srgba(0.396, 0.314, 0.113, 1.0),
-                ..default_material
-            },
-            _ => default_material,
-        }
-    }
-}
-
-#[derive(Resource, Clone, Reflect)]
-#[reflect(Resource)]
-pub struct VoxelMaterials {
-    pub base: Handle<StandardMaterial>,
-    pub sand: Handle<StandardMaterial>,
-    pub dirt: Handle<StandardMaterial>,
-    pub grass: Handle<StandardMaterial>,
-    pub water: Handle<StandardMaterial>,
-    pub oil: Handle<StandardMaterial>,
-}
-
-impl VoxelMaterials {
-    pub fn new(materials: &mut Assets<StandardMaterial>) -> Self {
-        Self {
-            base: materials.add(Voxel::Base.material()),
-            dirt: materials.add(Voxel::Dirt.material()),
-            sand: materials.add(Voxel::Sand.material()),
-            grass: materials.add(Voxel::Grass.material()),
-            water: materials.add(Voxel::Water(default()).material()),
-            oil: materials.add(Voxel::Oil(default()).material()),
-        }
-    }
-
-    pub fn get(&self, voxel: Voxel) -> Handle<StandardMaterial> {
-        match voxel {
-            Voxel::Base => self.base.clone(),
-            Voxel::Sand => self.sand.clone(),
-            Voxel::Dirt => self.dirt.clone(),
-            Voxel::Grass => self.grass.clone(),
-            Voxel::Water { .. } => self.water.clone(),
-            Voxel::Oil { .. } => self.oil.clone(),
-            _ => self.base.clone(),
-        }
-    }
-
-    pub fn setup(mut commands: Commands, materials: Option<ResMut<Assets<StandardMaterial>>>) {
-        if let Some(mut materials) = materials {
-            commands.insert_resource(VoxelMaterials::new(&mut *materials));
-        }
+        self.definition().material.standard_material()
     }
 }
 
+/// Definition used for [`Voxel::Custom`] ids that nothing has registered, e.g.
+/// loading a world saved with a voxel type that has since been removed.
+pub const MISSING_DEFINITION: VoxelDefinition = VoxelDefinition {
+    voxel: Voxel::Custom(u8::MAX),
+    name: Cow::Borrowed("missing"),
+    simulation_kind: SimKind::Solid,
+    simulated: false,
+    collidable: true,
+    rendered: true,
+    transparent: false,
+    pickable: true,
+    breakable: true,
+    initial_health: 10,
+    density: 0,
+    strength: 4,
+    shadow_caster: true,
+    shadow_receiver: true,
+
+    interactions: DEFAULT_INTERACTIONS,
+    material: VoxelPbr { base_color: Color::srgb(1.0, 0.0, 1.0), ..DEFAULT_PBR },
+};
+
+/// Bitset over every [`VoxelId`].
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Reflect, Serialize, Deserialize)]
-pub struct VoxelSet(u16);
+pub struct VoxelSet([u64; VoxelSet::WORDS]);
 
 impl VoxelSet {
     pub const AIR: VoxelSet = VoxelSet::from_voxel(Voxel::Air);
     pub const BREAKABLE: VoxelSet = VoxelSet::from_list([Voxel::Barrier, Voxel::Base]).inverted();
+    /// Highest voxel id + 1 that fits in the set.
+    pub const CAPACITY: usize = VOXEL_ID_COUNT;
+    const WORDS: usize = Self::CAPACITY / u64::BITS as usize;
 }
 
 impl Default for VoxelSet {
@@ -563,12 +910,17 @@ impl Iterator for VoxelSet {
     type Item = Voxel;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.0 != 0 {
-            // `bitset & -bitset` returns a bitset with only the lowest significant bit set
-            let t = self.0 & self.0.wrapping_neg();
-            let trailing = self.0.trailing_zeros() as usize;
-            self.0 ^= t;
-            return Voxel::from_id(trailing as u16);
+        for (word_index, word) in self.0.iter_mut().enumerate() {
+            while *word != 0 {
+                let trailing = word.trailing_zeros() as usize;
+                // clear the lowest set bit
+                *word &= *word - 1;
+                // skip ids nothing is registered for (e.g. from inverted sets)
+                let id = word_index * u64::BITS as usize + trailing;
+                if let Some(voxel) = Voxel::from_id(id as u16) {
+                    return Some(voxel);
+                }
+            }
         }
 
         None
@@ -577,23 +929,34 @@ impl Iterator for VoxelSet {
 
 impl VoxelSet {
     pub const fn new() -> Self {
-        Self(0)
+        Self([0; Self::WORDS])
     }
 
     pub const fn clear(&mut self) {
-        self.0 = 0;
+        self.0 = [0; Self::WORDS];
     }
 
-    pub const fn voxel_bit(voxel: Voxel) -> u16 {
-        1 << voxel.id()
+    /// Word index and bit within that word for this voxel.
+    #[inline]
+    pub const fn voxel_bit(voxel: Voxel) -> (usize, u64) {
+        let id = voxel.id() as usize;
+        (id / u64::BITS as usize, 1 << (id % u64::BITS as usize))
     }
 
     pub const fn set(&mut self, voxel: Voxel) {
-        self.0 |= Self::voxel_bit(voxel);
+        let (word, bit) = Self::voxel_bit(voxel);
+        self.0[word] |= bit;
+    }
+
+    pub const fn remove(&mut self, voxel: Voxel) {
+        let (word, bit) = Self::voxel_bit(voxel);
+        self.0[word] &= !bit;
     }
 
     pub const fn from_voxel(voxel: Voxel) -> Self {
-        Self(Self::voxel_bit(voxel))
+        let mut set = Self::new();
+        set.set(voxel);
+        set
     }
 
     pub const fn from_list<const N: usize>(voxels: [Voxel; N]) -> Self {
@@ -607,11 +970,29 @@ impl VoxelSet {
     }
 
     pub const fn contains(&self, voxel: Voxel) -> bool {
-        (self.0 & Self::voxel_bit(voxel)) != 0
+        let (word, bit) = Self::voxel_bit(voxel);
+        (self.0[word] & bit) != 0
     }
 
     pub const fn inverted(self) -> Self {
-        Self(!self.0)
+        let mut words = self.0;
+        let mut i = 0;
+        while i < Self::WORDS {
+            words[i] = !words[i];
+            i += 1;
+        }
+        Self(words)
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        let mut i = 0;
+        while i < Self::WORDS {
+            if self.0[i] != 0 {
+                return false;
+            }
+            i += 1;
+        }
+        true
     }
 }
 
@@ -676,4 +1057,44 @@ mod test {
             assert_eq!(voxel, unpacked);
         }
     }
+
+    #[test]
+    fn voxel_set() {
+        let mut set = VoxelSet::from_list([Voxel::Air, Voxel::Sand, Voxel::Custom(200)]);
+        assert!(set.contains(Voxel::Air));
+        assert!(set.contains(Voxel::Sand));
+        assert!(set.contains(Voxel::Custom(200)));
+        assert!(!set.contains(Voxel::Custom(199)));
+        assert!(!set.contains(Voxel::Stone));
+
+        set.remove(Voxel::Custom(200));
+        assert!(!set.contains(Voxel::Custom(200)));
+
+        let inverted = set.inverted();
+        assert!(!inverted.contains(Voxel::Sand));
+        assert!(inverted.contains(Voxel::Custom(u8::MAX)));
+        assert!(VoxelSet::BREAKABLE.contains(Voxel::Custom(64)));
+        assert!(!VoxelSet::BREAKABLE.contains(Voxel::Base));
+        assert!(VoxelSet::new().is_empty());
+        assert!(!VoxelSet::AIR.is_empty());
+    }
+
+    #[test]
+    fn voxel_set_iter() {
+        let set = VoxelSet::from_list([Voxel::Stone, Voxel::Air, Voxel::Fire { voxel_id: 0 }]);
+        assert_eq!(set.collect::<Vec<_>>(), vec![Voxel::Air, Voxel::Stone, Voxel::Fire {
+            voxel_id: 0
+        }]);
+
+        // unregistered ids are skipped
+        assert_eq!(VoxelSet::from_voxel(Voxel::Custom(130)).count(), 0);
+        assert_eq!(VoxelSet::new().inverted().count(), Voxel::type_count());
+    }
+
+    #[test]
+    fn voxel_set_serde() {
+        let set = VoxelSet::from_list([Voxel::Water(default()), Voxel::Custom(250)]);
+        let ron = ron::to_string(&set).unwrap();
+        assert_eq!(ron::from_str::<VoxelSet>(&ron).unwrap(), set);
+    }
 }
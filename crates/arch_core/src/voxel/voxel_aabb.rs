@@ -62,6 +62,11 @@ impl VoxelAabb {
         Some(VoxelAabb { min: self.min.max(other.min), max: self.max.min(other.max) })
     }
 
+    /// Is the voxel point inside this AABB?
+    pub fn contains(&self, point: IVec3) -> bool {
+        point.cmpge(self.min).all() && point.cmple(self.max).all()
+    }
+
     /// Does this AABB fit inside another?
     pub fn fits_inside(&self, container: &VoxelAabb) -> bool {
         self.min.cmpge(container.min).all() && self.max.cmple(container.max).all()
@@ -10,16 +10,18 @@ use tracing::*;
 use super::raycast::Hit;
 use crate::sdf::Sdf;
 use crate::sdf::voxel_rasterize::PointIter;
+use crate::voxel::diff::{self, ChunkDiff};
 use crate::voxel::mesh::binary_greedy::Chunks;
 // use crate::voxel::mesh::surface_net::Remeshed;
 use crate::voxel::mesh::{BinaryGreedy, ChangedChunk, SurfaceNet};
 use crate::voxel::raycast::VoxelHit;
 use crate::voxel::simulation::data::{ChunkPoint, SimChunks};
 use crate::voxel::tree::VoxelTree;
-use crate::voxel::{GRID_SCALE, UpdateVoxelMeshSet, Voxel, VoxelAabb};
+use crate::voxel::{GRID_SCALE, UpdateVoxelMeshSet, Voxel, VoxelAabb, smooth};
 
 pub fn plugin(app: &mut App) {
     // app.add_plugins(super::voxel::plugin);
+    app.register_type::<WorldGrid>();
     app.add_systems(PreUpdate, changed_chunks_writer);
 }
 
@@ -34,6 +36,15 @@ pub fn changed_chunks_writer(
     }
 }
 
+/// Grid the map is generated in.
+///
+/// Any entity with [`Voxels`] is a grid with its own transform (props, fossil
+/// blocks, vehicles, ...), this marks the one the terrain and borders belong
+/// to.
+#[derive(Component, Debug, Default, Copy, Clone, Reflect)]
+#[reflect(Component)]
+pub struct WorldGrid;
+
 #[derive(Debug, Component, Clone)]
 #[require(
     Name::new("Voxels"),
@@ -48,19 +59,25 @@ pub struct Voxels {
     pub tree: VoxelTree,
 
     // Shared data
+    /// Size of the map this grid was created for, starting at the origin.
+    ///
+    /// The tree grows past this as voxels are set outside of it.
     pub voxel_size: IVec3,
 }
 
 impl Voxels {
     pub fn new(voxel_size: IVec3) -> Self {
         let mut tree = VoxelTree::new();
-        tree.grow_n_layers(4);
-        let max_width = tree.root.voxel_width();
-        let voxel_size = IVec3::splat(max_width as i32);
-        info!("voxel tree max width: {:?}", max_width);
+        tree.grow_to_contain((voxel_size - IVec3::ONE).max(IVec3::ZERO));
+        info!("voxel tree max width: {:?}", tree.root.voxel_width());
         Self { tree, voxel_size }
     }
 
+    /// Cheap copy of the grid for other threads, see [`VoxelTree::snapshot`].
+    pub fn snapshot(&self) -> Self {
+        Self { tree: self.tree.snapshot(), voxel_size: self.voxel_size }
+    }
+
     #[inline]
     pub fn get_voxel(&self, point: IVec3) -> Voxel {
         self.tree.get_voxel(point) // sim is source of truth
@@ -71,15 +88,14 @@ impl Voxels {
         self.tree.set_voxel(point, voxel);
     }
 
-    // #[inline]
-    // pub fn set_voxel_aabb(&mut self, aabb: VoxelAabb, voxel: Voxel) {
-    //     self.tree.set_voxel_aabb(aabb, voxel);
-    // }
+    #[inline]
+    pub fn fill_aabb(&mut self, aabb: VoxelAabb, voxel: Voxel) {
+        self.tree.fill_aabb(aabb, voxel);
+    }
 
+    /// Current bounds of the tree, this changes as the tree grows.
     pub fn voxel_bounds(&self) -> (IVec3, IVec3) {
-        // let (min, max) = self.chunk_bounds();
-        // (min * unpadded::SIZE as Scalar, max * unpadded::SIZE as Scalar)
-        (IVec3::ZERO, self.voxel_size)
+        self.tree.voxel_bounds()
     }
 
     pub fn voxel_aabb(&self) -> VoxelAabb {
@@ -134,7 +150,7 @@ impl Voxels {
             }
         };
 
-        let volume = VoxelAabb { min: IVec3::ZERO, max: self.voxel_size };
+        let volume = self.voxel_aabb();
         volume.traverse_ray(local_ray, length).into_iter().map(move |hit| {
             // translate hit back to world space
             let local_distance = hit.distance;
@@ -167,70 +183,11 @@ impl Voxels {
         None
     }
 
-    /// Take a 3x3 matrix around the voxel and smooth it on the y-axis.
+    /// Take a 3x3 matrix around the voxel and smooth it on the y-axis, see
+    /// [`smooth::smooth_column`].
     pub fn smooth_voxel(&mut self, pos: IVec3, height_range: RangeInclusive<i32>) {
-        #[rustfmt::skip]
-        let surrounding = [
-            IVec3::new(-1, 0, 1), IVec3::new(0, 0, 1), IVec3::new(1, 0, 1),
-            IVec3::new(-1, 0, 0), IVec3::new(0, 0, 0), IVec3::new(1, 0, 0),
-            IVec3::new(-1, 0, -1), IVec3::new(0, 0, -1), IVec3::new(1, 0, -1),
-        ];
-
-        #[rustfmt::skip]
-        let convolution = [
-            1, 2, 1,
-            2, 3, 2,
-            1, 2, 1,
-        ];
-
-        let mut heights = [0; 9];
-        for (index, relative) in surrounding.iter().enumerate() {
-            // find the height by searching up and down until we find [`Voxel::Air`].
-            let voxel = pos + relative;
-
-            match self.get_voxel(voxel) {
-                Voxel::Air => {
-                    while heights[index] > *height_range.start() {
-                        match self.get_voxel(voxel + IVec3::Y * heights[index]) {
-                            Voxel::Air => {},
-                            _ => break,
-                        }
-
-                        heights[index] -= 1;
-                    }
-                },
-                _ => {
-                    while heights[index] < *height_range.end() {
-                        match self.get_voxel(voxel + IVec3::Y * heights[index]) {
-                            Voxel::Air => break,
-                            _ => {},
-                        }
-
-                        heights[index] += 1;
-                    }
-                },
-            };
-        }
-
-        let mut sum = 0;
-        for index in 0..9 {
-            sum += heights[index] * convolution[index];
-        }
-        let new_height = sum / 9;
-
-        // create a gradient over the range to the current height or the new height if
-        // lower.
-        let mut gradient = Vec::new();
-        for height in *height_range.start()..new_height.max(0) {
-            let voxel = self.get_voxel(pos + IVec3::Y * height);
-            gradient.push(voxel);
-        }
-
-        if new_height < pos.y {
-            // squish the voxels down
-        } else {
-            // stretch the voxels out
-        }
+        let source = self.tree.snapshot();
+        smooth::smooth_column(&source, &mut self.tree, pos, height_range);
     }
 
     pub fn point_iter<'a, 'b>(&'b self) -> impl Iterator<Item = IVec3> + 'a {
@@ -240,21 +197,9 @@ impl Voxels {
         })
     }
 
-    pub fn diff(&self, other: &Voxels, cutoff: usize) -> Vec<VoxelDiff> {
-        let mut diffs = Vec::new();
-
-        for point in self.point_iter() {
-            let v1 = self.get_voxel(point);
-            let v2 = other.get_voxel(point);
-            if v1 != v2 {
-                diffs.push(VoxelDiff { point, v1, v2 });
-                if diffs.len() > cutoff {
-                    break;
-                }
-            }
-        }
-
-        return diffs;
+    /// Chunks that changed from `self` to `other`, see [`diff::diff`].
+    pub fn diff(&self, other: &Voxels) -> Vec<ChunkDiff> {
+        diff::diff(&self.tree, &other.tree)
     }
 
     pub fn set_voxel_brush<S: Sdf>(&mut self, center: IVec3, brush: S, voxel: Voxel) {
@@ -265,10 +210,3 @@ impl Voxels {
         }
     }
 }
-
-#[derive(Debug, Copy, Clone)]
-pub struct VoxelDiff {
-    pub point: IVec3,
-    pub v1: Voxel,
-    pub v2: Voxel,
-}